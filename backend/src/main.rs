@@ -6,26 +6,209 @@ use rocket_cors::{AllowedOrigins, CorsOptions};
 #[macro_use]
 extern crate rocket;
 
-use crate::simulation::circuit_validator::QuantumCircuitError;
+use crate::simulation::circuit_validator::{CircuitProblem, QuantumCircuitError};
+use crate::simulation::entanglement::EntanglementAnalysis;
+use crate::simulation::equivalence::Comparison;
+use crate::simulation::gate_registry::{CustomGate, GateDefinition, GateRegistry};
+use crate::simulation::lint::{lint_grid, LintWarning};
+use crate::simulation::metrics::{grid_metrics, Metrics};
+use crate::simulation::optimizer::{optimize_grid, Optimization};
+use crate::simulation::qasm::QasmVersion;
+use crate::simulation::quantum_gate::QuantumGate;
+use crate::simulation::routing::{route_grid, CouplingMap, Routing};
+use crate::simulation::scheduler::{schedule_grid, Schedule, Scheduling};
+use crate::simulation::simulator::{SimulationMode, SimulationOptions};
+use crate::simulation::transpiler::{transpile_grid, Basis, Transpilation};
+use crate::simulation::utils::{format_matrix, matrix_to_little_endian};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use rocket::serde::json::Json;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::RwLock;
 
+use rocket::fairing::AdHoc;
 use rocket::http::Status;
 use rocket::response::{self, Responder, Response};
-use rocket::Request;
+use rocket::{Request, State};
+
+// Server settings, read from Rocket.toml or ROCKET_ prefixed environment variables
+// e.g. ROCKET_MAX_QUBITS=16
+#[derive(Deserialize)]
+struct SimulatorConfig {
+    #[serde(default = "default_max_qubits")]
+    max_qubits: usize,
+    #[serde(default = "default_max_density_matrix_qubits")]
+    max_density_matrix_qubits: usize,
+    #[serde(default = "default_max_shots")]
+    max_shots: usize,
+    #[serde(default = "default_max_trajectory_amplitudes")]
+    max_trajectory_amplitudes: usize,
+    #[serde(default = "default_max_unitary_qubits")]
+    max_unitary_qubits: usize,
+    #[serde(default = "default_max_analysis_qubits")]
+    max_analysis_qubits: usize,
+    #[serde(default = "default_max_stabilizer_qubits")]
+    max_stabilizer_qubits: usize,
+    #[serde(default = "default_max_mps_qubits")]
+    max_mps_qubits: usize,
+    #[serde(default = "default_max_bond_dimension")]
+    max_bond_dimension: usize,
+    #[serde(default = "default_max_step_state_qubits")]
+    max_step_state_qubits: usize,
+    #[serde(default = "default_max_custom_gates")]
+    max_custom_gates: usize,
+    // File the custom gates are kept in between restarts, only kept in memory if not set
+    #[serde(default)]
+    gates_file: Option<String>,
+}
+
+fn default_max_qubits() -> usize {
+    20
+}
+
+fn default_max_density_matrix_qubits() -> usize {
+    10
+}
+
+fn default_max_shots() -> usize {
+    100_000
+}
+
+fn default_max_trajectory_amplitudes() -> usize {
+    1 << 28
+}
+
+fn default_max_unitary_qubits() -> usize {
+    8
+}
+
+fn default_max_analysis_qubits() -> usize {
+    12
+}
+
+fn default_max_stabilizer_qubits() -> usize {
+    1000
+}
+
+fn default_max_mps_qubits() -> usize {
+    64
+}
+
+fn default_max_bond_dimension() -> usize {
+    256
+}
+
+fn default_max_step_state_qubits() -> usize {
+    12
+}
+
+fn default_max_custom_gates() -> usize {
+    100
+}
+
+impl SimulatorConfig {
+    // The largest number of qubits for a simulation with the given options
+    // The entanglement analysis looks at every pair of qubits, so it has its own limit
+    fn max_qubits_for(&self, options: &SimulationOptions) -> usize {
+        let max_qubits = match options.simulation_mode {
+            SimulationMode::StateVector => self.max_qubits,
+            SimulationMode::DensityMatrix => self.max_density_matrix_qubits,
+            SimulationMode::Stabilizer => self.max_stabilizer_qubits,
+            SimulationMode::Mps => self.max_mps_qubits,
+        };
+
+        match options.analysis {
+            true => max_qubits.min(self.max_analysis_qubits),
+            false => max_qubits,
+        }
+    }
+
+    // The largest grid any simulation mode accepts, for requests that only look at the grid
+    fn max_grid_qubits(&self) -> usize {
+        self.max_qubits
+            .max(self.max_stabilizer_qubits)
+            .max(self.max_mps_qubits)
+    }
+
+    // The bond dimension of a matrix product state is chosen by the request, but limited by the server
+    fn check_options(&self, options: &SimulationOptions) -> Result<(), ApiError> {
+        match options.mps.max_bond_dimension > self.max_bond_dimension {
+            true => Err(ApiError::from(QuantumCircuitError::InvalidMpsOptions)),
+            false => Ok(()),
+        }
+    }
+}
+
+// The custom gates of the server, shared by all requests and clients
+struct GateStore {
+    registry: RwLock<GateRegistry>,
+}
 
 #[derive(Serialize, Deserialize)]
 struct IncomingData {
     circuit_matrix: Vec<Vec<String>>,
+    #[serde(flatten)]
+    options: SimulationOptions,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
 struct Step {
     step: usize,
+    // Left out in density matrix and stabilizer mode
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     state: Vec<ComplexContainer>,
+    // One entry per qubit, null until the qubit has been measured, left out if nothing is measured
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    classical_bits: Vec<Option<u8>>,
+    // The classically controlled gates in this step and whether they were applied
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    conditions: Vec<ClassicalCondition>,
+    // Only in density matrix mode
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    density_matrix: Vec<Vec<ComplexContainer>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    purity: Option<f64>,
+    // Only in mps mode, the total weight dropped by truncation so far and the largest bond dimension
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    truncation_error: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bond_dimension: Option<usize>,
+    // Only in stabilizer mode, the stabilizer generators of the state as signed Pauli strings, e.g. "+XX"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    stabilizers: Vec<String>,
+    // The state of every qubit on its own, in row order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    qubits: Vec<QubitState>,
+    // Only if the analysis was requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    analysis: Option<EntanglementAnalysis>,
+}
+
+// The reduced density matrix of a single qubit with its Bloch vector and purity
+#[derive(Serialize, Deserialize)]
+struct QubitState {
+    density_matrix: Vec<Vec<ComplexContainer>>,
+    bloch_vector: BlochVector,
+    purity: f64,
 }
 
 #[derive(Serialize, Deserialize)]
+struct BlochVector {
+    x: f64,
+    y: f64,
+    z: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ClassicalCondition {
+    qubit: usize,
+    classical_bit: usize,
+    applied: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 struct ComplexContainer {
     re: f64,
     im: f64,
@@ -34,11 +217,193 @@ struct ComplexContainer {
 #[derive(Serialize, Deserialize)]
 struct OutgoingData {
     state_list: Vec<Step>,
+    // Things in the circuit that are probably mistakes, they do not stop the simulation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<LintWarning>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LintRequest {
+    circuit_matrix: Vec<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LintResponse {
+    warnings: Vec<LintWarning>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MetricsRequest {
+    circuit_matrix: Vec<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OptimizeRequest {
+    circuit_matrix: Vec<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ScheduleRequest {
+    circuit_matrix: Vec<Vec<String>>,
+    // "asap" (the default) or "alap"
+    #[serde(default)]
+    schedule: Schedule,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TranspileRequest {
+    circuit_matrix: Vec<Vec<String>>,
+    // The names of the native gates, e.g. ["CNOT", "RZ", "SX", "X"]
+    basis: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RouteRequest {
+    circuit_matrix: Vec<Vec<String>>,
+    coupling_map: CouplingMap,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SampleRequest {
+    circuit_matrix: Vec<Vec<String>>,
+    shots: usize,
+    // Fixed seed for reproducible results, a random seed is used if left out
+    #[serde(default)]
+    seed: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SampleResponse {
+    shots: usize,
+    // Number of shots per bitstring, qubit 0 is the leftmost bit
+    counts: BTreeMap<String, usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct UnitaryRequest {
+    circuit_matrix: Vec<Vec<String>>,
+    // Also return the unitary of the circuit up to every step
+    #[serde(default)]
+    per_step: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct UnitaryStep {
+    step: usize,
+    unitary: Vec<Vec<ComplexContainer>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct UnitaryResponse {
+    // Little-endian like the states in /simulate
+    unitary: Vec<Vec<ComplexContainer>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    steps: Vec<UnitaryStep>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExpectationRequest {
+    circuit_matrix: Vec<Vec<String>>,
+    // Sums of Pauli strings with one letter per qubit, e.g. "ZZI" or "0.5*XIX + YYZ"
+    observables: Vec<String>,
+    // Also return the expectation values after every step
+    #[serde(default)]
+    per_step: bool,
+    #[serde(default)]
+    seed: Option<u64>,
+    #[serde(flatten)]
+    options: SimulationOptions,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExpectationStep {
+    step: usize,
+    expectation_values: Vec<f64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExpectationResponse {
+    // One value per observable for the final state
+    expectation_values: Vec<f64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    steps: Vec<ExpectationStep>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CompareRequest {
+    circuit_matrix: Vec<Vec<String>>,
+    // The circuit to compare against, e.g. the solution of an exercise
+    target_matrix: Vec<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct QasmExportRequest {
+    circuit_matrix: Vec<Vec<String>>,
+    // "2.0" or "3.0", OpenQASM 2.0 if left out
+    #[serde(default)]
+    version: QasmVersion,
+}
+
+#[derive(Serialize, Deserialize)]
+struct QasmExportResponse {
+    qasm: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct QasmImportRequest {
+    qasm: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct QasmImportResponse {
+    circuit_matrix: Vec<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct GateResponse {
+    name: String,
+    // The number of qubits, so the gate is used as "<name>" or "<name>-1" to "<name>-<size>"
+    size: usize,
+    // Little-endian like the unitaries of /unitary
+    unitary: Vec<Vec<ComplexContainer>>,
+}
+
+impl GateResponse {
+    fn new(gate: &CustomGate) -> GateResponse {
+        GateResponse {
+            name: gate.name.clone(),
+            size: gate.matrix.size,
+            unitary: format_matrix(&matrix_to_little_endian(&gate.matrix.matrix)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct GateListResponse {
+    gates: Vec<GateResponse>,
 }
 
+// A grid with problems reports the kind of its first problem as the error, and every problem with its cell
 #[derive(Debug, Serialize)]
 struct ApiError {
     error: QuantumCircuitError,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    problems: Vec<CircuitProblem>,
+}
+
+impl From<QuantumCircuitError> for ApiError {
+    fn from(error: QuantumCircuitError) -> Self {
+        match error {
+            QuantumCircuitError::InvalidCircuit(problems) => ApiError {
+                error: problems[0].error.clone(),
+                problems,
+            },
+            error => ApiError {
+                error,
+                problems: Vec::new(),
+            },
+        }
+    }
 }
 
 impl<'r> Responder<'r, 'static> for ApiError {
@@ -55,22 +420,362 @@ impl<'r> Responder<'r, 'static> for ApiError {
 #[post("/simulate", format = "json", data = "<incoming_data>")]
 fn simulate_circuit_handler(
     incoming_data: Json<IncomingData>,
+    config: &State<SimulatorConfig>,
+    gates: &State<GateStore>,
 ) -> Result<Json<OutgoingData>, ApiError> {
     let binding = incoming_data.into_inner();
+    let matrix = as_grid(&binding.circuit_matrix);
+    let gates = gates.registry.read().unwrap();
+    let options = SimulationOptions {
+        max_step_state_qubits: Some(config.max_step_state_qubits),
+        ..binding
+            .options
+            .for_circuit(&matrix, config.max_qubits, &gates)
+    };
+    config.check_options(&options)?;
 
-    let matrix = binding
-        .circuit_matrix
-        .iter()
-        .map(|row| row.iter().map(|item| item.as_str()).collect())
-        .collect();
-
-    match simulation::simulator::simulate_circuit(matrix) {
+    let max_qubits = config.max_qubits_for(&options);
+    match simulation::simulator::simulate_circuit(matrix.clone(), max_qubits, &gates, &options) {
         Ok(state_list) => {
-            let outgoing_data = OutgoingData { state_list };
+            // The grid is valid, it was just simulated
+            let warnings = lint_grid(&matrix, max_qubits, &gates).unwrap_or_default();
+            let outgoing_data = OutgoingData {
+                state_list,
+                warnings,
+            };
             Ok(Json(outgoing_data))
         }
-        Err(err) => Err(ApiError { error: err }),
+        Err(err) => Err(ApiError::from(err)),
+    }
+}
+
+#[post("/lint", format = "json", data = "<lint_request>")]
+fn lint_handler(
+    lint_request: Json<LintRequest>,
+    config: &State<SimulatorConfig>,
+    gates: &State<GateStore>,
+) -> Result<Json<LintResponse>, ApiError> {
+    match lint_grid(
+        &as_grid(&lint_request.circuit_matrix),
+        config.max_grid_qubits(),
+        &gates.registry.read().unwrap(),
+    ) {
+        Ok(warnings) => Ok(Json(LintResponse { warnings })),
+        Err(err) => Err(ApiError::from(err)),
+    }
+}
+
+#[post("/metrics", format = "json", data = "<metrics_request>")]
+fn metrics_handler(
+    metrics_request: Json<MetricsRequest>,
+    config: &State<SimulatorConfig>,
+    gates: &State<GateStore>,
+) -> Result<Json<Metrics>, ApiError> {
+    match grid_metrics(
+        &as_grid(&metrics_request.circuit_matrix),
+        config.max_grid_qubits(),
+        &gates.registry.read().unwrap(),
+    ) {
+        Ok(metrics) => Ok(Json(metrics)),
+        Err(err) => Err(ApiError::from(err)),
+    }
+}
+
+#[post("/optimize", format = "json", data = "<optimize_request>")]
+fn optimize_handler(
+    optimize_request: Json<OptimizeRequest>,
+    config: &State<SimulatorConfig>,
+    gates: &State<GateStore>,
+) -> Result<Json<Optimization>, ApiError> {
+    match optimize_grid(
+        &as_grid(&optimize_request.circuit_matrix),
+        config.max_grid_qubits(),
+        config.max_unitary_qubits,
+        &gates.registry.read().unwrap(),
+    ) {
+        Ok(optimization) => Ok(Json(optimization)),
+        Err(err) => Err(ApiError::from(err)),
+    }
+}
+
+#[post("/schedule", format = "json", data = "<schedule_request>")]
+fn schedule_handler(
+    schedule_request: Json<ScheduleRequest>,
+    config: &State<SimulatorConfig>,
+    gates: &State<GateStore>,
+) -> Result<Json<Scheduling>, ApiError> {
+    match schedule_grid(
+        &as_grid(&schedule_request.circuit_matrix),
+        schedule_request.schedule,
+        config.max_grid_qubits(),
+        &gates.registry.read().unwrap(),
+    ) {
+        Ok(scheduling) => Ok(Json(scheduling)),
+        Err(err) => Err(ApiError::from(err)),
+    }
+}
+
+#[post("/transpile", format = "json", data = "<transpile_request>")]
+fn transpile_handler(
+    transpile_request: Json<TranspileRequest>,
+    config: &State<SimulatorConfig>,
+    gates: &State<GateStore>,
+) -> Result<Json<Transpilation>, ApiError> {
+    let basis = Basis::new(&transpile_request.basis).map_err(ApiError::from)?;
+    match transpile_grid(
+        &as_grid(&transpile_request.circuit_matrix),
+        &basis,
+        config.max_grid_qubits(),
+        config.max_unitary_qubits,
+        &gates.registry.read().unwrap(),
+    ) {
+        Ok(transpilation) => Ok(Json(transpilation)),
+        Err(err) => Err(ApiError::from(err)),
+    }
+}
+
+#[post("/route", format = "json", data = "<route_request>")]
+fn route_handler(
+    route_request: Json<RouteRequest>,
+    config: &State<SimulatorConfig>,
+    gates: &State<GateStore>,
+) -> Result<Json<Routing>, ApiError> {
+    match route_grid(
+        &as_grid(&route_request.circuit_matrix),
+        &route_request.coupling_map,
+        config.max_grid_qubits(),
+        config.max_qubits,
+        &gates.registry.read().unwrap(),
+    ) {
+        Ok(routing) => Ok(Json(routing)),
+        Err(err) => Err(ApiError::from(err)),
+    }
+}
+
+#[post("/sample", format = "json", data = "<sample_request>")]
+fn sample_circuit_handler(
+    sample_request: Json<SampleRequest>,
+    config: &State<SimulatorConfig>,
+    gates: &State<GateStore>,
+) -> Result<Json<SampleResponse>, ApiError> {
+    let binding = sample_request.into_inner();
+    let matrix = as_grid(&binding.circuit_matrix);
+
+    let mut rng = match binding.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
+    match simulation::sampler::sample_circuit(
+        matrix,
+        config.max_qubits,
+        &gates.registry.read().unwrap(),
+        binding.shots,
+        config.max_shots,
+        config.max_trajectory_amplitudes,
+        &mut rng,
+    ) {
+        Ok(counts) => Ok(Json(SampleResponse {
+            shots: binding.shots,
+            counts,
+        })),
+        Err(err) => Err(ApiError::from(err)),
+    }
+}
+
+#[post("/unitary", format = "json", data = "<unitary_request>")]
+fn unitary_handler(
+    unitary_request: Json<UnitaryRequest>,
+    config: &State<SimulatorConfig>,
+    gates: &State<GateStore>,
+) -> Result<Json<UnitaryResponse>, ApiError> {
+    let binding = unitary_request.into_inner();
+    let matrix = as_grid(&binding.circuit_matrix);
+
+    let to_json = |gate: &QuantumGate| format_matrix(&matrix_to_little_endian(&gate.matrix));
+
+    let registry = gates.registry.read().unwrap();
+
+    // The unitaries of the steps are only kept if they are returned
+    if !binding.per_step {
+        return match simulation::unitary::circuit_unitary(
+            matrix,
+            config.max_unitary_qubits,
+            &registry,
+        ) {
+            Ok(unitary) => Ok(Json(UnitaryResponse {
+                unitary: to_json(&unitary),
+                steps: Vec::new(),
+            })),
+            Err(err) => Err(ApiError::from(err)),
+        };
+    }
+
+    match simulation::unitary::circuit_unitaries(matrix, config.max_unitary_qubits, &registry) {
+        Ok(unitaries) => Ok(Json(UnitaryResponse {
+            unitary: to_json(unitaries.last().unwrap()),
+            steps: unitaries
+                .iter()
+                .enumerate()
+                .map(|(step, gate)| UnitaryStep {
+                    step,
+                    unitary: to_json(gate),
+                })
+                .collect(),
+        })),
+        Err(err) => Err(ApiError::from(err)),
+    }
+}
+
+#[post("/expectation", format = "json", data = "<expectation_request>")]
+fn expectation_handler(
+    expectation_request: Json<ExpectationRequest>,
+    config: &State<SimulatorConfig>,
+    gates: &State<GateStore>,
+) -> Result<Json<ExpectationResponse>, ApiError> {
+    let binding = expectation_request.into_inner();
+    let matrix = as_grid(&binding.circuit_matrix);
+
+    let mut rng = match binding.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
+    let gates = gates.registry.read().unwrap();
+    let options = binding
+        .options
+        .for_circuit(&matrix, config.max_qubits, &gates);
+    config.check_options(&options)?;
+
+    match simulation::observable::circuit_expectation_values(
+        matrix,
+        config.max_qubits_for(&options),
+        &gates,
+        &binding.observables,
+        &options,
+        binding.per_step,
+        &mut rng,
+    ) {
+        Ok(values) => {
+            let expectation_values = values.last().unwrap().clone();
+            let steps = match binding.per_step {
+                true => values
+                    .into_iter()
+                    .enumerate()
+                    .map(|(step, expectation_values)| ExpectationStep {
+                        step,
+                        expectation_values,
+                    })
+                    .collect(),
+                false => Vec::new(),
+            };
+            Ok(Json(ExpectationResponse {
+                expectation_values,
+                steps,
+            }))
+        }
+        Err(err) => Err(ApiError::from(err)),
+    }
+}
+
+#[post("/compare", format = "json", data = "<compare_request>")]
+fn compare_handler(
+    compare_request: Json<CompareRequest>,
+    config: &State<SimulatorConfig>,
+    gates: &State<GateStore>,
+) -> Result<Json<Comparison>, ApiError> {
+    let binding = compare_request.into_inner();
+
+    match simulation::equivalence::compare_circuits(
+        as_grid(&binding.circuit_matrix),
+        as_grid(&binding.target_matrix),
+        config.max_unitary_qubits,
+        &gates.registry.read().unwrap(),
+    ) {
+        Ok(comparison) => Ok(Json(comparison)),
+        Err(err) => Err(ApiError::from(err)),
+    }
+}
+
+#[post("/export/qasm", format = "json", data = "<export_request>")]
+fn export_qasm_handler(
+    export_request: Json<QasmExportRequest>,
+    config: &State<SimulatorConfig>,
+    gates: &State<GateStore>,
+) -> Result<Json<QasmExportResponse>, ApiError> {
+    let binding = export_request.into_inner();
+    let matrix = as_grid(&binding.circuit_matrix);
+
+    match simulation::qasm::export_qasm(
+        &matrix,
+        config.max_qubits,
+        &gates.registry.read().unwrap(),
+        binding.version,
+    ) {
+        Ok(qasm) => Ok(Json(QasmExportResponse { qasm })),
+        Err(err) => Err(ApiError::from(err)),
+    }
+}
+
+#[post("/import/qasm", format = "json", data = "<import_request>")]
+fn import_qasm_handler(
+    import_request: Json<QasmImportRequest>,
+    config: &State<SimulatorConfig>,
+) -> Result<Json<QasmImportResponse>, ApiError> {
+    match simulation::qasm::import_qasm(&import_request.qasm, config.max_qubits) {
+        Ok(circuit_matrix) => Ok(Json(QasmImportResponse { circuit_matrix })),
+        Err(err) => Err(ApiError::from(err)),
+    }
+}
+
+#[post("/gates", format = "json", data = "<definition>")]
+fn define_gate_handler(
+    definition: Json<GateDefinition>,
+    config: &State<SimulatorConfig>,
+    gates: &State<GateStore>,
+) -> Result<Json<GateResponse>, ApiError> {
+    let mut registry = gates.registry.write().unwrap();
+
+    // The registry is shared by all clients, so it can not grow without bounds
+    if registry.custom_gates().count() >= config.max_custom_gates {
+        return Err(ApiError::from(QuantumCircuitError::InvalidCustomGate(
+            "the server already has the largest number of custom gates".to_string(),
+        )));
+    }
+
+    let response = match registry.define(&definition, config.max_unitary_qubits) {
+        Ok(gate) => GateResponse::new(gate),
+        Err(err) => return Err(ApiError::from(err)),
+    };
+
+    // A gate that could not be written is removed again, so the error means it was not defined
+    if let Some(path) = &config.gates_file {
+        if let Err(err) = registry.save(Path::new(path)) {
+            registry.remove(&definition.name);
+            return Err(ApiError::from(QuantumCircuitError::InvalidCustomGate(
+                format!("the gate could not be stored: {}", err),
+            )));
+        }
     }
+
+    Ok(Json(response))
+}
+
+#[get("/gates")]
+fn list_gates_handler(gates: &State<GateStore>) -> Json<GateListResponse> {
+    let registry = gates.registry.read().unwrap();
+
+    Json(GateListResponse {
+        gates: registry.custom_gates().map(GateResponse::new).collect(),
+    })
+}
+
+// Borrow the rows of an incoming circuit_matrix as the grid used by the simulation
+fn as_grid(circuit_matrix: &[Vec<String>]) -> Vec<Vec<&str>> {
+    circuit_matrix
+        .iter()
+        .map(|row| row.iter().map(|item| item.as_str()).collect())
+        .collect()
 }
 
 #[derive(Serialize, Deserialize)]
@@ -112,7 +817,45 @@ fn rocket() -> _ {
 
     rocket::build()
         .attach(cors.to_cors().unwrap())
-        .mount("/", routes![simulate_circuit_handler, ping_handler])
+        .attach(AdHoc::config::<SimulatorConfig>())
+        .attach(AdHoc::try_on_ignite("Gate registry", |rocket| async {
+            let config = rocket.state::<SimulatorConfig>().unwrap();
+            let registry = match &config.gates_file {
+                Some(path) => GateRegistry::load(Path::new(path), config.max_unitary_qubits),
+                None => Ok(GateRegistry::default()),
+            };
+
+            match registry {
+                Ok(registry) => Ok(rocket.manage(GateStore {
+                    registry: RwLock::new(registry),
+                })),
+                Err(err) => {
+                    eprintln!("The custom gates could not be loaded: {}", err);
+                    Err(rocket)
+                }
+            }
+        }))
+        .mount(
+            "/",
+            routes![
+                simulate_circuit_handler,
+                lint_handler,
+                metrics_handler,
+                optimize_handler,
+                schedule_handler,
+                transpile_handler,
+                route_handler,
+                sample_circuit_handler,
+                unitary_handler,
+                compare_handler,
+                expectation_handler,
+                export_qasm_handler,
+                import_qasm_handler,
+                define_gate_handler,
+                list_gates_handler,
+                ping_handler
+            ],
+        )
 }
 
 #[cfg(test)]
@@ -138,7 +881,7 @@ mod tests {
             )
             .dispatch();
 
-        let expected_response = r#"{"state_list":[{"step":0,"state":[{"re":1.0,"im":0.0},{"re":0.0,"im":0.0},{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}]},{"step":1,"state":[{"re":0.7071067811865475,"im":0.0},{"re":0.7071067811865475,"im":0.0},{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}]},{"step":2,"state":[{"re":0.4999999999999999,"im":0.0},{"re":0.4999999999999999,"im":0.0},{"re":0.4999999999999999,"im":0.0},{"re":0.4999999999999999,"im":0.0}]}]}"#;
+        let expected_response = r#"{"state_list":[{"step":0,"state":[{"re":1.0,"im":0.0},{"re":0.0,"im":0.0},{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}],"qubits":[{"density_matrix":[[{"re":1.0,"im":0.0},{"re":0.0,"im":0.0}],[{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}]],"bloch_vector":{"x":0.0,"y":0.0,"z":1.0},"purity":1.0},{"density_matrix":[[{"re":1.0,"im":0.0},{"re":0.0,"im":0.0}],[{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}]],"bloch_vector":{"x":0.0,"y":0.0,"z":1.0},"purity":1.0}]},{"step":1,"state":[{"re":0.7071067811865475,"im":0.0},{"re":0.7071067811865475,"im":0.0},{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}],"qubits":[{"density_matrix":[[{"re":0.4999999999999999,"im":0.0},{"re":0.4999999999999999,"im":0.0}],[{"re":0.4999999999999999,"im":0.0},{"re":0.4999999999999999,"im":0.0}]],"bloch_vector":{"x":0.9999999999999998,"y":0.0,"z":0.0},"purity":0.9999999999999996},{"density_matrix":[[{"re":0.9999999999999998,"im":0.0},{"re":0.0,"im":0.0}],[{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}]],"bloch_vector":{"x":0.0,"y":0.0,"z":0.9999999999999998},"purity":0.9999999999999996}]},{"step":2,"state":[{"re":0.4999999999999999,"im":0.0},{"re":0.4999999999999999,"im":0.0},{"re":0.4999999999999999,"im":0.0},{"re":0.4999999999999999,"im":0.0}],"qubits":[{"density_matrix":[[{"re":0.4999999999999998,"im":0.0},{"re":0.4999999999999998,"im":0.0}],[{"re":0.4999999999999998,"im":0.0},{"re":0.4999999999999998,"im":0.0}]],"bloch_vector":{"x":0.9999999999999996,"y":0.0,"z":0.0},"purity":0.9999999999999991},{"density_matrix":[[{"re":0.4999999999999998,"im":0.0},{"re":0.4999999999999998,"im":0.0}],[{"re":0.4999999999999998,"im":0.0},{"re":0.4999999999999998,"im":0.0}]],"bloch_vector":{"x":0.9999999999999996,"y":0.0,"z":0.0},"purity":0.9999999999999991}]}]}"#;
 
         assert_eq!(response.status(), Status::Ok);
         assert_eq!(response.into_string(), Some(expected_response.to_string()));
@@ -161,9 +904,831 @@ mod tests {
             )
             .dispatch();
 
-        let expected_response = r#"{"state_list":[{"step":0,"state":[{"re":1.0,"im":0.0},{"re":0.0,"im":0.0},{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}]},{"step":1,"state":[{"re":0.7071067811865475,"im":0.0},{"re":0.7071067811865475,"im":0.0},{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}]},{"step":2,"state":[{"re":0.7071067811865475,"im":0.0},{"re":0.0,"im":0.0},{"re":0.0,"im":0.0},{"re":0.7071067811865475,"im":0.0}]}]}"#;
+        let expected_response = r#"{"state_list":[{"step":0,"state":[{"re":1.0,"im":0.0},{"re":0.0,"im":0.0},{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}],"qubits":[{"density_matrix":[[{"re":1.0,"im":0.0},{"re":0.0,"im":0.0}],[{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}]],"bloch_vector":{"x":0.0,"y":0.0,"z":1.0},"purity":1.0},{"density_matrix":[[{"re":1.0,"im":0.0},{"re":0.0,"im":0.0}],[{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}]],"bloch_vector":{"x":0.0,"y":0.0,"z":1.0},"purity":1.0}]},{"step":1,"state":[{"re":0.7071067811865475,"im":0.0},{"re":0.7071067811865475,"im":0.0},{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}],"qubits":[{"density_matrix":[[{"re":0.4999999999999999,"im":0.0},{"re":0.4999999999999999,"im":0.0}],[{"re":0.4999999999999999,"im":0.0},{"re":0.4999999999999999,"im":0.0}]],"bloch_vector":{"x":0.9999999999999998,"y":0.0,"z":0.0},"purity":0.9999999999999996},{"density_matrix":[[{"re":0.9999999999999998,"im":0.0},{"re":0.0,"im":0.0}],[{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}]],"bloch_vector":{"x":0.0,"y":0.0,"z":0.9999999999999998},"purity":0.9999999999999996}]},{"step":2,"state":[{"re":0.7071067811865475,"im":0.0},{"re":0.0,"im":0.0},{"re":0.0,"im":0.0},{"re":0.7071067811865475,"im":0.0}],"qubits":[{"density_matrix":[[{"re":0.4999999999999999,"im":0.0},{"re":0.0,"im":0.0}],[{"re":0.0,"im":0.0},{"re":0.4999999999999999,"im":0.0}]],"bloch_vector":{"x":0.0,"y":0.0,"z":0.0},"purity":0.4999999999999998},{"density_matrix":[[{"re":0.4999999999999999,"im":0.0},{"re":0.0,"im":0.0}],[{"re":0.0,"im":0.0},{"re":0.4999999999999999,"im":0.0}]],"bloch_vector":{"x":0.0,"y":0.0,"z":0.0},"purity":0.4999999999999998}]}]}"#;
 
         assert_eq!(response.status(), Status::Ok);
         assert_eq!(response.into_string(), Some(expected_response.to_string()));
     }
+
+    #[test]
+    fn test_simulate_circuit_too_many_qubits() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+        // Clifford circuits this large would be simulated with the stabilizer backend
+        let circuit_matrix = vec![vec!["T"]; default_max_qubits() + 1];
+
+        let response = client
+            .post("/simulate")
+            .header(rocket::http::ContentType::JSON)
+            .body(serde_json::json!({ "circuit_matrix": circuit_matrix }).to_string())
+            .dispatch();
+
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(
+            response.into_string(),
+            Some(r#"{"error":"TooManyQubits"}"#.to_string())
+        );
+    }
+
+    #[test]
+    fn test_simulate_large_circuit_only_returns_the_last_state() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+        let no_of_qubits = default_max_step_state_qubits() + 1;
+        let circuit_matrix = vec![vec!["H", "T"]; no_of_qubits];
+
+        let response = client
+            .post("/simulate")
+            .header(rocket::http::ContentType::JSON)
+            .body(serde_json::json!({ "circuit_matrix": circuit_matrix }).to_string())
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        let body: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        let state_list = body["state_list"].as_array().unwrap();
+        assert_eq!(state_list.len(), 3);
+        assert!(state_list[..2]
+            .iter()
+            .all(|step| step.get("state").is_none()));
+        assert_eq!(
+            state_list[2]["state"].as_array().unwrap().len(),
+            1 << no_of_qubits
+        );
+        assert_eq!(
+            state_list[1]["qubits"].as_array().unwrap().len(),
+            no_of_qubits
+        );
+    }
+
+    #[test]
+    fn test_simulate_circuit_reports_all_problems() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/simulate")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"circuit_matrix": [["A", "CNOT-1"], ["H", "I"]]}"#)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(
+            response.into_string(),
+            Some(
+                r#"{"error":"InvalidGate","problems":[{"error":"InvalidGate","row":0,"column":0,"token":"A","message":"\"A\" is not a valid gate"},{"error":"MultiQubitGateMismatch","row":0,"column":1,"token":"CNOT-1","message":"\"CNOT-1\" belongs to a CNOT that does not have each of its 2 parts exactly once in this step"}]}"#
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_lint() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/lint")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"circuit_matrix": [["H", "H", "I"], ["Z", "X", "I"]]}"#)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.into_string(),
+            Some(
+                r#"{"warnings":[{"warning":"NoEffect","cells":[{"row":1,"column":0}],"message":"Z on qubit 1 in column 0 does nothing, qubit 1 is still |0>"},{"warning":"RedundantPair","cells":[{"row":0,"column":0},{"row":0,"column":1}],"message":"H on qubit 0 in columns 0 and 1 cancel each other"},{"warning":"EmptyTrailingColumns","cells":[{"row":0,"column":2},{"row":1,"column":2}],"message":"column 2 at the end is empty"}]}"#
+                    .to_string()
+            )
+        );
+
+        // The same warnings come with a simulation, which still runs
+        let response = client
+            .post("/simulate")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"circuit_matrix": [["H", "H", "I"], ["Z", "X", "I"]]}"#)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        let body: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(body["state_list"].as_array().unwrap().len(), 4);
+        assert_eq!(body["warnings"].as_array().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_metrics() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/metrics")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"circuit_matrix": [["H", "CNOT-1", "T"], ["I", "CNOT-2", "I"]]}"#)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.into_string(),
+            Some(
+                r#"{"width":2,"columns":3,"depth":3,"gates":3,"gate_counts":{"CNOT":1,"H":1,"T":1},"two_qubit_gates":1,"multi_qubit_gates":0,"t_count":1,"t_depth":1,"uncounted_controlled_gates":0,"critical_path":[{"column":0,"gate":"H on qubit 0"},{"column":1,"gate":"CNOT on qubits 0, 1"},{"column":2,"gate":"T on qubit 0"}],"state_vector_bytes":64.0,"state_vector_memory":"64 B"}"#
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_optimize() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        // T T is an S, which moves through the control so the CNOTs cancel, and S H is one U3
+        let response = client
+            .post("/optimize")
+            .header(rocket::http::ContentType::JSON)
+            .body(
+                r#"{"circuit_matrix": [["H", "CNOT-1", "T", "T", "CNOT-1"], ["I", "CNOT-2", "I", "I", "CNOT-2"]]}"#,
+            )
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.into_string(),
+            Some(
+                r#"{"circuit_matrix":[["U3(pi/2, pi/2, -pi)"],["I"]],"gates_before":5,"gates_after":1,"depth_before":5,"depth_after":1,"equivalence":{"equivalence":"equivalent","average_gate_fidelity":1.0,"global_phase":0.0}}"#
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_schedule() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/schedule")
+            .header(rocket::http::ContentType::JSON)
+            .body(
+                r#"{"circuit_matrix": [["H", "I", "CNOT-1"], ["I", "I", "CNOT-2"], ["X", "I", "I"]], "schedule": "alap"}"#,
+            )
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.into_string(),
+            Some(
+                r#"{"circuit_matrix":[["H","CNOT-1"],["I","CNOT-2"],["I","X"]],"columns_before":3,"columns_after":2}"#
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_transpile() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/transpile")
+            .header(rocket::http::ContentType::JSON)
+            .body(
+                r#"{"circuit_matrix": [["SWAP-1"], ["SWAP-2"]], "basis": ["CNOT", "RZ", "SX", "X"]}"#,
+            )
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.into_string(),
+            Some(
+                r#"{"circuit_matrix":[["CNOT-2","CNOT-1","CNOT-2"],["CNOT-1","CNOT-2","CNOT-1"]],"gates":3,"depth":3,"equivalence":{"equivalence":"equivalent","average_gate_fidelity":1.0,"global_phase":0.0}}"#
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_transpile_unsupported_basis() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/transpile")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"circuit_matrix": [["H"]], "basis": ["RZ", "SX"]}"#)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(
+            response.into_string(),
+            Some(r#"{"error":{"UnsupportedBasis":"the basis needs CNOT or CZ"}}"#.to_string())
+        );
+    }
+
+    #[test]
+    fn test_route() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/route")
+            .header(rocket::http::ContentType::JSON)
+            .body(
+                r#"{
+                    "circuit_matrix": [["H", "CNOT-1"], ["I", "I"], ["I", "CNOT-2"]],
+                    "coupling_map": { "kind": "line", "qubits": 3 }
+                }"#,
+            )
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.into_string(),
+            Some(
+                r#"{"circuit_matrix":[["H","SWAP-1","I"],["I","SWAP-2","CNOT-1"],["I","I","CNOT-2"]],"swaps":1,"final_layout":[1,0,2],"same_distribution":true}"#
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_route_invalid_coupling_map() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/route")
+            .header(rocket::http::ContentType::JSON)
+            .body(
+                r#"{"circuit_matrix": [["H"]], "coupling_map": { "kind": "edges", "edges": [[0, 1], [2, 3]] }}"#,
+            )
+            .dispatch();
+
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(
+            response.into_string(),
+            Some(
+                r#"{"error":{"InvalidCouplingMap":"the coupling map is not connected"}}"#
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_simulate_circuit_with_measurement() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/simulate")
+            .header(rocket::http::ContentType::JSON)
+            .body(
+                r#"{
+                    "circuit_matrix": [
+                        ["X", "M"]
+                    ]
+                }"#,
+            )
+            .dispatch();
+
+        let expected_response = r#"{"state_list":[{"step":0,"state":[{"re":1.0,"im":0.0},{"re":0.0,"im":0.0}],"classical_bits":[null],"qubits":[{"density_matrix":[[{"re":1.0,"im":0.0},{"re":0.0,"im":0.0}],[{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}]],"bloch_vector":{"x":0.0,"y":0.0,"z":1.0},"purity":1.0}]},{"step":1,"state":[{"re":0.0,"im":0.0},{"re":1.0,"im":0.0}],"classical_bits":[null],"qubits":[{"density_matrix":[[{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}],[{"re":0.0,"im":0.0},{"re":1.0,"im":0.0}]],"bloch_vector":{"x":0.0,"y":0.0,"z":-1.0},"purity":1.0}]},{"step":2,"state":[{"re":0.0,"im":0.0},{"re":1.0,"im":0.0}],"classical_bits":[1],"qubits":[{"density_matrix":[[{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}],[{"re":0.0,"im":0.0},{"re":1.0,"im":0.0}]],"bloch_vector":{"x":0.0,"y":0.0,"z":-1.0},"purity":1.0}]}]}"#;
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string(), Some(expected_response.to_string()));
+    }
+
+    #[test]
+    fn test_simulate_circuit_with_classically_controlled_gate() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/simulate")
+            .header(rocket::http::ContentType::JSON)
+            .body(
+                r#"{
+                    "circuit_matrix": [
+                        ["X", "M", "I"],
+                        ["I", "I", "X?c0"]
+                    ]
+                }"#,
+            )
+            .dispatch();
+
+        let expected_response = r#"{"state_list":[{"step":0,"state":[{"re":1.0,"im":0.0},{"re":0.0,"im":0.0},{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}],"classical_bits":[null,null],"qubits":[{"density_matrix":[[{"re":1.0,"im":0.0},{"re":0.0,"im":0.0}],[{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}]],"bloch_vector":{"x":0.0,"y":0.0,"z":1.0},"purity":1.0},{"density_matrix":[[{"re":1.0,"im":0.0},{"re":0.0,"im":0.0}],[{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}]],"bloch_vector":{"x":0.0,"y":0.0,"z":1.0},"purity":1.0}]},{"step":1,"state":[{"re":0.0,"im":0.0},{"re":1.0,"im":0.0},{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}],"classical_bits":[null,null],"qubits":[{"density_matrix":[[{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}],[{"re":0.0,"im":0.0},{"re":1.0,"im":0.0}]],"bloch_vector":{"x":0.0,"y":0.0,"z":-1.0},"purity":1.0},{"density_matrix":[[{"re":1.0,"im":0.0},{"re":0.0,"im":0.0}],[{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}]],"bloch_vector":{"x":0.0,"y":0.0,"z":1.0},"purity":1.0}]},{"step":2,"state":[{"re":0.0,"im":0.0},{"re":1.0,"im":0.0},{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}],"classical_bits":[1,null],"qubits":[{"density_matrix":[[{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}],[{"re":0.0,"im":0.0},{"re":1.0,"im":0.0}]],"bloch_vector":{"x":0.0,"y":0.0,"z":-1.0},"purity":1.0},{"density_matrix":[[{"re":1.0,"im":0.0},{"re":0.0,"im":0.0}],[{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}]],"bloch_vector":{"x":0.0,"y":0.0,"z":1.0},"purity":1.0}]},{"step":3,"state":[{"re":0.0,"im":0.0},{"re":0.0,"im":0.0},{"re":0.0,"im":0.0},{"re":1.0,"im":0.0}],"classical_bits":[1,null],"conditions":[{"qubit":1,"classical_bit":0,"applied":true}],"qubits":[{"density_matrix":[[{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}],[{"re":0.0,"im":0.0},{"re":1.0,"im":0.0}]],"bloch_vector":{"x":0.0,"y":0.0,"z":-1.0},"purity":1.0},{"density_matrix":[[{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}],[{"re":0.0,"im":0.0},{"re":1.0,"im":0.0}]],"bloch_vector":{"x":0.0,"y":0.0,"z":-1.0},"purity":1.0}]}]}"#;
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string(), Some(expected_response.to_string()));
+    }
+
+    #[test]
+    fn test_simulate_circuit_density_matrix_with_noise() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/simulate")
+            .header(rocket::http::ContentType::JSON)
+            .body(
+                r#"{
+                    "circuit_matrix": [
+                        ["X"]
+                    ],
+                    "simulation_mode": "density_matrix",
+                    "noise": {
+                        "gates": { "X": [{ "channel": "bit_flip", "probability": 0.25 }] }
+                    }
+                }"#,
+            )
+            .dispatch();
+
+        let expected_response = r#"{"state_list":[{"step":0,"density_matrix":[[{"re":1.0,"im":0.0},{"re":0.0,"im":0.0}],[{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}]],"purity":1.0,"qubits":[{"density_matrix":[[{"re":1.0,"im":0.0},{"re":0.0,"im":0.0}],[{"re":0.0,"im":0.0},{"re":0.0,"im":0.0}]],"bloch_vector":{"x":0.0,"y":0.0,"z":1.0},"purity":1.0}]},{"step":1,"density_matrix":[[{"re":0.25,"im":0.0},{"re":0.0,"im":0.0}],[{"re":0.0,"im":0.0},{"re":0.7499999999999999,"im":0.0}]],"purity":0.6249999999999999,"qubits":[{"density_matrix":[[{"re":0.25,"im":0.0},{"re":0.0,"im":0.0}],[{"re":0.0,"im":0.0},{"re":0.7499999999999999,"im":0.0}]],"bloch_vector":{"x":0.0,"y":0.0,"z":-0.4999999999999999},"purity":0.6249999999999999}]}]}"#;
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string(), Some(expected_response.to_string()));
+    }
+
+    #[test]
+    fn test_density_matrix_qubit_limit() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+        let circuit_matrix = vec![vec!["I"]; default_max_density_matrix_qubits() + 1];
+
+        let response = client
+            .post("/simulate")
+            .header(rocket::http::ContentType::JSON)
+            .body(
+                serde_json::json!({
+                    "circuit_matrix": circuit_matrix,
+                    "simulation_mode": "density_matrix"
+                })
+                .to_string(),
+            )
+            .dispatch();
+
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn test_sample_circuit() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/sample")
+            .header(rocket::http::ContentType::JSON)
+            .body(
+                r#"{
+                    "circuit_matrix": [
+                        ["X", "M"],
+                        ["I", "M"]
+                    ],
+                    "shots": 10,
+                    "seed": 1
+                }"#,
+            )
+            .dispatch();
+
+        let expected_response = r#"{"shots":10,"counts":{"10":10}}"#;
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string(), Some(expected_response.to_string()));
+    }
+
+    #[test]
+    fn test_sample_circuit_with_seed_is_reproducible() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+        let body = r#"{
+            "circuit_matrix": [
+                ["H", "CNOT-1"],
+                ["H", "CNOT-2"],
+                ["H", "I"]
+            ],
+            "shots": 100,
+            "seed": 42
+        }"#;
+
+        let first = client
+            .post("/sample")
+            .header(rocket::http::ContentType::JSON)
+            .body(body)
+            .dispatch()
+            .into_string();
+        let second = client
+            .post("/sample")
+            .header(rocket::http::ContentType::JSON)
+            .body(body)
+            .dispatch()
+            .into_string();
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_export_qasm() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/export/qasm")
+            .header(rocket::http::ContentType::JSON)
+            .body(
+                r#"{
+                    "circuit_matrix": [
+                        ["H", "CNOT-1"],
+                        ["I", "CNOT-2"]
+                    ],
+                    "version": "3.0"
+                }"#,
+            )
+            .dispatch();
+
+        let expected_response = r#"{"qasm":"OPENQASM 3.0;\ninclude \"stdgates.inc\";\nqubit[2] q;\nh q[0];\ncx q[0],q[1];\n"}"#;
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string(), Some(expected_response.to_string()));
+    }
+
+    #[test]
+    fn test_import_qasm() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/import/qasm")
+            .header(rocket::http::ContentType::JSON)
+            .body(
+                serde_json::json!({
+                    "qasm": "OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[2];\nh q[0];\ncx q[0],q[1];\n"
+                })
+                .to_string(),
+            )
+            .dispatch();
+
+        let expected_response = r#"{"circuit_matrix":[["H","CNOT-1"],["I","CNOT-2"]]}"#;
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string(), Some(expected_response.to_string()));
+    }
+
+    #[test]
+    fn test_import_qasm_unsupported_feature() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/import/qasm")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{ "qasm": "OPENQASM 2.0;\nqreg q[1];\nreset q[0];" }"#)
+            .dispatch();
+
+        let expected_response =
+            r#"{"error":{"UnsupportedQasm":"line 3: reset can not be represented in the grid"}}"#;
+
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(response.into_string(), Some(expected_response.to_string()));
+    }
+
+    #[test]
+    fn test_unitary() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        // CNOT with qubit 0 as control, in little-endian order qubit 0 is the lowest bit
+        let response = client
+            .post("/unitary")
+            .header(rocket::http::ContentType::JSON)
+            .body(
+                r#"{
+                    "circuit_matrix": [
+                        ["CNOT-1"],
+                        ["CNOT-2"]
+                    ],
+                    "per_step": true
+                }"#,
+            )
+            .dispatch();
+
+        let zero = r#"{"re":0.0,"im":0.0}"#;
+        let one = r#"{"re":1.0,"im":0.0}"#;
+        let identity = format!(
+            "[[{one},{zero},{zero},{zero}],[{zero},{one},{zero},{zero}],[{zero},{zero},{one},{zero}],[{zero},{zero},{zero},{one}]]"
+        );
+        let cnot = format!(
+            "[[{one},{zero},{zero},{zero}],[{zero},{zero},{zero},{one}],[{zero},{zero},{one},{zero}],[{zero},{one},{zero},{zero}]]"
+        );
+        let expected_response = format!(
+            r#"{{"unitary":{cnot},"steps":[{{"step":0,"unitary":{identity}}},{{"step":1,"unitary":{cnot}}}]}}"#
+        );
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string(), Some(expected_response));
+    }
+
+    #[test]
+    fn test_unitary_with_measurement() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/unitary")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{ "circuit_matrix": [["H", "M"]] }"#)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(
+            response.into_string(),
+            Some(r#"{"error":"NonUnitaryOperation"}"#.to_string())
+        );
+    }
+
+    #[test]
+    fn test_compare() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/compare")
+            .header(rocket::http::ContentType::JSON)
+            .body(
+                r#"{
+                    "circuit_matrix": [
+                        ["CNOT-1", "CNOT-2", "CNOT-1"],
+                        ["CNOT-2", "CNOT-1", "CNOT-2"]
+                    ],
+                    "target_matrix": [
+                        ["SWAP-1"],
+                        ["SWAP-2"]
+                    ]
+                }"#,
+            )
+            .dispatch();
+
+        let expected_response =
+            r#"{"equivalence":"equivalent","average_gate_fidelity":1.0,"global_phase":0.0}"#;
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string(), Some(expected_response.to_string()));
+    }
+
+    #[test]
+    fn test_simulate_circuit_with_analysis() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/simulate")
+            .header(rocket::http::ContentType::JSON)
+            .body(
+                r#"{
+                    "circuit_matrix": [
+                        ["H", "CNOT-1"],
+                        ["I", "CNOT-2"]
+                    ],
+                    "analysis": true
+                }"#,
+            )
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        let body: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        let analysis = &body["state_list"][2]["analysis"];
+        let close = |value: &serde_json::Value, expected: f64| {
+            (value.as_f64().unwrap() - expected).abs() < 1e-9
+        };
+
+        assert!(close(&analysis["entropies"][0], 1.0));
+        assert!(close(&analysis["concurrence"][0][1], 1.0));
+        assert!(close(&analysis["mutual_information"][1][0], 2.0));
+        assert!(close(
+            &body["state_list"][1]["analysis"]["concurrence"][0][1],
+            0.0
+        ));
+    }
+
+    #[test]
+    fn test_simulate_circuit_analysis_qubit_limit() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+        let circuit_matrix = vec![vec!["I"]; default_max_analysis_qubits() + 1];
+
+        let response = client
+            .post("/simulate")
+            .header(rocket::http::ContentType::JSON)
+            .body(
+                serde_json::json!({ "circuit_matrix": circuit_matrix, "analysis": true })
+                    .to_string(),
+            )
+            .dispatch();
+
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn test_simulate_large_clifford_circuit() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+        // A GHZ state on more qubits than a state vector may have
+        let no_of_qubits = default_max_qubits() + 76;
+        let circuit_matrix: Vec<Vec<String>> = (0..no_of_qubits)
+            .map(|qubit| {
+                (0..no_of_qubits)
+                    .map(|column| match (qubit, column) {
+                        (0, 0) => "H".to_string(),
+                        _ if column > 0 && qubit + 1 == column => "CNOT-1".to_string(),
+                        _ if column > 0 && qubit == column => "CNOT-2".to_string(),
+                        _ => "I".to_string(),
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let response = client
+            .post("/simulate")
+            .header(rocket::http::ContentType::JSON)
+            .body(serde_json::json!({ "circuit_matrix": circuit_matrix }).to_string())
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        let body: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        let last_step = &body["state_list"][no_of_qubits];
+        assert!(last_step.get("state").is_none());
+        assert_eq!(
+            last_step["stabilizers"][0].as_str().unwrap(),
+            format!("+{}", "X".repeat(no_of_qubits))
+        );
+        assert_eq!(
+            last_step["stabilizers"][1].as_str().unwrap(),
+            format!("+ZZ{}", "I".repeat(no_of_qubits - 2))
+        );
+    }
+
+    #[test]
+    fn test_simulate_long_chain_with_mps() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+        // A 1D circuit on more qubits than a state vector may have, with entangling gates between neighbours
+        let no_of_qubits: usize = 40;
+        let circuit_matrix: Vec<Vec<String>> = (0..no_of_qubits)
+            .map(|qubit| {
+                let part = format!("CZ-{}#{}", qubit % 2 + 1, qubit / 2);
+                let shifted = match qubit {
+                    0 => "I".to_string(),
+                    _ if qubit == no_of_qubits - 1 => "I".to_string(),
+                    _ => format!("CZ-{}#{}", (qubit + 1) % 2 + 1, qubit.div_ceil(2)),
+                };
+                vec![
+                    "H".to_string(),
+                    part.clone(),
+                    "RX(0.4)".to_string(),
+                    shifted,
+                    "RY(0.7)".to_string(),
+                    part,
+                ]
+            })
+            .collect();
+
+        let response = client
+            .post("/simulate")
+            .header(rocket::http::ContentType::JSON)
+            .body(
+                serde_json::json!({
+                    "circuit_matrix": circuit_matrix,
+                    "simulation_mode": "mps",
+                    "mps": { "max_bond_dimension": 8 }
+                })
+                .to_string(),
+            )
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        let body: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        let last_step = &body["state_list"][6];
+        assert!(last_step.get("state").is_none());
+        assert!(last_step["truncation_error"].as_f64().unwrap() < 1e-9);
+        // Every cut is crossed by at most two CZ gates
+        assert_eq!(last_step["bond_dimension"].as_u64(), Some(4));
+        assert_eq!(last_step["qubits"].as_array().unwrap().len(), no_of_qubits);
+    }
+
+    #[test]
+    fn test_simulate_mps_bond_dimension_limit() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/simulate")
+            .header(rocket::http::ContentType::JSON)
+            .body(
+                serde_json::json!({
+                    "circuit_matrix": [["H"]],
+                    "simulation_mode": "mps",
+                    "mps": { "max_bond_dimension": default_max_bond_dimension() + 1 }
+                })
+                .to_string(),
+            )
+            .dispatch();
+
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(
+            response.into_string(),
+            Some(r#"{"error":"InvalidMpsOptions"}"#.to_string())
+        );
+    }
+
+    #[test]
+    fn test_expectation_values() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/expectation")
+            .header(rocket::http::ContentType::JSON)
+            .body(
+                r#"{
+                    "circuit_matrix": [
+                        ["X"],
+                        ["I"]
+                    ],
+                    "observables": ["ZI", "IZ", "0.5*ZZ + 2*XX"],
+                    "per_step": true
+                }"#,
+            )
+            .dispatch();
+
+        let expected_response = r#"{"expectation_values":[-1.0,1.0,-0.5],"steps":[{"step":0,"expectation_values":[1.0,1.0,0.5]},{"step":1,"expectation_values":[-1.0,1.0,-0.5]}]}"#;
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string(), Some(expected_response.to_string()));
+    }
+
+    #[test]
+    fn test_custom_gates() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/gates")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"name": "BELL", "circuit": [["H", "CNOT-1"], ["I", "CNOT-2"]]}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        // Controlled by qubit 0, so the Bell pair is only made on qubits 1 and 2 when qubit 0 is 1
+        let response = client
+            .post("/simulate")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"circuit_matrix": [["X", "C"], ["I", "BELL-1"], ["I", "BELL-2"]]}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        let state = &body["state_list"][2]["state"];
+        // Little endian, so |100> is index 1 and |111> index 7
+        assert!((state[1]["re"].as_f64().unwrap() - 0.5_f64.sqrt()).abs() < 1e-12);
+        assert!((state[7]["re"].as_f64().unwrap() - 0.5_f64.sqrt()).abs() < 1e-12);
+
+        let response = client.get("/gates").dispatch();
+        let body: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(body["gates"][0]["name"], "BELL");
+        assert_eq!(body["gates"][0]["size"], 2);
+
+        let response = client
+            .post("/gates")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"name": "BAD", "matrix": [[{"re": 1.0, "im": 0.0}, {"re": 1.0, "im": 0.0}], [{"re": 0.0, "im": 0.0}, {"re": 1.0, "im": 0.0}]]}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(
+            response.into_string(),
+            Some(r#"{"error":{"InvalidCustomGate":"the matrix is not unitary"}}"#.to_string())
+        );
+
+        let response = client
+            .post("/gates")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"name": "BELL", "circuit": [["H"], ["H"]]}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(
+            response.into_string(),
+            Some(
+                r#"{"error":{"InvalidCustomGate":"a gate with this name already exists"}}"#
+                    .to_string()
+            )
+        );
+
+        // Gates are only known to the server they were defined on
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+        let response = client
+            .post("/simulate")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"circuit_matrix": [["BELL-1"], ["BELL-2"]]}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn test_custom_gate_limit() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        for index in 0..default_max_custom_gates() {
+            let response = client
+                .post("/gates")
+                .header(rocket::http::ContentType::JSON)
+                .body(format!(r#"{{"name": "G{}", "circuit": [["H"]]}}"#, index))
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+        }
+
+        let response = client
+            .post("/gates")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"name": "LAST", "circuit": [["H"]]}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        let response = client.get("/gates").dispatch();
+        let body: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(
+            body["gates"].as_array().unwrap().len(),
+            default_max_custom_gates()
+        );
+    }
 }
@@ -0,0 +1,519 @@
+// Typed representation of a circuit, produced from the grid by circuit_parser::parse_circuit
+// A circuit is a list of moments (the columns of the grid), every moment holds operations on disjoint qubits
+// Validation, simulation and export work on this instead of the strings of the grid,
+// so a new gate only has to be added to Gate
+
+use crate::simulation::gate_registry::CustomGate;
+use crate::simulation::qasm::format_angle;
+use crate::simulation::quantum_gate::QuantumGate;
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+// Every gate a cell of the grid can name, multi-qubit gates are written as parts, e.g. "CNOT-1" and "CNOT-2"
+// Custom gates come from the GateRegistry the grid is parsed with
+#[derive(Debug, Clone, PartialEq)]
+pub enum Gate {
+    I,
+    H,
+    X,
+    Y,
+    Z,
+    T,
+    S,
+    SX,
+    RX,
+    RY,
+    RZ,
+    P,
+    U3,
+    Cnot,
+    Cz,
+    Swap,
+    Ccnot,
+    Measure,
+    Custom(Arc<CustomGate>),
+}
+
+impl Gate {
+    const ALL: [Gate; 18] = [
+        Gate::I,
+        Gate::H,
+        Gate::X,
+        Gate::Y,
+        Gate::Z,
+        Gate::T,
+        Gate::S,
+        Gate::SX,
+        Gate::RX,
+        Gate::RY,
+        Gate::RZ,
+        Gate::P,
+        Gate::U3,
+        Gate::Cnot,
+        Gate::Cz,
+        Gate::Swap,
+        Gate::Ccnot,
+        Gate::Measure,
+    ];
+
+    // The name used in the cells of the grid
+    pub fn name(&self) -> &str {
+        match self {
+            Gate::I => "I",
+            Gate::H => "H",
+            Gate::X => "X",
+            Gate::Y => "Y",
+            Gate::Z => "Z",
+            Gate::T => "T",
+            Gate::S => "S",
+            Gate::SX => "SX",
+            Gate::RX => "RX",
+            Gate::RY => "RY",
+            Gate::RZ => "RZ",
+            Gate::P => "P",
+            Gate::U3 => "U3",
+            Gate::Cnot => "CNOT",
+            Gate::Cz => "CZ",
+            Gate::Swap => "SWAP",
+            Gate::Ccnot => "CCNOT",
+            Gate::Measure => "M",
+            Gate::Custom(gate) => &gate.name,
+        }
+    }
+
+    // Only the built-in gates, custom gates are found with GateRegistry::get
+    pub fn from_name(name: &str) -> Option<Gate> {
+        Gate::ALL.into_iter().find(|gate| gate.name() == name)
+    }
+
+    // The number of qubits the gate acts on, without controls
+    pub fn size(&self) -> usize {
+        match self {
+            Gate::Cnot | Gate::Cz | Gate::Swap => 2,
+            Gate::Ccnot => 3,
+            Gate::Custom(gate) => gate.matrix.size,
+            _ => 1,
+        }
+    }
+
+    // The number of angles in the parentheses, e.g. 3 for "U3(theta, phi, lambda)"
+    pub fn no_of_params(&self) -> usize {
+        match self {
+            Gate::RX | Gate::RY | Gate::RZ | Gate::P => 1,
+            Gate::U3 => 3,
+            _ => 0,
+        }
+    }
+
+    // Gates that undo themselves when applied twice to the same qubits in the same order
+    pub fn is_self_inverse(&self) -> bool {
+        matches!(
+            self,
+            Gate::H
+                | Gate::X
+                | Gate::Y
+                | Gate::Z
+                | Gate::Cnot
+                | Gate::Cz
+                | Gate::Swap
+                | Gate::Ccnot
+        )
+    }
+
+    // The matrix of the gate, the first qubit of the matrix is part 1
+    // Panics for a measurement or a wrong number of parameters, which the parser rules out
+    pub fn matrix(&self, params: &[f64]) -> QuantumGate {
+        match (self, params) {
+            (Gate::I, []) => QuantumGate::i_gate(),
+            (Gate::H, []) => QuantumGate::h_gate(),
+            (Gate::X, []) => QuantumGate::x_gate(),
+            (Gate::Y, []) => QuantumGate::y_gate(),
+            (Gate::Z, []) => QuantumGate::z_gate(),
+            (Gate::T, []) => QuantumGate::t_gate(),
+            (Gate::S, []) => QuantumGate::s_gate(),
+            (Gate::SX, []) => QuantumGate::sx_gate(),
+            (Gate::RX, [theta]) => QuantumGate::rx(*theta),
+            (Gate::RY, [theta]) => QuantumGate::ry(*theta),
+            (Gate::RZ, [theta]) => QuantumGate::rz(*theta),
+            (Gate::P, [lambda]) => QuantumGate::phase(*lambda),
+            (Gate::U3, [theta, phi, lambda]) => QuantumGate::u3(*theta, *phi, *lambda),
+            (Gate::Cnot, []) => QuantumGate::cnot_gate(),
+            (Gate::Cz, []) => QuantumGate::cz_gate(),
+            (Gate::Swap, []) => QuantumGate::swap_gate(),
+            (Gate::Ccnot, []) => QuantumGate::ccnot_gate(),
+            (Gate::Custom(gate), []) => gate.matrix.clone(),
+            _ => panic!(
+                "{} has no matrix for {} parameters",
+                self.name(),
+                params.len()
+            ),
+        }
+    }
+}
+
+// Largest number of qubits, controls included, of an operation whose full matrix is built, 2^10 x 2^10 is 16 MiB
+// The state vector and density matrix apply the gate to the matching blocks instead, so they have no such limit
+pub const MAX_MATRIX_QUBITS: usize = 10;
+
+// A control marker, "C" controls on |1> (state true) and "O" on |0> (state false)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Control {
+    pub qubit: usize,
+    pub state: bool,
+}
+
+// A gate with the qubits it acts on
+// targets are in part order, so targets[0] is the qubit of "CNOT-1", and a measurement has a single target
+// The operation is only applied if the classical bit of the condition is 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operation {
+    pub gate: Gate,
+    pub targets: Vec<usize>,
+    pub controls: Vec<Control>,
+    pub params: Vec<f64>,
+    pub condition: Option<usize>,
+}
+
+impl Operation {
+    pub fn new(gate: Gate, targets: Vec<usize>, params: Vec<f64>) -> Operation {
+        Operation {
+            gate,
+            targets,
+            controls: Vec::new(),
+            params,
+            condition: None,
+        }
+    }
+
+    // All qubits the operation touches, the controls come before the targets like in matrix()
+    pub fn qubits(&self) -> Vec<usize> {
+        self.controls
+            .iter()
+            .map(|control| control.qubit)
+            .chain(self.targets.iter().copied())
+            .collect()
+    }
+
+    pub fn is_measurement(&self) -> bool {
+        self.gate == Gate::Measure
+    }
+
+    // Whether this operation undoes the other, which must be the same self-inverse gate on the same qubits
+    // The order of symmetric parts does not matter, e.g. SWAP-1 and SWAP-2 can be exchanged
+    pub fn cancels(&self, other: &Operation) -> bool {
+        let sorted = |qubits: &[usize]| {
+            let mut qubits = qubits.to_vec();
+            qubits.sort_unstable();
+            qubits
+        };
+        let same_targets = match self.gate {
+            Gate::Cz | Gate::Swap => sorted(&self.targets) == sorted(&other.targets),
+            Gate::Ccnot => {
+                sorted(&self.targets[..2]) == sorted(&other.targets[..2])
+                    && self.targets[2] == other.targets[2]
+            }
+            _ => self.targets == other.targets,
+        };
+        let mut controls = self.controls.clone();
+        let mut other_controls = other.controls.clone();
+        controls.sort_by_key(|control| control.qubit);
+        other_controls.sort_by_key(|control| control.qubit);
+
+        self.gate.is_self_inverse()
+            && self.gate == other.gate
+            && self.condition.is_none()
+            && other.condition.is_none()
+            && same_targets
+            && controls == other_controls
+    }
+
+    // The matrix for the qubits in qubits(), including the controls, only for operations up to MAX_MATRIX_QUBITS
+    pub fn matrix(&self) -> QuantumGate {
+        let gate = self.gate.matrix(&self.params);
+
+        match self.controls.is_empty() {
+            true => gate,
+            false => {
+                let states: Vec<bool> = self.controls.iter().map(|control| control.state).collect();
+                gate.controlled_on(&states)
+            }
+        }
+    }
+
+    // The cell of a target qubit in the grid, e.g. "RX(pi/2)", "CNOT-2#1" or "X?c0"
+    fn cell(&self, part: usize, group: Option<usize>) -> String {
+        let mut cell = self.gate.name().to_string();
+
+        if !self.params.is_empty() {
+            let params: Vec<String> = self
+                .params
+                .iter()
+                .map(|param| format_angle(*param))
+                .collect();
+            cell = format!("{}({})", cell, params.join(", "));
+        }
+        if self.gate.size() > 1 {
+            cell = format!("{}-{}", cell, part + 1);
+        }
+        if let Some(group) = group {
+            cell = format!("{}#{}", cell, group);
+        }
+        if let Some(bit) = self.condition {
+            cell = format!("{}?c{}", cell, bit);
+        }
+
+        cell
+    }
+}
+
+// The operations of one column of the grid
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Moment {
+    pub operations: Vec<Operation>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Circuit {
+    pub qubits: usize,
+    pub moments: Vec<Moment>,
+}
+
+impl Circuit {
+    // Place operations given in the order they are applied in as few columns as possible
+    // An operation depends on the operations before it on its qubits, a classically controlled gate
+    // on the measurement of its bit and a measurement on the gates that read the bit before it
+    // Every column is filled with the operations whose dependencies are all in earlier columns,
+    // starting with the one with the longest chain of operations after it
+    // Like in the grid an operation with controls gets a column of its own, so if that one has controls
+    // it is the only one in the column, otherwise all ready operations without controls share the column
+    pub fn from_operations(qubits: usize, operations: Vec<Operation>) -> Circuit {
+        let mut successors: Vec<Vec<usize>> = vec![Vec::new(); operations.len()];
+        let mut dependencies = vec![0; operations.len()];
+        // The last operation on every qubit, the last measurement of every bit and the gates that read it since
+        let mut last: Vec<Option<usize>> = vec![None; qubits];
+        let mut measurements: Vec<Option<usize>> = vec![None; qubits];
+        let mut readers: Vec<Vec<usize>> = vec![Vec::new(); qubits];
+
+        for (index, operation) in operations.iter().enumerate() {
+            let mut before: Vec<usize> = operation
+                .qubits()
+                .iter()
+                .filter_map(|&qubit| last[qubit].replace(index))
+                .collect();
+            if let Some(bit) = operation.condition {
+                before.extend(measurements[bit]);
+                readers[bit].push(index);
+            }
+            if operation.is_measurement() {
+                let bit = operation.targets[0];
+                before.append(&mut readers[bit]);
+                measurements[bit] = Some(index);
+            }
+
+            before.sort_unstable();
+            before.dedup();
+            dependencies[index] = before.len();
+            before
+                .into_iter()
+                .for_each(|previous| successors[previous].push(index));
+        }
+
+        // The number of operations on the longest chain starting at every operation
+        let mut heights = vec![1; operations.len()];
+        for index in (0..operations.len()).rev() {
+            heights[index] += successors[index]
+                .iter()
+                .map(|&next| heights[next])
+                .max()
+                .unwrap_or(0);
+        }
+
+        let mut ready: BTreeSet<(Reverse<usize>, usize)> = (0..operations.len())
+            .filter(|&index| dependencies[index] == 0)
+            .map(|index| (Reverse(heights[index]), index))
+            .collect();
+        let mut columns: Vec<Vec<usize>> = Vec::new();
+        while let Some(&(_, first)) = ready.first() {
+            let column: Vec<usize> = match operations[first].controls.is_empty() {
+                true => ready
+                    .iter()
+                    .map(|&(_, index)| index)
+                    .filter(|&index| operations[index].controls.is_empty())
+                    .collect(),
+                false => vec![first],
+            };
+
+            for &index in &column {
+                ready.remove(&(Reverse(heights[index]), index));
+                for &next in &successors[index] {
+                    dependencies[next] -= 1;
+                    if dependencies[next] == 0 {
+                        ready.insert((Reverse(heights[next]), next));
+                    }
+                }
+            }
+            columns.push(column);
+        }
+
+        let mut operations: Vec<Option<Operation>> = operations.into_iter().map(Some).collect();
+        let moments = columns
+            .into_iter()
+            .map(|mut column| {
+                column.sort_unstable();
+                Moment {
+                    operations: column
+                        .into_iter()
+                        .filter_map(|index| operations[index].take())
+                        .collect(),
+                }
+            })
+            .collect();
+
+        Circuit { qubits, moments }
+    }
+
+    pub fn operations(&self) -> impl Iterator<Item = &Operation> {
+        self.moments
+            .iter()
+            .flat_map(|moment| moment.operations.iter())
+    }
+
+    pub fn has_measurements(&self) -> bool {
+        self.operations().any(Operation::is_measurement)
+    }
+
+    pub fn has_conditions(&self) -> bool {
+        self.operations()
+            .any(|operation| operation.condition.is_some())
+    }
+
+    // The number of layers when every operation is applied as soon as its qubits are free
+    // A classically controlled gate also waits for the measurement that writes its bit
+    pub fn depth(&self) -> usize {
+        let mut layers = vec![0; self.qubits];
+
+        for operation in self.operations() {
+            let qubits = operation.qubits();
+            let layer = 1 + qubits
+                .iter()
+                .chain(operation.condition.iter())
+                .map(|&qubit| layers[qubit])
+                .max()
+                .unwrap();
+            qubits.iter().for_each(|&qubit| layers[qubit] = layer);
+        }
+
+        layers.into_iter().max().unwrap_or(0)
+    }
+
+    // Write the circuit back to the grid format, with one column per moment
+    // Multi-qubit gates get a group number when their moment has more than one gate of the same kind
+    pub fn to_grid(&self) -> Vec<Vec<String>> {
+        let mut grid = vec![vec!["I".to_string(); self.moments.len()]; self.qubits];
+
+        for (step, moment) in self.moments.iter().enumerate() {
+            for (index, operation) in moment.operations.iter().enumerate() {
+                let same_kind = moment
+                    .operations
+                    .iter()
+                    .filter(|other| other.gate.size() > 1 && other.gate == operation.gate)
+                    .count();
+                let group = (same_kind > 1).then_some(index);
+
+                for (part, &qubit) in operation.targets.iter().enumerate() {
+                    grid[qubit][step] = operation.cell(part, group);
+                }
+                for control in &operation.controls {
+                    grid[control.qubit][step] = if control.state { "C" } else { "O" }.to_string();
+                }
+            }
+        }
+
+        grid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::circuit_parser::parse_circuit;
+    use crate::simulation::gate_registry::GateRegistry;
+
+    #[test]
+    fn test_gate_names() {
+        for gate in Gate::ALL {
+            assert_eq!(Gate::from_name(gate.name()), Some(gate));
+        }
+        assert_eq!(Gate::from_name("CNOT-1"), None);
+    }
+
+    #[test]
+    fn test_parse_circuit() {
+        let grid = vec![
+            vec!["H", "CNOT-1#0", "C", "M", "I"],
+            vec!["RX(pi/4)", "CNOT-2#0", "O", "I", "U3(pi/2, 0, -pi)?c0"],
+            vec!["I", "CNOT-2#1", "SWAP-1", "I", "I"],
+            vec!["I", "CNOT-1#1", "SWAP-2", "I", "I"],
+        ];
+
+        let circuit = parse_circuit(&grid, &GateRegistry::default()).unwrap();
+
+        assert_eq!(circuit.qubits, 4);
+        assert_eq!(circuit.moments.len(), 5);
+        assert_eq!(
+            circuit.moments[0].operations,
+            vec![
+                Operation::new(Gate::H, vec![0], vec![]),
+                Operation::new(Gate::RX, vec![1], vec![std::f64::consts::PI / 4.0]),
+            ]
+        );
+        assert_eq!(
+            circuit.moments[1].operations,
+            vec![
+                Operation::new(Gate::Cnot, vec![0, 1], vec![]),
+                Operation::new(Gate::Cnot, vec![3, 2], vec![]),
+            ]
+        );
+        assert_eq!(circuit.moments[2].operations[0].qubits(), vec![0, 1, 2, 3]);
+        assert!(circuit.moments[3].operations[0].is_measurement());
+        assert_eq!(circuit.moments[4].operations[0].condition, Some(0));
+        assert!(circuit.moments[4].operations[0].controls.is_empty());
+        assert!(circuit.has_measurements());
+        assert!(circuit.has_conditions());
+    }
+
+    #[test]
+    fn test_from_operations() {
+        let controlled_z = Operation {
+            controls: vec![Control {
+                qubit: 0,
+                state: true,
+            }],
+            ..Operation::new(Gate::Z, vec![2], vec![])
+        };
+        let conditioned_x = Operation {
+            condition: Some(1),
+            ..Operation::new(Gate::X, vec![2], vec![])
+        };
+        let operations = vec![
+            Operation::new(Gate::H, vec![0], vec![]),
+            Operation::new(Gate::X, vec![2], vec![]),
+            Operation::new(Gate::Cnot, vec![0, 1], vec![]),
+            controlled_z,
+            Operation::new(Gate::Measure, vec![1], vec![]),
+            conditioned_x,
+        ];
+
+        let circuit = Circuit::from_operations(3, operations);
+
+        assert_eq!(
+            circuit.to_grid(),
+            vec![
+                vec!["H", "CNOT-1", "C", "I", "I"],
+                vec!["I", "CNOT-2", "I", "M", "I"],
+                vec!["X", "I", "Z", "I", "X?c1"],
+            ]
+        );
+    }
+}
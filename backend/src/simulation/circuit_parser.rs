@@ -1,65 +1,465 @@
+use crate::simulation::circuit::{Circuit, Control, Gate, Moment, Operation};
+use crate::simulation::circuit_validator::{CircuitProblem, QuantumCircuitError};
+use crate::simulation::expression_parser::parse_expression;
+use crate::simulation::gate_registry::GateRegistry;
 use crate::simulation::quantum_gate::QuantumGate;
-use ndarray::{arr2, Array1};
+use crate::simulation::quantum_state::QuantumState;
+use ndarray::Array2;
 use num::Complex;
 
-pub fn build_circuit_from_data(grid: Vec<Vec<&str>>) -> Array1<QuantumGate> {
-    let mut return_list: Vec<QuantumGate> = Vec::new();
+// What a single cell of the grid holds
+// The parts of multi-qubit gates are collected by group_gate_parts
+enum Cell {
+    Identity,
+    Control(bool),
+    Part,
+    Operation(Operation),
+}
+
+// Parse the grid into a circuit with one moment per column, this is the only place the cells are interpreted
+// Checks that all rows have the same length, every cell is a valid gate, the parts of every multi-qubit gate
+// are complete and control markers have exactly one gate to control
+// The number of qubits and the classical bits are checked by circuit_validator::validate_circuit
+// Names that are not built-in gates are looked up in the registry
+pub fn parse_circuit(
+    grid: &[Vec<&str>],
+    gates: &GateRegistry,
+) -> Result<Circuit, QuantumCircuitError> {
+    let (circuit, problems) = parse_grid(grid, gates);
+
+    match problems.is_empty() {
+        true => Ok(circuit),
+        false => Err(QuantumCircuitError::InvalidCircuit(problems)),
+    }
+}
 
-    for step in 0..grid[0].len() {
-        let mut combined_gate: Option<QuantumGate> = None;
+// Parse the grid as far as possible and collect every problem instead of stopping at the first
+// The circuit leaves out the cells with problems, so it is only complete if there are none
+// If the rows have different lengths only those rows are reported, since the columns are not known
+pub fn parse_grid(grid: &[Vec<&str>], gates: &GateRegistry) -> (Circuit, Vec<CircuitProblem>) {
+    let row_length = grid.first().map_or(0, |row| row.len());
+    let mut circuit = Circuit {
+        qubits: grid.len(),
+        moments: Vec::new(),
+    };
+
+    let uneven_rows: Vec<CircuitProblem> = grid
+        .iter()
+        .enumerate()
+        .filter(|(_, row)| row.len() != row_length)
+        .map(|(qubit, row)| CircuitProblem {
+            error: QuantumCircuitError::InvalidRowLength,
+            row: qubit,
+            column: None,
+            token: None,
+            message: format!(
+                "row {} has {} steps, but row 0 has {}",
+                qubit,
+                row.len(),
+                row_length
+            ),
+        })
+        .collect();
+    if !uneven_rows.is_empty() {
+        return (circuit, uneven_rows);
+    }
 
-        for qubit in &grid {
-            let gate = parse_gate(qubit[step]);
+    let mut problems: Vec<CircuitProblem> = Vec::new();
+    for step in 0..row_length {
+        let column: Vec<&str> = grid.iter().map(|row| row[step]).collect();
+        let (moment, column_problems) = parse_column(step, &column, gates);
+        circuit.moments.push(moment);
+        problems.extend(column_problems);
+    }
 
-            // If there is already a gate for this qubit, combine it with the new gate
-            combined_gate = Some(match combined_gate {
-                Some(existing_gate) => existing_gate.kronecker(gate),
-                None => gate,
-            });
+    (circuit, problems)
+}
+
+// Parse a column into the operations of one moment, the multi-qubit gates first and then the other gates by row
+// The parts of multi-qubit gates can be on any rows and in any order,
+// but every group must contain each of its parts exactly once
+// The problems are ordered by row
+fn parse_column(
+    step: usize,
+    column: &[&str],
+    gates: &GateRegistry,
+) -> (Moment, Vec<CircuitProblem>) {
+    let problem = |row: usize, error: QuantumCircuitError, message: String| CircuitProblem {
+        error,
+        row,
+        column: Some(step),
+        token: Some(column[row].to_string()),
+        message,
+    };
+    let mut problems: Vec<CircuitProblem> = Vec::new();
+
+    let cells: Vec<Option<Cell>> = column
+        .iter()
+        .map(|gate_string| parse_cell(gate_string, gates))
+        .collect();
+    for (row, _) in cells.iter().enumerate().filter(|(_, cell)| cell.is_none()) {
+        problems.push(problem(
+            row,
+            QuantumCircuitError::InvalidGate,
+            format!("\"{}\" is not a valid gate", column[row]),
+        ));
+    }
+
+    let mut operations: Vec<Operation> = Vec::new();
+    for (name, parts) in group_gate_parts(column, gates) {
+        if parts.iter().any(|rows| rows.len() != 1) {
+            for &row in parts.iter().flatten() {
+                problems.push(problem(
+                    row,
+                    QuantumCircuitError::MultiQubitGateMismatch,
+                    format!(
+                        "\"{}\" belongs to a {} that does not have each of its {} parts exactly once in this step",
+                        column[row],
+                        name,
+                        parts.len()
+                    ),
+                ));
+            }
+            continue;
+        }
+
+        let targets = parts.iter().map(|rows| rows[0]).collect();
+        operations.push(Operation::new(
+            gates.get(name).unwrap(),
+            targets,
+            Vec::new(),
+        ));
+    }
+
+    let mut controls: Vec<Control> = Vec::new();
+    for (qubit, cell) in cells.into_iter().enumerate() {
+        match cell {
+            None | Some(Cell::Identity) | Some(Cell::Part) => (),
+            Some(Cell::Control(state)) => controls.push(Control { qubit, state }),
+            Some(Cell::Operation(mut operation)) => {
+                operation.targets = vec![qubit];
+                operations.push(operation);
+            }
         }
+    }
 
-        if let Some(gate) = combined_gate {
-            return_list.push(gate);
+    // A column that already has problems may be missing the gate the controls are meant for
+    if !controls.is_empty() {
+        let control_problems = control_problems(&operations, &controls, problems.is_empty());
+        match operations.as_mut_slice() {
+            [operation] if control_problems.is_empty() => operation.controls = controls,
+            _ => problems.extend(
+                control_problems
+                    .into_iter()
+                    .map(|(row, error, message)| problem(row, error, message)),
+            ),
         }
     }
 
-    Array1::from(return_list)
+    problems.sort_by_key(|problem| problem.row);
+    (Moment { operations }, problems)
+}
+
+// Check that the control markers of a column have exactly one gate to control
+// Every multi-qubit gate group and every other gate that is not an identity counts as one target
+// Returns the rows with problems, the controls themselves if there is no gate and the gates if there is not exactly one
+fn control_problems(
+    operations: &[Operation],
+    controls: &[Control],
+    report_missing_target: bool,
+) -> Vec<(usize, QuantumCircuitError, String)> {
+    let uncontrollable: Vec<(usize, QuantumCircuitError, String)> = operations
+        .iter()
+        .filter(|operation| operation.is_measurement() || operation.condition.is_some())
+        .map(|operation| {
+            (
+                operation.targets[0],
+                QuantumCircuitError::UncontrollableGate,
+                "measurements and classically controlled gates can not have control markers"
+                    .to_string(),
+            )
+        })
+        .collect();
+    if !uncontrollable.is_empty() {
+        return uncontrollable;
+    }
+
+    match operations {
+        [] if report_missing_target => controls
+            .iter()
+            .map(|control| {
+                (
+                    control.qubit,
+                    QuantumCircuitError::MissingControlTarget,
+                    "there is no gate in this step for the control marker".to_string(),
+                )
+            })
+            .collect(),
+        [] | [_] => Vec::new(),
+        _ => operations
+            .iter()
+            .flat_map(|operation| operation.targets.iter())
+            .map(|&row| {
+                (
+                    row,
+                    QuantumCircuitError::MultipleControlTargets,
+                    "a step with control markers can only have one gate".to_string(),
+                )
+            })
+            .collect(),
+    }
+}
+
+// Parse a single cell, the qubits of an operation are filled in by the column
+// Returns None if the cell is not valid, a classically controlled gate must be a single-qubit gate
+fn parse_cell(gate_string: &str, gates: &GateRegistry) -> Option<Cell> {
+    if gate_string == "I" {
+        return Some(Cell::Identity);
+    }
+    if let Some(state) = control_state(gate_string) {
+        return Some(Cell::Control(state));
+    }
+    if split_gate_part(gate_string, gates).is_some() {
+        return Some(Cell::Part);
+    }
+    if gate_string == "M" {
+        return Some(Cell::Operation(Operation::new(
+            Gate::Measure,
+            Vec::new(),
+            Vec::new(),
+        )));
+    }
+
+    let (gate_string, condition) = match split_classical_condition(gate_string) {
+        Some((gate_string, bit)) => (gate_string, Some(bit)),
+        None => (gate_string, None),
+    };
+    let (gate, params) = parse_single_qubit_gate(gate_string, gates)?;
+
+    Some(Cell::Operation(Operation {
+        condition,
+        ..Operation::new(gate, Vec::new(), params)
+    }))
+}
+
+// Parse a single-qubit gate with its parameters, e.g. "H" or "RX(pi/4)"
+// Only gates with parameters may have parentheses, and they must have the right number of parameters
+fn parse_single_qubit_gate(gate_string: &str, gates: &GateRegistry) -> Option<(Gate, Vec<f64>)> {
+    let (gate, params) = match split_parameters(gate_string) {
+        Some((name, params)) => (
+            gates.get(name).filter(|gate| gate.no_of_params() > 0)?,
+            params,
+        ),
+        None => (gates.get(gate_string)?, Vec::new()),
+    };
+
+    match gate.size() == 1 && gate != Gate::Measure && gate.no_of_params() == params.len() {
+        true => Some((gate, params)),
+        false => None,
+    }
+}
+
+// Expand every moment of the circuit to one gate for all qubits
+// The moments are expanded one at a time as the iterator is consumed, so only the gates kept by the caller use memory
+// Panics if the circuit contains a measurement or classically controlled gate, since they have no matrix representation
+pub fn expand_circuit(circuit: &Circuit) -> impl Iterator<Item = QuantumGate> + '_ {
+    circuit
+        .moments
+        .iter()
+        .map(|moment| expand_moment(moment, circuit.qubits))
 }
 
-fn parse_gate(gate_string: &str) -> QuantumGate {
-    // Multi qubit gates are only applied once, so we can ignore the subsequent parts
+// Expand the operations of a moment to one gate for all qubits by applying them to every basis state
+fn expand_moment(moment: &Moment, no_of_qubits: usize) -> QuantumGate {
+    let dim = 1_usize << no_of_qubits;
+    let mut matrix = Array2::<Complex<f64>>::zeros((dim, dim));
+    let gates: Vec<(&Operation, QuantumGate)> = moment
+        .operations
+        .iter()
+        .map(|operation| {
+            if operation.is_measurement() || operation.condition.is_some() {
+                panic!("A measurement or classically controlled gate can not be expanded to a gate")
+            }
+            (operation, operation.gate.matrix(&operation.params))
+        })
+        .collect();
+
+    for basis in 0..dim {
+        let mut col = Array2::<Complex<f64>>::zeros((dim, 1));
+        col[[basis, 0]] = Complex::new(1.0, 0.0);
+
+        let state = gates
+            .iter()
+            .fold(QuantumState { col }, |state, (operation, gate)| {
+                state.apply_controlled_gate_to_qubits(gate, &operation.controls, &operation.targets)
+            });
+
+        matrix.column_mut(basis).assign(&state.col.column(0));
+    }
+
+    QuantumGate {
+        matrix,
+        size: no_of_qubits,
+    }
+}
+
+// The state a control marker requires, "C" controls on |1> and "O" (anti-control) on |0>
+// Returns None if the string is not a control marker
+pub fn control_state(gate_string: &str) -> Option<bool> {
     match gate_string {
-        "I" => QuantumGate::i_gate(),
-        "H" => QuantumGate::h_gate(),
-        "X" => QuantumGate::x_gate(),
-        "Y" => QuantumGate::y_gate(),
-        "Z" => QuantumGate::z_gate(),
-        "T" => QuantumGate::t_gate(),
-        "S" => QuantumGate::s_gate(),
-        "CZ" => QuantumGate::cz_gate(),
-        "SWAP-1" => QuantumGate::swap_gate(),
-        "CCNOT-1" => QuantumGate::ccnot_gate(),
-        "CNOT-1" => QuantumGate::cnot_gate(),
-        "CNOT-2" | "CCNOT-2" | "CCNOT-3" | "SWAP-2" => QuantumGate {
-            matrix: arr2(&[[Complex::new(1.0_f64, 0.0_f64)]]),
-            size: 0,
-        },
-        _ => panic!("Invalid gate"),
+        "C" => Some(true),
+        "O" => Some(false),
+        _ => None,
+    }
+}
+
+// A part of a multi-qubit gate, written as "<name>-<part>" with an optional "#<group>"
+// e.g. "CNOT-1" is the control and "CNOT-2" the target of a CNOT, "CCNOT-3" is the target of a Toffoli
+#[derive(Debug, PartialEq)]
+pub struct GatePart<'a> {
+    pub name: &'a str,
+    pub part: usize,
+    pub group: Option<usize>,
+}
+
+// The number of parts (qubits) of a multi-qubit gate, None if the name is not a multi-qubit gate
+fn multi_qubit_gate_size(name: &str, gates: &GateRegistry) -> Option<usize> {
+    gates
+        .get(name)
+        .map(|gate| gate.size())
+        .filter(|&size| size > 1)
+}
+
+// Split a part of a multi-qubit gate such as "CNOT-2" or "SWAP-1#3" into its name, part and group
+// Returns None if the string is not a part of a multi-qubit gate
+pub fn split_gate_part<'a>(gate_string: &'a str, gates: &GateRegistry) -> Option<GatePart<'a>> {
+    let (gate, group) = match gate_string.split_once('#') {
+        Some((gate, group)) => (gate, Some(group.parse::<usize>().ok()?)),
+        None => (gate_string, None),
+    };
+
+    let (name, part) = gate.rsplit_once('-')?;
+    let part = part.parse::<usize>().ok()?;
+
+    if !(1..=multi_qubit_gate_size(name, gates)?).contains(&part) {
+        return None;
+    }
+
+    Some(GatePart { name, part, group })
+}
+
+// Group the parts of the multi-qubit gates in a column, in the order the groups first appear
+// Parts with an explicit group belong together, parts without one are matched by order,
+// so the second "CNOT-1" from the top belongs with the second "CNOT-2"
+// Every group has the gate name and, for every part, the rows it was found on
+pub fn group_gate_parts<'a>(
+    column: &[&'a str],
+    gates: &GateRegistry,
+) -> Vec<(&'a str, Vec<Vec<usize>>)> {
+    // Groups are keyed by name, whether the group is explicit and the group number or occurrence
+    let mut keys: Vec<(&str, bool, usize)> = Vec::new();
+    let mut groups: Vec<(&str, Vec<Vec<usize>>)> = Vec::new();
+    let mut occurrences: Vec<((&str, usize), usize)> = Vec::new();
+
+    for (row, gate_string) in column.iter().enumerate() {
+        let Some(gate_part) = split_gate_part(gate_string, gates) else {
+            continue;
+        };
+
+        let key = match gate_part.group {
+            Some(group) => (gate_part.name, true, group),
+            None => {
+                let part_key = (gate_part.name, gate_part.part);
+                let occurrence = match occurrences.iter_mut().find(|(key, _)| *key == part_key) {
+                    Some((_, count)) => {
+                        *count += 1;
+                        *count - 1
+                    }
+                    None => {
+                        occurrences.push((part_key, 1));
+                        0
+                    }
+                };
+                (gate_part.name, false, occurrence)
+            }
+        };
+
+        let index = match keys.iter().position(|existing| *existing == key) {
+            Some(index) => index,
+            None => {
+                keys.push(key);
+                let size = multi_qubit_gate_size(gate_part.name, gates).unwrap();
+                groups.push((gate_part.name, vec![Vec::new(); size]));
+                keys.len() - 1
+            }
+        };
+
+        groups[index].1[gate_part.part - 1].push(row);
+    }
+
+    groups
+}
+
+// Split a classically controlled gate such as "X?c0" into the gate and the classical bit it is controlled by
+// Returns None if the string is not a classically controlled gate
+pub fn split_classical_condition(gate_string: &str) -> Option<(&str, usize)> {
+    let (gate, bit) = gate_string.split_once("?c")?;
+    let bit = bit.parse::<usize>().ok()?;
+
+    Some((gate, bit))
+}
+
+// Split a parameterised gate such as "RX(pi/4)" into its name and the values of its parameters
+// Returns None if the string has no parameter list or a parameter is not a valid expression
+pub fn split_parameters(gate_string: &str) -> Option<(&str, Vec<f64>)> {
+    let (name, arguments) = gate_string.split_once('(')?;
+    let arguments = arguments.strip_suffix(')')?;
+
+    let parameters = split_arguments(arguments)
+        .into_iter()
+        .map(parse_expression)
+        .collect::<Option<Vec<f64>>>()?;
+
+    Some((name.trim(), parameters))
+}
+
+// Split the arguments of a parameterised gate on the commas that are not inside parentheses
+fn split_arguments(arguments: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, c) in arguments.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&arguments[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
     }
+    parts.push(&arguments[start..]);
+
+    parts
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::simulation::quantum_state::QuantumState;
-    use ndarray::Array2;
+    use ndarray::arr2;
+
+    fn build(grid: Vec<Vec<&str>>) -> Vec<QuantumGate> {
+        expand_circuit(&parse_circuit(&grid, &GateRegistry::default()).unwrap()).collect()
+    }
 
     #[test]
     fn x_gate_circuit_test() {
         let q0 = vec!["X"];
         let grid = vec![q0];
 
-        let circuit = build_circuit_from_data(grid);
+        let circuit = build(grid);
 
         let state = QuantumState::new(&[0]).apply_gate(circuit[0].clone());
 
@@ -74,7 +474,7 @@ mod tests {
         let q0 = vec!["X", "H"];
         let grid = vec![q0];
 
-        let circuit = build_circuit_from_data(grid);
+        let circuit = build(grid);
 
         let state = QuantumState::new(&[0])
             .apply_gate(circuit[0].clone())
@@ -95,7 +495,7 @@ mod tests {
 
         let grid = vec![q0, q1];
 
-        let circuit = build_circuit_from_data(grid);
+        let circuit = build(grid);
 
         let state = QuantumState::new(&[0, 0])
             .apply_gate(circuit[0].clone())
@@ -113,27 +513,13 @@ mod tests {
 
     #[test]
     fn ghz_state_circuit_test() {
-        let mut q0 = Vec::new();
-        q0.push("H");
-        q0.push("CNOT-1");
-        q0.push("I");
-
-        let mut q1 = Vec::new();
-        q1.push("I");
-        q1.push("CNOT-2");
-        q1.push("CNOT-1");
+        let q0 = vec!["H", "CNOT-1", "I"];
+        let q1 = vec!["I", "CNOT-2", "CNOT-1"];
+        let q2 = vec!["I", "I", "CNOT-2"];
 
-        let mut q2 = Vec::new();
-        q2.push("I");
-        q2.push("I");
-        q2.push("CNOT-2");
+        let grid = vec![q0, q1, q2];
 
-        let mut grid = Vec::new();
-        grid.push(q0);
-        grid.push(q1);
-        grid.push(q2);
-
-        let circuit = build_circuit_from_data(grid);
+        let circuit = build(grid);
 
         let expected_result: Array2<Complex<f64>> = arr2(&[
             [Complex::new(1.0_f64 / 2.0_f64.sqrt(), 0.0_f64)],
@@ -154,4 +540,197 @@ mod tests {
 
         assert_eq!(state.col, expected_result);
     }
+
+    #[test]
+    fn operations_only_hold_touched_qubits_test() {
+        let q0 = vec!["H", "CNOT-1"];
+        let q1 = vec!["I", "CNOT-2"];
+        let q2 = vec!["X", "I"];
+
+        let circuit = parse_circuit(&[q0, q1, q2], &GateRegistry::default()).unwrap();
+
+        let qubits: Vec<Vec<Vec<usize>>> = circuit
+            .moments
+            .iter()
+            .map(|moment| moment.operations.iter().map(Operation::qubits).collect())
+            .collect();
+
+        assert_eq!(qubits, vec![vec![vec![0], vec![2]], vec![vec![0, 1]]]);
+    }
+
+    #[test]
+    fn measurement_operation_test() {
+        let q0 = vec!["H", "M"];
+        let q1 = vec!["M", "I"];
+
+        let circuit = parse_circuit(&[q0, q1], &GateRegistry::default()).unwrap();
+
+        assert_eq!(
+            circuit.moments[0].operations[1],
+            Operation::new(Gate::Measure, vec![1], vec![])
+        );
+        assert_eq!(circuit.moments[1].operations.len(), 1);
+        assert_eq!(
+            circuit.moments[1].operations[0],
+            Operation::new(Gate::Measure, vec![0], vec![])
+        );
+    }
+
+    #[test]
+    fn classically_controlled_operation_test() {
+        let q0 = vec!["M", "I"];
+        let q1 = vec!["I", "X?c0"];
+
+        let circuit = parse_circuit(&[q0, q1], &GateRegistry::default()).unwrap();
+
+        let operation = &circuit.moments[1].operations[0];
+        assert_eq!(operation.condition, Some(0));
+        assert_eq!(operation.targets, vec![1]);
+        assert_eq!(operation.matrix().matrix, QuantumGate::x_gate().matrix);
+    }
+
+    #[test]
+    fn parameterised_gate_test() {
+        let (gate, params) = parse_single_qubit_gate("RX(pi/2)", &GateRegistry::default()).unwrap();
+        assert_eq!(
+            gate.matrix(&params).matrix,
+            QuantumGate::rx(std::f64::consts::PI / 2.0).matrix
+        );
+
+        let (gate, params) =
+            parse_single_qubit_gate("U3(pi/2, 0, (1+1)*pi/2)", &GateRegistry::default()).unwrap();
+        assert_eq!(
+            gate.matrix(&params).matrix,
+            QuantumGate::u3(std::f64::consts::PI / 2.0, 0.0, std::f64::consts::PI).matrix
+        );
+
+        assert!(parse_single_qubit_gate("P(0.5)", &GateRegistry::default()).is_some());
+        assert!(parse_single_qubit_gate("RX(pi, 0)", &GateRegistry::default()).is_none());
+        assert!(parse_single_qubit_gate("U3(pi)", &GateRegistry::default()).is_none());
+        assert!(parse_single_qubit_gate("RX(theta)", &GateRegistry::default()).is_none());
+        assert!(parse_single_qubit_gate("RW(pi)", &GateRegistry::default()).is_none());
+        assert!(parse_single_qubit_gate("RX(pi", &GateRegistry::default()).is_none());
+        assert!(parse_single_qubit_gate("H()", &GateRegistry::default()).is_none());
+    }
+
+    #[test]
+    fn parameterised_gate_circuit_test() {
+        let q0 = vec!["RY(pi)", "RZ(pi/2)?c0"];
+        let circuit = parse_circuit(&[q0], &GateRegistry::default()).unwrap();
+
+        assert_eq!(
+            circuit.moments[0].operations[0],
+            Operation::new(Gate::RY, vec![0], vec![std::f64::consts::PI])
+        );
+        assert_eq!(circuit.moments[1].operations[0].condition, Some(0));
+    }
+
+    #[test]
+    fn cnot_with_control_below_target_test() {
+        // Control on qubit 2, target on qubit 0 with an unrelated qubit in between
+        let q0 = vec!["I", "CNOT-2"];
+        let q1 = vec!["I", "I"];
+        let q2 = vec!["X", "CNOT-1"];
+
+        let circuit = build(vec![q0, q1, q2]);
+
+        let state = QuantumState::new(&[0, 0, 0])
+            .apply_gate(circuit[0].clone())
+            .apply_gate(circuit[1].clone());
+
+        assert_eq!(state.col, QuantumState::new(&[1, 0, 1]).col);
+    }
+
+    #[test]
+    fn toffoli_with_parts_in_any_order_test() {
+        let q0 = vec!["CCNOT-3"];
+        let q1 = vec!["CCNOT-1"];
+        let q2 = vec!["I"];
+        let q3 = vec!["CCNOT-2"];
+
+        let circuit = parse_circuit(&[q0, q1, q2, q3], &GateRegistry::default()).unwrap();
+
+        assert_eq!(
+            circuit.moments[0].operations,
+            vec![Operation::new(Gate::Ccnot, vec![1, 3, 0], vec![])]
+        );
+    }
+
+    #[test]
+    fn controlled_gate_test() {
+        // Controlled H with the control below the target and an anti-control above it
+        let q0 = vec!["O"];
+        let q1 = vec!["H"];
+        let q2 = vec!["C"];
+
+        let circuit = parse_circuit(&[q0, q1, q2], &GateRegistry::default()).unwrap();
+
+        assert_eq!(circuit.moments[0].operations.len(), 1);
+        let operation = &circuit.moments[0].operations[0];
+        assert_eq!(operation.qubits(), vec![0, 2, 1]);
+        assert_eq!(
+            operation.matrix().matrix,
+            QuantumGate::h_gate().controlled_on(&[false, true]).matrix
+        );
+    }
+
+    #[test]
+    fn controlled_gate_matches_cnot_test() {
+        let controlled = build(vec![vec!["X", "C"], vec!["I", "X"]]);
+        let cnot = build(vec![vec!["X", "CNOT-1"], vec!["I", "CNOT-2"]]);
+
+        assert_eq!(controlled[1].matrix, cnot[1].matrix);
+    }
+
+    #[test]
+    fn group_gate_parts_test() {
+        // Two interleaved CNOTs with explicit groups and a SWAP matched by order
+        let column = vec![
+            "CNOT-1#1", "CNOT-1#2", "SWAP-1", "CNOT-2#2", "SWAP-2", "CNOT-2#1",
+        ];
+
+        assert_eq!(
+            group_gate_parts(&column, &GateRegistry::default()),
+            vec![
+                ("CNOT", vec![vec![0], vec![5]]),
+                ("CNOT", vec![vec![1], vec![3]]),
+                ("SWAP", vec![vec![2], vec![4]]),
+            ]
+        );
+
+        // Without groups the n-th control belongs to the n-th target
+        let column = vec!["CNOT-2", "CNOT-1", "CNOT-1", "CNOT-2"];
+        assert_eq!(
+            group_gate_parts(&column, &GateRegistry::default()),
+            vec![
+                ("CNOT", vec![vec![1], vec![0]]),
+                ("CNOT", vec![vec![2], vec![3]])
+            ]
+        );
+    }
+
+    #[test]
+    fn split_gate_part_test() {
+        assert_eq!(
+            split_gate_part("CCNOT-3#2", &GateRegistry::default()),
+            Some(GatePart {
+                name: "CCNOT",
+                part: 3,
+                group: Some(2)
+            })
+        );
+        assert_eq!(split_gate_part("CNOT-3", &GateRegistry::default()), None);
+        assert_eq!(split_gate_part("CNOT-0", &GateRegistry::default()), None);
+        assert_eq!(split_gate_part("CNOT-1#a", &GateRegistry::default()), None);
+        assert_eq!(split_gate_part("H", &GateRegistry::default()), None);
+        assert_eq!(split_gate_part("CNOT-1?c0", &GateRegistry::default()), None);
+    }
+
+    #[test]
+    fn split_classical_condition_test() {
+        assert_eq!(split_classical_condition("Z?c12"), Some(("Z", 12)));
+        assert_eq!(split_classical_condition("Z"), None);
+        assert_eq!(split_classical_condition("Z?c"), None);
+        assert_eq!(split_classical_condition("Z?q1"), None);
+    }
 }
@@ -1,144 +1,186 @@
 // All rows must be the same length
 // All elements must be a valid gate
-// If a multi-qubit gate is present, the other parts of that gate must be in the same step
-// The number of rows (qubits) in the circuit must be between 1 and 6, inclusive
+// If a multi-qubit gate is present, the other parts of that gate must be in the same step, on any rows
+// The number of rows (qubits) in the circuit must be between 1 and the configured maximum, inclusive
 // Atleast one column must be present
+// A column with control markers must have exactly one gate (single-qubit gate or multi-qubit group) to control
+// A classically controlled gate must read a classical bit that exists and was written by a measurement in an earlier step
+// The problems with the rows and cells are all collected and returned together as InvalidCircuit
 
+use crate::simulation::circuit::Circuit;
+use crate::simulation::circuit_parser::parse_grid;
+use crate::simulation::gate_registry::GateRegistry;
 use serde::Serialize;
 
-#[derive(Debug, PartialEq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum QuantumCircuitError {
     TooManyQubits,
     TooFewQubits,
     InvalidGate,
     InvalidRowLength,
     MultiQubitGateMismatch,
+    InvalidClassicalBit,
+    ClassicalBitReadBeforeWrite,
+    MissingControlTarget,
+    MultipleControlTargets,
+    UncontrollableGate,
+    InvalidNoiseModel,
+    InvalidShotCount,
+    // The circuit has a measurement or classically controlled gate, so it has no unitary
+    NonUnitaryOperation,
+    // Two circuits that are compared have a different number of qubits
+    QubitCountMismatch,
+    // An observable is not a sum of Pauli strings with one letter per qubit
+    InvalidObservable,
+    // The stabilizer simulation was requested for a circuit with gates outside of the Clifford group
+    NonCliffordCircuit,
+    // The bond dimension of the matrix product state is 0 or above the server limit, or the threshold is not in [0, 1)
+    InvalidMpsOptions,
+    // A custom gate has an invalid name or a matrix that is not a unitary of a supported size, with the reason
+    InvalidCustomGate(String),
+    // Every problem with the cells of the grid, ordered by step and row
+    InvalidCircuit(Vec<CircuitProblem>),
+    // The OpenQASM source could not be parsed, with the line and reason
+    InvalidQasm(String),
+    // The OpenQASM source or grid uses a feature the other format can not represent
+    UnsupportedQasm(String),
+    // The native gate set of a transpilation can not express every gate, or a gate can not be decomposed, with the reason
+    UnsupportedBasis(String),
+    // The coupling map has no qubits or more than the server accepts, connects a qubit to itself,
+    // is not connected or has fewer qubits than the circuit, with the reason
+    InvalidCouplingMap(String),
+    // A gate of the circuit acts on more than two qubits or a classical bit is overwritten by routing, with the reason
+    UnroutableCircuit(String),
+    // An operation has too many control markers for the matrix the mps backend applies, see MAX_MATRIX_QUBITS
+    TooManyControls,
+}
+
+// A problem with one cell of the grid, or with a whole row if there is no column
+// The token is the text of the cell, so the client can point at it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CircuitProblem {
+    pub error: QuantumCircuitError,
+    pub row: usize,
+    pub column: Option<usize>,
+    pub token: Option<String>,
+    pub message: String,
 }
 
 // Ensures that all rows are the same length and that there is at least one row
-// and that the number of rows is between 1 and 6
-pub fn validate_grid_input(grid: &Vec<Vec<&str>>) -> Result<(), QuantumCircuitError> {
+// and that the number of rows is between 1 and max_qubits
+// Only the built-in gates are accepted
+pub fn validate_grid_input(
+    grid: &Vec<Vec<&str>>,
+    max_qubits: usize,
+) -> Result<(), QuantumCircuitError> {
+    validate_circuit(grid, max_qubits, &GateRegistry::default()).map(|_| ())
+}
+
+// Validate the grid and parse it into a circuit, the parser checks the gates and columns
+pub fn validate_circuit(
+    grid: &[Vec<&str>],
+    max_qubits: usize,
+    gates: &GateRegistry,
+) -> Result<Circuit, QuantumCircuitError> {
     if grid.is_empty() {
         return Err(QuantumCircuitError::TooFewQubits);
     }
 
-    if grid.len() > 6 {
+    if grid.len() > max_qubits {
         return Err(QuantumCircuitError::TooManyQubits);
     }
 
-    let row_length = grid[0].len();
-    for row in grid {
-        if row.len() != row_length {
-            return Err(QuantumCircuitError::InvalidRowLength);
-        }
-    }
-
-    // Validate steps (columns)
-    for i in 0..row_length {
-        let mut col: Vec<&str> = Vec::new();
-        for row in grid {
-            col.push(row[i]);
-        }
-
-        match validate_col(&col) {
-            Ok(_) => (),
-            Err(err) => return Err(err),
-        }
-    }
-
-    Ok(())
-}
-
-// Ensure that a gate is valid
-fn validate_gate(gate: &str) -> bool {
-    matches!(
-        gate,
-        "I" | "H"
-            | "X"
-            | "Y"
-            | "Z"
-            | "T"
-            | "S"
-            | "CZ"
-            | "SWAP-1"
-            | "CCNOT-1"
-            | "CNOT-1"
-            | "CNOT-2"
-            | "CCNOT-2"
-            | "CCNOT-3"
-            | "SWAP-2"
-    )
-}
-
-// If a multi-qubit gate, return the other parts of the gate which must be in the same step
-fn is_multi_qubit_gate(gate: &str) -> &str {
-    match gate {
-        "CNOT-1" => "CNOT-2",
-        "CCNOT-1" => "CCNOT-2",
-        "CCNOT-2" => "CCNOT-3",
-        "SWAP-1" => "SWAP-2",
-        _ => "",
-    }
-}
-
-fn is_sub_gate(gate: &str) -> bool {
-    matches!(gate, "CNOT-2" | "CCNOT-2" | "CCNOT-3" | "SWAP-2")
-}
+    let (circuit, mut problems) = parse_grid(grid, gates);
+    problems.extend(classical_condition_problems(grid, &circuit));
+    problems.sort_by_key(|problem| (problem.column, problem.row));
 
-fn ensure_multi_qubit_gate(gate: &str, prev_gate: &str) -> Result<(), QuantumCircuitError> {
-    if is_sub_gate(gate) && prev_gate.is_empty() && is_multi_qubit_gate(prev_gate) != gate {
-        return Err(QuantumCircuitError::MultiQubitGateMismatch);
+    match problems.is_empty() {
+        true => Ok(circuit),
+        false => Err(QuantumCircuitError::InvalidCircuit(problems)),
     }
-
-    Ok(())
 }
 
-// Validate a row of gates
-// Go through each gate and check if it is valid
-// If a multi-qubit gate is present, check if the other parts of the gate are in the same step
-// by adding them to a list and removing them if they are found
-fn validate_col(row: &Vec<&str>) -> Result<(), QuantumCircuitError> {
-    let mut next_multi_qubit_gate = "";
-
-    for gate in row {
-        if !validate_gate(gate) {
-            return Err(QuantumCircuitError::InvalidGate);
-        }
-
-        if !next_multi_qubit_gate.is_empty() && gate != &next_multi_qubit_gate {
-            println!(
-                "gate: {}, next_multi_qubit_gate: {}",
-                gate, next_multi_qubit_gate
-            );
-            return Err(QuantumCircuitError::MultiQubitGateMismatch);
+// Find the classically controlled gates that read a classical bit that does not exist
+// or has not been written by a measurement in an earlier step
+fn classical_condition_problems(grid: &[Vec<&str>], circuit: &Circuit) -> Vec<CircuitProblem> {
+    let mut measured = vec![false; circuit.qubits];
+    let mut problems: Vec<CircuitProblem> = Vec::new();
+
+    for (step, moment) in circuit.moments.iter().enumerate() {
+        for operation in &moment.operations {
+            let Some(bit) = operation.condition else {
+                continue;
+            };
+
+            let (error, message) = if bit >= circuit.qubits {
+                (
+                    QuantumCircuitError::InvalidClassicalBit,
+                    format!(
+                        "classical bit {} does not exist, there is one per qubit",
+                        bit
+                    ),
+                )
+            } else if !measured[bit] {
+                (
+                    QuantumCircuitError::ClassicalBitReadBeforeWrite,
+                    format!(
+                        "classical bit {} is read before qubit {} is measured in an earlier step",
+                        bit, bit
+                    ),
+                )
+            } else {
+                continue;
+            };
+
+            let row = operation.targets[0];
+            problems.push(CircuitProblem {
+                error,
+                row,
+                column: Some(step),
+                token: Some(grid[row][step].to_string()),
+                message,
+            });
         }
 
-        if ensure_multi_qubit_gate(gate, next_multi_qubit_gate).is_err() {
-            return Err(QuantumCircuitError::MultiQubitGateMismatch);
+        // Measurements are marked after the whole step so a bit can not be read in the step it is written
+        for operation in moment
+            .operations
+            .iter()
+            .filter(|operation| operation.is_measurement())
+        {
+            measured[operation.targets[0]] = true;
         }
-
-        next_multi_qubit_gate = is_multi_qubit_gate(gate);
     }
 
-    if next_multi_qubit_gate.is_empty() {
-        Ok(())
-    } else {
-        Err(QuantumCircuitError::MultiQubitGateMismatch)
-    }
+    problems
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::simulation::circuit_parser::{parse_circuit, split_gate_part};
+
+    // The kind of the first problem, which is enough for most tests
+    fn validate(grid: &Vec<Vec<&str>>, max_qubits: usize) -> Result<(), QuantumCircuitError> {
+        validate_grid_input(grid, max_qubits).map_err(|err| match err {
+            QuantumCircuitError::InvalidCircuit(problems) => problems[0].error.clone(),
+            err => err,
+        })
+    }
+
+    // A single cell is valid if it parses on its own, parts of multi-qubit gates are checked separately
+    fn validate_gate(gate: &str) -> bool {
+        parse_circuit(&[vec![gate]], &GateRegistry::default()).is_ok()
+    }
 
     #[test]
     fn test_validate_grid_input() {
         let valid_grid = vec![vec!["I", "H"], vec!["X", "Y"]];
         let invalid_grid = vec![vec!["I", "H"], vec!["X", "Y", "Z"]];
 
-        assert_eq!(validate_grid_input(&valid_grid), Ok(()));
+        assert_eq!(validate(&valid_grid, 6), Ok(()));
         assert_eq!(
-            validate_grid_input(&invalid_grid),
+            validate(&invalid_grid, 6),
             Err(QuantumCircuitError::InvalidRowLength)
         );
     }
@@ -148,17 +190,67 @@ mod tests {
         let valid_gate = "I";
         let invalid_gate = "A";
 
-        assert_eq!(validate_gate(valid_gate), true);
-        assert_eq!(validate_gate(invalid_gate), false);
+        assert!(validate_gate(valid_gate));
+        assert!(!validate_gate(invalid_gate));
+    }
+
+    #[test]
+    fn test_validate_parameterised_gate() {
+        assert!(validate_gate("RX(pi/4)"));
+        assert!(validate_gate("U3(pi/2, 0, pi)"));
+        assert!(validate_gate("RZ(-pi)?c0"));
+        assert!(!validate_gate("RX(pi/)"));
+        assert!(!validate_gate("RX"));
+        assert!(!validate_gate("U3(pi)"));
     }
 
     #[test]
     fn test_is_multi_qubit_gate() {
         let multi_qubit_gate: &str = "CNOT-1";
+        let grouped_multi_qubit_gate: &str = "CCNOT-3#1";
         let non_multi_qubit_gate: &str = "I";
 
-        assert_eq!(is_multi_qubit_gate(multi_qubit_gate), "CNOT-2");
-        assert_eq!(is_multi_qubit_gate(non_multi_qubit_gate), "");
+        assert!(split_gate_part(multi_qubit_gate, &GateRegistry::default()).is_some());
+        assert!(split_gate_part(grouped_multi_qubit_gate, &GateRegistry::default()).is_some());
+        assert!(split_gate_part(non_multi_qubit_gate, &GateRegistry::default()).is_none());
+    }
+
+    #[test]
+    fn non_adjacent_multi_qubit_gates() {
+        // CNOT with the control below the target and a Toffoli spread over the column
+        let grid = vec![
+            vec!["CNOT-2", "CCNOT-3"],
+            vec!["H", "I"],
+            vec!["CNOT-1", "CCNOT-1"],
+            vec!["I", "CCNOT-2"],
+        ];
+        assert_eq!(validate(&grid, 6), Ok(()));
+    }
+
+    #[test]
+    fn grouped_multi_qubit_gates() {
+        let grid = vec![
+            vec!["CNOT-1#1"],
+            vec!["CNOT-1#2"],
+            vec!["CNOT-2#2"],
+            vec!["CNOT-2#1"],
+        ];
+        assert_eq!(validate(&grid, 6), Ok(()));
+
+        let grid = vec![vec!["CNOT-1#1"], vec!["CNOT-2#2"]];
+        assert_eq!(
+            validate(&grid, 6),
+            Err(QuantumCircuitError::MultiQubitGateMismatch)
+        );
+    }
+
+    #[test]
+    fn duplicate_part_in_group() {
+        let grid = vec![vec!["SWAP-1#1"], vec!["SWAP-1#1"], vec!["SWAP-2#1"]];
+        assert_eq!(
+            validate(&grid, 6),
+            Err(QuantumCircuitError::MultiQubitGateMismatch)
+        );
     }
 
     #[test]
@@ -167,7 +259,7 @@ mod tests {
             vec!["CNOT-1", "I", "CNOT-2"], // CNOT-1 and CNOT-2 separated by an I gate
         ];
         assert_eq!(
-            validate_grid_input(&separated_multi_qubit_gate_grid),
+            validate(&separated_multi_qubit_gate_grid, 6),
             Err(QuantumCircuitError::MultiQubitGateMismatch)
         );
     }
@@ -178,7 +270,7 @@ mod tests {
             vec!["CNOT-2", "CNOT-1"], // CNOT-2 and CNOT-1 in alone in a step
         ];
         assert_eq!(
-            validate_grid_input(&grid),
+            validate(&grid, 6),
             Err(QuantumCircuitError::MultiQubitGateMismatch)
         );
     }
@@ -194,10 +286,14 @@ mod tests {
             vec!["Y", "Z"],
             vec!["I", "S"],
         ];
-        assert_eq!(
-            validate_grid_input(&grid),
-            Err(QuantumCircuitError::TooManyQubits)
-        );
+        assert_eq!(validate(&grid, 6), Err(QuantumCircuitError::TooManyQubits));
+    }
+
+    #[test]
+    fn test_qubit_limit_is_configurable() {
+        let grid = vec![vec!["H"]; 20];
+        assert_eq!(validate(&grid, 24), Ok(()));
+        assert_eq!(validate(&grid, 19), Err(QuantumCircuitError::TooManyQubits));
     }
 
     #[test]
@@ -206,7 +302,7 @@ mod tests {
             vec!["CNOT-1"], // Missing CNOT-2
         ];
         assert_eq!(
-            validate_grid_input(&grid),
+            validate(&grid, 6),
             Err(QuantumCircuitError::MultiQubitGateMismatch)
         );
     }
@@ -215,7 +311,7 @@ mod tests {
     fn test_valid_circuit_inconsistent_row_lengths() {
         let grid = vec![vec!["I", "H", "X"], vec!["X", "Y"]];
         assert_eq!(
-            validate_grid_input(&grid),
+            validate(&grid, 6),
             Err(QuantumCircuitError::InvalidRowLength)
         );
     }
@@ -223,21 +319,173 @@ mod tests {
     #[test]
     fn valid_circuit() {
         let grid = vec![vec!["H", "CNOT-1"], vec!["I", "CNOT-2"]];
-        assert_eq!(validate_grid_input(&grid), Ok(()));
+        assert_eq!(validate(&grid, 6), Ok(()));
     }
 
     #[test]
     fn valid_circuit_with_single_gate() {
         let grid = vec![vec!["H"]];
-        assert_eq!(validate_grid_input(&grid), Ok(()));
+        assert_eq!(validate(&grid, 6), Ok(()));
     }
 
     #[test]
     fn ending_with_multi_qubit_gate() {
         let grid = vec![vec!["H", "CNOT-2"], vec!["I", "CNOT-2"]];
         assert_eq!(
-            validate_grid_input(&grid),
+            validate(&grid, 6),
             Err(QuantumCircuitError::MultiQubitGateMismatch)
         );
     }
+
+    #[test]
+    fn classically_controlled_gate_after_measurement() {
+        let grid = vec![vec!["H", "M", "I"], vec!["I", "I", "X?c0"]];
+        assert_eq!(validate(&grid, 6), Ok(()));
+    }
+
+    #[test]
+    fn classically_controlled_gate_before_measurement() {
+        let grid = vec![vec!["H", "I", "M"], vec!["I", "X?c0", "I"]];
+        assert_eq!(
+            validate(&grid, 6),
+            Err(QuantumCircuitError::ClassicalBitReadBeforeWrite)
+        );
+    }
+
+    #[test]
+    fn classically_controlled_gate_in_same_step_as_measurement() {
+        let grid = vec![vec!["H", "M"], vec!["I", "X?c0"]];
+        assert_eq!(
+            validate(&grid, 6),
+            Err(QuantumCircuitError::ClassicalBitReadBeforeWrite)
+        );
+    }
+
+    #[test]
+    fn classically_controlled_gate_with_invalid_bit() {
+        let grid = vec![vec!["M", "I"], vec!["I", "X?c2"]];
+        assert_eq!(
+            validate(&grid, 6),
+            Err(QuantumCircuitError::InvalidClassicalBit)
+        );
+    }
+
+    #[test]
+    fn classically_controlled_multi_qubit_gate() {
+        let grid = vec![vec!["M", "CNOT-1?c0"], vec!["I", "CNOT-2"]];
+        assert_eq!(validate(&grid, 6), Err(QuantumCircuitError::InvalidGate));
+    }
+
+    #[test]
+    fn controlled_gates() {
+        // 4-controlled Z with one anti-control, and a controlled SWAP
+        let grid = vec![
+            vec!["C", "SWAP-1"],
+            vec!["O", "C"],
+            vec!["Z", "SWAP-2"],
+            vec!["C", "I"],
+            vec!["C", "I"],
+        ];
+        assert_eq!(validate(&grid, 6), Ok(()));
+    }
+
+    #[test]
+    fn controls_without_target() {
+        let grid = vec![vec!["C"], vec!["O"], vec!["I"]];
+        assert_eq!(
+            validate(&grid, 6),
+            Err(QuantumCircuitError::MissingControlTarget)
+        );
+    }
+
+    #[test]
+    fn controls_with_multiple_targets() {
+        let grid = vec![vec!["C"], vec!["H"], vec!["X"]];
+        assert_eq!(
+            validate(&grid, 6),
+            Err(QuantumCircuitError::MultipleControlTargets)
+        );
+
+        let grid = vec![vec!["C"], vec!["CNOT-1"], vec!["CNOT-2"], vec!["X"]];
+        assert_eq!(
+            validate(&grid, 6),
+            Err(QuantumCircuitError::MultipleControlTargets)
+        );
+    }
+
+    #[test]
+    fn controlled_measurement() {
+        let grid = vec![vec!["C"], vec!["M"]];
+        assert_eq!(
+            validate(&grid, 6),
+            Err(QuantumCircuitError::UncontrollableGate)
+        );
+    }
+
+    #[test]
+    fn all_problems_are_reported() {
+        let grid = vec![
+            vec!["H", "A", "CNOT-1"],
+            vec!["I", "I", "X?c3"],
+            vec!["I", "RX(pi/)", "I"],
+        ];
+
+        let Err(QuantumCircuitError::InvalidCircuit(problems)) = validate_grid_input(&grid, 6)
+        else {
+            panic!("expected an invalid circuit");
+        };
+
+        let locations: Vec<(QuantumCircuitError, usize, Option<usize>, Option<&str>)> = problems
+            .iter()
+            .map(|problem| {
+                (
+                    problem.error.clone(),
+                    problem.row,
+                    problem.column,
+                    problem.token.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            locations,
+            vec![
+                (QuantumCircuitError::InvalidGate, 0, Some(1), Some("A")),
+                (
+                    QuantumCircuitError::InvalidGate,
+                    2,
+                    Some(1),
+                    Some("RX(pi/)")
+                ),
+                (
+                    QuantumCircuitError::MultiQubitGateMismatch,
+                    0,
+                    Some(2),
+                    Some("CNOT-1")
+                ),
+                (
+                    QuantumCircuitError::InvalidClassicalBit,
+                    1,
+                    Some(2),
+                    Some("X?c3")
+                ),
+            ]
+        );
+        assert_eq!(problems[0].message, "\"A\" is not a valid gate");
+    }
+
+    #[test]
+    fn uneven_rows_are_reported_per_row() {
+        let grid = vec![vec!["H", "X"], vec!["X"], vec!["I", "I", "I"]];
+
+        let Err(QuantumCircuitError::InvalidCircuit(problems)) = validate_grid_input(&grid, 6)
+        else {
+            panic!("expected an invalid circuit");
+        };
+
+        let rows: Vec<usize> = problems.iter().map(|problem| problem.row).collect();
+        assert_eq!(rows, vec![1, 2]);
+        assert!(problems.iter().all(|problem| problem.column.is_none()
+            && problem.error == QuantumCircuitError::InvalidRowLength));
+        assert_eq!(problems[0].message, "row 1 has 1 steps, but row 0 has 2");
+    }
 }
@@ -0,0 +1,300 @@
+use crate::simulation::circuit::Control;
+use crate::simulation::quantum_gate::QuantumGate;
+use crate::simulation::quantum_state::{basis_offsets, QuantumState};
+
+use ndarray::Array2;
+use num::{Complex, ToPrimitive};
+use rand::Rng;
+
+// DensityMatrix struct
+// Matrix is the 2^n x 2^n density matrix rho, which can also represent mixed states
+#[derive(Debug, Clone)]
+pub struct DensityMatrix {
+    pub matrix: Array2<Complex<f64>>,
+}
+
+impl DensityMatrix {
+    // Create a DensityMatrix for the pure state given by a list of bits, see QuantumState::new
+    pub fn new(bits: &[usize]) -> DensityMatrix {
+        DensityMatrix::from_state(&QuantumState::new(bits))
+    }
+
+    // Create the DensityMatrix |psi><psi| of a pure state
+    pub fn from_state(state: &QuantumState) -> DensityMatrix {
+        let matrix = state.col.dot(&state.col.t().mapv(|value| value.conj()));
+
+        DensityMatrix { matrix }
+    }
+
+    // Calculate the number of qubits in the DensityMatrix
+    pub fn size(&self) -> usize {
+        self.matrix.nrows().ilog2().to_usize().unwrap()
+    }
+
+    // Apply a QuantumGate to the given qubits, rho -> U rho U^dagger
+    pub fn apply_gate_to_qubits(self, gate: &QuantumGate, qubits: &[usize]) -> DensityMatrix {
+        self.apply_controlled_gate_to_qubits(gate, &[], qubits)
+    }
+
+    // Apply a QuantumGate with control markers, see QuantumState::apply_controlled_gate_to_qubits
+    pub fn apply_controlled_gate_to_qubits(
+        self,
+        gate: &QuantumGate,
+        controls: &[Control],
+        qubits: &[usize],
+    ) -> DensityMatrix {
+        DensityMatrix {
+            matrix: self.conjugate_with(gate, controls, qubits),
+        }
+    }
+
+    // Apply a noise channel given by its Kraus operators to a qubit, rho -> sum of K rho K^dagger
+    pub fn apply_channel(self, kraus_operators: &[QuantumGate], qubit: usize) -> DensityMatrix {
+        let dim = self.matrix.nrows();
+        let matrix = kraus_operators.iter().fold(
+            Array2::<Complex<f64>>::zeros((dim, dim)),
+            |acc, operator| acc + self.conjugate_with(operator, &[], &[qubit]),
+        );
+
+        DensityMatrix { matrix }
+    }
+
+    // Calculate the probability that measuring a qubit gives 1
+    pub fn probability_of_one(&self, qubit: usize) -> f64 {
+        let mask = 1_usize << (self.size() - qubit - 1);
+
+        self.matrix
+            .diag()
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| index & mask != 0)
+            .map(|(_, value)| value.re)
+            .sum()
+    }
+
+    // Measure a qubit in the computational basis, the outcome is sampled from the diagonal of the matrix
+    // The state is projected onto the outcome and renormalised, the outcome (0 or 1) is returned with it
+    pub fn measure<R: Rng + ?Sized>(mut self, qubit: usize, rng: &mut R) -> (DensityMatrix, u8) {
+        let probability_of_one = self.probability_of_one(qubit);
+        let outcome: u8 = if rng.gen::<f64>() < probability_of_one {
+            1
+        } else {
+            0
+        };
+
+        let probability = if outcome == 1 {
+            probability_of_one
+        } else {
+            1.0 - probability_of_one
+        };
+
+        let mask = 1_usize << (self.size() - qubit - 1);
+        for ((row, column), value) in self.matrix.indexed_iter_mut() {
+            if u8::from(row & mask != 0) == outcome && u8::from(column & mask != 0) == outcome {
+                *value /= probability;
+            } else {
+                *value = Complex::new(0.0, 0.0);
+            }
+        }
+
+        (self, outcome)
+    }
+
+    // Calculate the purity Tr(rho^2), 1 for a pure state and 1/2^n for the maximally mixed state
+    pub fn purity(&self) -> f64 {
+        // rho is Hermitian, so Tr(rho^2) is the sum of |rho_ij|^2
+        self.matrix.iter().map(|value| value.norm_sqr()).sum()
+    }
+
+    // Calculate the density matrix of the given qubits by tracing out all other qubits
+    // The first listed qubit is the most significant bit of the reduced density matrix
+    pub fn reduced_density_matrix(&self, qubits: &[usize]) -> DensityMatrix {
+        let dim = 1_usize << qubits.len();
+        let offsets = basis_offsets(qubits, self.size());
+        let mask = offsets[dim - 1];
+        let mut matrix = Array2::<Complex<f64>>::zeros((dim, dim));
+
+        // Sum the blocks on the diagonal of the other qubits, where they are in the same basis state
+        for base in 0..self.matrix.nrows() {
+            if base & mask != 0 {
+                continue;
+            }
+
+            for (row, &row_offset) in offsets.iter().enumerate() {
+                for (column, &column_offset) in offsets.iter().enumerate() {
+                    matrix[[row, column]] += self.matrix[[base | row_offset, base | column_offset]];
+                }
+            }
+        }
+
+        DensityMatrix { matrix }
+    }
+
+    // Calculate the Bloch vector (x, y, z) of a single-qubit density matrix, rho = (I + xX + yY + zZ) / 2
+    // The vector has length 1 for a pure state and is shorter for a mixed state
+    pub fn bloch_vector(&self) -> [f64; 3] {
+        let rho = &self.matrix;
+
+        [
+            2.0 * rho[[0, 1]].re,
+            2.0 * rho[[1, 0]].im,
+            rho[[0, 0]].re - rho[[1, 1]].re,
+        ]
+    }
+
+    // Calculate K rho K^dagger for an operator K on the given qubits
+    // rho is treated as a state vector of 2n qubits, where the first n qubits index the rows and the last n the columns,
+    // so K is applied to the row qubits and the complex conjugate of K to the column qubits
+    // The controls are projectors, so they are the same on both sides
+    fn conjugate_with(
+        &self,
+        operator: &QuantumGate,
+        controls: &[Control],
+        qubits: &[usize],
+    ) -> Array2<Complex<f64>> {
+        let no_of_qubits = self.size();
+        let dim = self.matrix.nrows();
+
+        let conjugate = QuantumGate {
+            matrix: operator.matrix.mapv(|value| value.conj()),
+            size: operator.size,
+        };
+        let column_qubits: Vec<usize> = qubits.iter().map(|qubit| qubit + no_of_qubits).collect();
+        let column_controls: Vec<Control> = controls
+            .iter()
+            .map(|control| Control {
+                qubit: control.qubit + no_of_qubits,
+                state: control.state,
+            })
+            .collect();
+
+        let vectorised = QuantumState {
+            col: self
+                .matrix
+                .as_standard_layout()
+                .into_owned()
+                .into_shape((dim * dim, 1))
+                .unwrap(),
+        };
+
+        vectorised
+            .apply_controlled_gate_to_qubits(operator, controls, qubits)
+            .apply_controlled_gate_to_qubits(&conjugate, &column_controls, &column_qubits)
+            .col
+            .into_shape((dim, dim))
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::noise::{ChannelKind, NoiseChannel};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn assert_matrix_close(actual: &Array2<Complex<f64>>, expected: &Array2<Complex<f64>>) {
+        for (a, b) in actual.iter().zip(expected.iter()) {
+            assert!((a - b).norm() < 1e-12, "{} != {}", actual, expected);
+        }
+    }
+
+    // Test that applying gates to a density matrix matches the pure state simulation
+    #[test]
+    fn test_gates_match_state_vector() {
+        let state = QuantumState::new(&[0, 1, 0])
+            .apply_gate_to_qubits(&QuantumGate::h_gate(), &[0])
+            .apply_gate_to_qubits(&QuantumGate::cnot_gate(), &[0, 2])
+            .apply_gate_to_qubits(&QuantumGate::rx(0.3), &[1]);
+
+        let density_matrix = DensityMatrix::new(&[0, 1, 0])
+            .apply_gate_to_qubits(&QuantumGate::h_gate(), &[0])
+            .apply_gate_to_qubits(&QuantumGate::cnot_gate(), &[0, 2])
+            .apply_gate_to_qubits(&QuantumGate::rx(0.3), &[1]);
+
+        assert_matrix_close(
+            &density_matrix.matrix,
+            &DensityMatrix::from_state(&state).matrix,
+        );
+        assert!((density_matrix.purity() - 1.0).abs() < 1e-12);
+    }
+
+    // Test that full depolarizing noise gives the maximally mixed state
+    #[test]
+    fn test_depolarizing_channel() {
+        let channel = NoiseChannel {
+            channel: ChannelKind::Depolarizing,
+            probability: 1.0,
+        };
+
+        let density_matrix = DensityMatrix::new(&[1]).apply_channel(&channel.kraus_operators(), 0);
+
+        assert_matrix_close(
+            &density_matrix.matrix,
+            &(Array2::<Complex<f64>>::eye(2) * Complex::new(0.5, 0.0)),
+        );
+        assert!((density_matrix.purity() - 0.5).abs() < 1e-12);
+    }
+
+    // Test that amplitude damping moves |1> towards |0>
+    #[test]
+    fn test_amplitude_damping_channel() {
+        let channel = NoiseChannel {
+            channel: ChannelKind::AmplitudeDamping,
+            probability: 0.25,
+        };
+
+        let density_matrix =
+            DensityMatrix::new(&[0, 1]).apply_channel(&channel.kraus_operators(), 1);
+
+        assert!((density_matrix.probability_of_one(1) - 0.75).abs() < 1e-12);
+        assert!((density_matrix.matrix[[0, 0]].re - 0.25).abs() < 1e-12);
+    }
+
+    // Test that measuring a mixed state projects it and keeps the trace at 1
+    #[test]
+    fn test_measure_collapses_state() {
+        let density_matrix = DensityMatrix::new(&[0, 0])
+            .apply_gate_to_qubits(&QuantumGate::h_gate(), &[0])
+            .apply_gate_to_qubits(&QuantumGate::cnot_gate(), &[0, 1]);
+
+        let (collapsed, outcome) = density_matrix.measure(0, &mut StdRng::seed_from_u64(1));
+        let expected_bits = [outcome as usize, outcome as usize];
+
+        assert_matrix_close(
+            &collapsed.matrix,
+            &DensityMatrix::new(&expected_bits).matrix,
+        );
+    }
+
+    // Test the Bloch vectors of |+>, |+i> and |1>, and that tracing out qubits matches the state vector
+    #[test]
+    fn test_reduced_density_matrix_and_bloch_vector() {
+        let state = QuantumState::new(&[0, 0, 1])
+            .apply_gate_to_qubits(&QuantumGate::h_gate(), &[0])
+            .apply_gate_to_qubits(&QuantumGate::h_gate(), &[1])
+            .apply_gate_to_qubits(&QuantumGate::s_gate(), &[1]);
+        let density_matrix = DensityMatrix::from_state(&state);
+
+        let expected = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, -1.0]];
+        for (qubit, expected) in expected.iter().enumerate() {
+            let reduced = density_matrix.reduced_density_matrix(&[qubit]);
+            assert_matrix_close(
+                &reduced.matrix,
+                &state.reduced_density_matrix(&[qubit]).matrix,
+            );
+
+            for (value, expected) in reduced.bloch_vector().iter().zip(expected) {
+                assert!((value - expected).abs() < 1e-12);
+            }
+        }
+
+        // The maximally mixed state is at the centre of the Bloch sphere
+        let mixed = DensityMatrix::new(&[0, 0])
+            .apply_gate_to_qubits(&QuantumGate::h_gate(), &[0])
+            .apply_gate_to_qubits(&QuantumGate::cnot_gate(), &[0, 1])
+            .reduced_density_matrix(&[1]);
+        assert!(mixed.bloch_vector().iter().all(|value| value.abs() < 1e-12));
+        assert!((mixed.purity() - 0.5).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::simulation::density_matrix::DensityMatrix;
+use crate::simulation::linalg::{adjoint, hermitian_eigen};
+use crate::simulation::quantum_gate::QuantumGate;
+use ndarray::Array2;
+use num::Complex;
+use serde::{Deserialize, Serialize};
+
+// Entanglement of the qubits in a step, entropies are in bits
+// entropies[i] is the von Neumann entropy of qubit i, for a pure state this is its entanglement with the rest
+// concurrence[i][j] is the entanglement of the pair i, j on its own, between 0 and 1
+// mutual_information[i][j] is S(i) + S(j) - S(i, j), the diagonal is 0
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntanglementAnalysis {
+    pub entropies: Vec<f64>,
+    pub concurrence: Vec<Vec<f64>>,
+    pub mutual_information: Vec<Vec<f64>>,
+}
+
+// Analyse the entanglement of a state given by a function returning the reduced density matrix of some qubits
+pub fn analyse_entanglement(
+    no_of_qubits: usize,
+    reduced_density_matrix: impl Fn(&[usize]) -> DensityMatrix,
+) -> EntanglementAnalysis {
+    let entropies: Vec<f64> = (0..no_of_qubits)
+        .map(|qubit| von_neumann_entropy(&reduced_density_matrix(&[qubit])))
+        .collect();
+
+    let mut concurrences = vec![vec![0.0; no_of_qubits]; no_of_qubits];
+    let mut mutual_information = vec![vec![0.0; no_of_qubits]; no_of_qubits];
+
+    for first in 0..no_of_qubits {
+        for second in first + 1..no_of_qubits {
+            let pair = reduced_density_matrix(&[first, second]);
+
+            let information = entropies[first] + entropies[second] - von_neumann_entropy(&pair);
+            // Rounding can make the mutual information of a product state slightly negative
+            let information = information.max(0.0);
+            mutual_information[first][second] = information;
+            mutual_information[second][first] = information;
+
+            let value = concurrence(&pair);
+            concurrences[first][second] = value;
+            concurrences[second][first] = value;
+        }
+    }
+
+    EntanglementAnalysis {
+        entropies,
+        concurrence: concurrences,
+        mutual_information,
+    }
+}
+
+// Calculate the von Neumann entropy -Tr(rho log2 rho) from the eigenvalues of rho
+pub fn von_neumann_entropy(density_matrix: &DensityMatrix) -> f64 {
+    let (eigenvalues, _) = hermitian_eigen(&density_matrix.matrix);
+
+    eigenvalues
+        .iter()
+        .filter(|&&value| value > 1e-12)
+        .map(|value| -value * value.log2())
+        .sum()
+}
+
+// Calculate the Wootters concurrence of a two-qubit density matrix, max(0, l1 - l2 - l3 - l4)
+// where l are the square roots of the eigenvalues of R = sqrt(rho) rho~ sqrt(rho), largest first,
+// and rho~ = (Y x Y) rho* (Y x Y) is the spin-flipped state
+pub fn concurrence(density_matrix: &DensityMatrix) -> f64 {
+    let rho = &density_matrix.matrix;
+    let y_y = QuantumGate::y_gate()
+        .kronecker(QuantumGate::y_gate())
+        .matrix;
+    let flipped = y_y.dot(&rho.mapv(|value| value.conj())).dot(&y_y);
+
+    let (eigenvalues, eigenvectors) = hermitian_eigen(rho);
+    let square_root_diagonal = Array2::from_shape_fn((4, 4), |(row, column)| {
+        if row == column {
+            Complex::new(eigenvalues[row].max(0.0).sqrt(), 0.0)
+        } else {
+            Complex::new(0.0, 0.0)
+        }
+    });
+    let square_root = eigenvectors
+        .dot(&square_root_diagonal)
+        .dot(&adjoint(&eigenvectors));
+
+    let (values, _) = hermitian_eigen(&square_root.dot(&flipped).dot(&square_root));
+    let lambdas: Vec<f64> = values.iter().map(|value| value.max(0.0).sqrt()).collect();
+
+    (lambdas[0] - lambdas[1] - lambdas[2] - lambdas[3]).max(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::quantum_state::QuantumState;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_bell_state() {
+        let state = QuantumState::new(&[0, 0, 0])
+            .apply_gate_to_qubits(&QuantumGate::h_gate(), &[0])
+            .apply_gate_to_qubits(&QuantumGate::cnot_gate(), &[0, 1]);
+
+        let analysis = analyse_entanglement(3, |qubits| state.reduced_density_matrix(qubits));
+
+        assert_close(analysis.entropies[0], 1.0);
+        assert_close(analysis.entropies[1], 1.0);
+        assert_close(analysis.entropies[2], 0.0);
+        assert_close(analysis.concurrence[0][1], 1.0);
+        assert_close(analysis.concurrence[1][0], 1.0);
+        assert_close(analysis.concurrence[0][2], 0.0);
+        assert_close(analysis.mutual_information[0][1], 2.0);
+        assert_close(analysis.mutual_information[1][2], 0.0);
+        assert_close(analysis.mutual_information[0][0], 0.0);
+    }
+
+    // In a GHZ state every qubit is entangled with the rest, but no pair is entangled on its own
+    #[test]
+    fn test_ghz_state_has_no_pairwise_concurrence() {
+        let state = QuantumState::new(&[0, 0, 0])
+            .apply_gate_to_qubits(&QuantumGate::h_gate(), &[0])
+            .apply_gate_to_qubits(&QuantumGate::cnot_gate(), &[0, 1])
+            .apply_gate_to_qubits(&QuantumGate::cnot_gate(), &[1, 2]);
+
+        let analysis = analyse_entanglement(3, |qubits| state.reduced_density_matrix(qubits));
+
+        for qubit in 0..3 {
+            assert_close(analysis.entropies[qubit], 1.0);
+        }
+        assert_close(analysis.concurrence[0][2], 0.0);
+        assert_close(analysis.mutual_information[0][2], 1.0);
+    }
+
+    #[test]
+    fn test_partially_entangled_state() {
+        // cos(theta/2)|00> + sin(theta/2)|11> has concurrence sin(theta)
+        let theta = 0.8_f64;
+        let state = QuantumState::new(&[0, 0])
+            .apply_gate_to_qubits(&QuantumGate::ry(theta), &[0])
+            .apply_gate_to_qubits(&QuantumGate::cnot_gate(), &[0, 1]);
+
+        assert_close(
+            concurrence(&state.reduced_density_matrix(&[0, 1])),
+            theta.sin(),
+        );
+    }
+
+    #[test]
+    fn test_maximally_mixed_state() {
+        let density_matrix = DensityMatrix {
+            matrix: Array2::<Complex<f64>>::eye(4) * Complex::new(0.25, 0.0),
+        };
+
+        assert_close(von_neumann_entropy(&density_matrix), 2.0);
+        assert_close(concurrence(&density_matrix), 0.0);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::simulation::circuit_validator::{validate_circuit, QuantumCircuitError};
+use crate::simulation::gate_registry::GateRegistry;
+use crate::simulation::quantum_gate::QuantumGate;
+use crate::simulation::unitary::circuit_unitary;
+use num::Complex;
+use serde::Serialize;
+
+const TOLERANCE: f64 = 1e-9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Equivalence {
+    Equivalent,
+    EquivalentUpToGlobalPhase,
+    Different,
+}
+
+// The result of comparing two circuits
+// The average gate fidelity is 1 for circuits that are equivalent up to global phase and goes down to 1/(d+1)
+// The global phase is the angle phi with second = e^(i phi) first, only for equivalent circuits
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Comparison {
+    pub equivalence: Equivalence,
+    pub average_gate_fidelity: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub global_phase: Option<f64>,
+}
+
+// Compare the unitaries of two circuits on the same number of qubits
+pub fn compare_circuits(
+    first: Vec<Vec<&str>>,
+    second: Vec<Vec<&str>>,
+    max_qubits: usize,
+    gates: &GateRegistry,
+) -> Result<Comparison, QuantumCircuitError> {
+    if first.len() != second.len() {
+        return Err(QuantumCircuitError::QubitCountMismatch);
+    }
+
+    let first = circuit_unitary(first, max_qubits, gates)?;
+    let second = circuit_unitary(second, max_qubits, gates)?;
+
+    Ok(compare_unitaries(&first, &second))
+}
+
+// Check a grid written by a rewrite of the circuit, e.g. /optimize, against the original grid
+// Only circuits that have a unitary and at most max_qubits rows are compared, so the result is None for the others
+// The rewritten grid is parsed again, so this also checks that it is written correctly
+pub fn compare_rewritten(
+    original: &[Vec<&str>],
+    rewritten: &[Vec<String>],
+    max_qubits: usize,
+    gates: &GateRegistry,
+) -> Result<Option<Comparison>, QuantumCircuitError> {
+    let circuit = validate_circuit(original, usize::MAX, gates)?;
+    if circuit.qubits > max_qubits || circuit.has_measurements() || circuit.has_conditions() {
+        return Ok(None);
+    }
+
+    let rewritten = rewritten
+        .iter()
+        .map(|row| row.iter().map(|cell| cell.as_str()).collect())
+        .collect();
+    compare_circuits(original.to_vec(), rewritten, max_qubits, gates).map(Some)
+}
+
+// Compare two unitaries of the same size using the overlap Tr(U^dagger V)
+pub fn compare_unitaries(first: &QuantumGate, second: &QuantumGate) -> Comparison {
+    let dim = first.matrix.nrows() as f64;
+
+    let overlap: Complex<f64> = first
+        .matrix
+        .iter()
+        .zip(second.matrix.iter())
+        .map(|(u, v)| u.conj() * v)
+        .sum();
+    let average_gate_fidelity = (overlap.norm_sqr() + dim) / (dim * (dim + 1.0));
+
+    let is_equal = first
+        .matrix
+        .iter()
+        .zip(second.matrix.iter())
+        .all(|(u, v)| (u - v).norm() < TOLERANCE);
+
+    // |Tr(U^dagger V)| = d only if V is U times a phase
+    let (equivalence, global_phase) = if is_equal {
+        (Equivalence::Equivalent, Some(0.0))
+    } else if (overlap.norm() - dim).abs() < TOLERANCE * dim {
+        (Equivalence::EquivalentUpToGlobalPhase, Some(overlap.arg()))
+    } else {
+        (Equivalence::Different, None)
+    };
+
+    Comparison {
+        equivalence,
+        average_gate_fidelity,
+        global_phase,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn test_swap_from_three_cnots() {
+        let swap = vec![vec!["SWAP-1"], vec!["SWAP-2"]];
+        let cnots = vec![
+            vec!["CNOT-1", "CNOT-2", "CNOT-1"],
+            vec!["CNOT-2", "CNOT-1", "CNOT-2"],
+        ];
+
+        let comparison = compare_circuits(cnots, swap, 6, &GateRegistry::default()).unwrap();
+
+        assert_eq!(comparison.equivalence, Equivalence::Equivalent);
+        assert!((comparison.average_gate_fidelity - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_equivalent_up_to_global_phase() {
+        // RZ(theta) = e^(-i theta/2) P(theta)
+        let comparison = compare_circuits(
+            vec![vec!["P(pi/2)"]],
+            vec![vec!["RZ(pi/2)"]],
+            6,
+            &GateRegistry::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            comparison.equivalence,
+            Equivalence::EquivalentUpToGlobalPhase
+        );
+        assert!((comparison.global_phase.unwrap() + PI / 4.0).abs() < 1e-12);
+        assert!((comparison.average_gate_fidelity - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_different_circuits() {
+        let comparison = compare_circuits(
+            vec![vec!["X"]],
+            vec![vec!["Z"]],
+            6,
+            &GateRegistry::default(),
+        )
+        .unwrap();
+
+        assert_eq!(comparison.equivalence, Equivalence::Different);
+        assert_eq!(comparison.global_phase, None);
+        // Tr(X Z) = 0, so the fidelity is d / (d (d + 1)) = 1/3
+        assert!((comparison.average_gate_fidelity - 1.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_qubit_count_mismatch() {
+        assert_eq!(
+            compare_circuits(
+                vec![vec!["X"]],
+                vec![vec!["X"], vec!["I"]],
+                6,
+                &GateRegistry::default()
+            ),
+            Err(QuantumCircuitError::QubitCountMismatch)
+        );
+    }
+}
@@ -0,0 +1,252 @@
+// Parser for the angle expressions used by parameterised gates, e.g. "pi/4", "-3*pi/2", "0.25", "2pi"
+// Supports decimals, pi (or π), + - * /, parentheses and unary minus
+// A number directly followed by pi or a parenthesis is multiplied with it
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    Number(f64),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    LeftParen,
+    RightParen,
+}
+
+// Deepest nesting of parentheses accepted, every level is a recursion of the parser
+const MAX_NESTING: usize = 32;
+
+// Evaluate an arithmetic expression, returns None if the expression is invalid or not finite
+// or nests parentheses deeper than MAX_NESTING
+pub fn parse_expression(input: &str) -> Option<f64> {
+    let tokens = tokenize(input)?;
+    if nesting(&tokens) > MAX_NESTING {
+        return None;
+    }
+    let mut position = 0;
+
+    let value = parse_sum(&tokens, &mut position)?;
+
+    if position != tokens.len() || !value.is_finite() {
+        return None;
+    }
+
+    Some(value)
+}
+
+fn tokenize(input: &str) -> Option<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = input.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            ' ' => i += 1,
+            '+' => {
+                tokens.push(Token::Plus);
+                i += 1;
+            }
+            '-' => {
+                tokens.push(Token::Minus);
+                i += 1;
+            }
+            '*' => {
+                tokens.push(Token::Star);
+                i += 1;
+            }
+            '/' => {
+                tokens.push(Token::Slash);
+                i += 1;
+            }
+            '(' => {
+                tokens.push(Token::LeftParen);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::RightParen);
+                i += 1;
+            }
+            'π' => {
+                tokens.push(Token::Number(std::f64::consts::PI));
+                i += 1;
+            }
+            'p' | 'P' if chars.get(i + 1).is_some_and(|c| *c == 'i' || *c == 'I') => {
+                tokens.push(Token::Number(std::f64::consts::PI));
+                i += 2;
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                let number: String = chars[start..i].iter().collect();
+                tokens.push(Token::Number(number.parse().ok()?));
+            }
+            _ => return None,
+        }
+    }
+
+    Some(tokens)
+}
+
+// The deepest level of parentheses, unbalanced ones are left to the parser
+fn nesting(tokens: &[Token]) -> usize {
+    let mut depth: usize = 0;
+    let mut deepest = 0;
+
+    for token in tokens {
+        match token {
+            Token::LeftParen => {
+                depth += 1;
+                deepest = deepest.max(depth);
+            }
+            Token::RightParen => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    deepest
+}
+
+// sum := product (('+' | '-') product)*
+fn parse_sum(tokens: &[Token], position: &mut usize) -> Option<f64> {
+    let mut value = parse_product(tokens, position)?;
+
+    while let Some(token) = tokens.get(*position) {
+        match token {
+            Token::Plus => {
+                *position += 1;
+                value += parse_product(tokens, position)?;
+            }
+            Token::Minus => {
+                *position += 1;
+                value -= parse_product(tokens, position)?;
+            }
+            _ => break,
+        }
+    }
+
+    Some(value)
+}
+
+// product := factor (('*' | '/') factor | implicit factor)*
+fn parse_product(tokens: &[Token], position: &mut usize) -> Option<f64> {
+    let mut value = parse_factor(tokens, position)?;
+
+    while let Some(token) = tokens.get(*position) {
+        match token {
+            Token::Star => {
+                *position += 1;
+                value *= parse_factor(tokens, position)?;
+            }
+            Token::Slash => {
+                *position += 1;
+                value /= parse_factor(tokens, position)?;
+            }
+            // Implicit multiplication, e.g. "2pi" or "3(pi/4)"
+            Token::Number(_) | Token::LeftParen => {
+                value *= parse_factor(tokens, position)?;
+            }
+            _ => break,
+        }
+    }
+
+    Some(value)
+}
+
+// factor := ('-' | '+')* (number | '(' sum ')')
+// The signs are counted in a loop, so a long run of them does not recurse
+fn parse_factor(tokens: &[Token], position: &mut usize) -> Option<f64> {
+    let mut sign = 1.0;
+    let mut token = *tokens.get(*position)?;
+    *position += 1;
+
+    while matches!(token, Token::Minus | Token::Plus) {
+        if token == Token::Minus {
+            sign = -sign;
+        }
+        token = *tokens.get(*position)?;
+        *position += 1;
+    }
+
+    let value = match token {
+        Token::Number(value) => value,
+        Token::LeftParen => {
+            let value = parse_sum(tokens, position)?;
+            if tokens.get(*position) != Some(&Token::RightParen) {
+                return None;
+            }
+            *position += 1;
+            value
+        }
+        _ => return None,
+    };
+
+    Some(sign * value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    fn assert_close(input: &str, expected: f64) {
+        let value = parse_expression(input).unwrap();
+        assert!(
+            (value - expected).abs() < 1e-12,
+            "{} gave {}, expected {}",
+            input,
+            value,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert_close("1", 1.0);
+        assert_close("0.25", 0.25);
+        assert_close(".5", 0.5);
+    }
+
+    #[test]
+    fn test_pi_and_fractions() {
+        assert_close("pi", PI);
+        assert_close("π", PI);
+        assert_close("pi/4", PI / 4.0);
+        assert_close("-3*pi/2", -3.0 * PI / 2.0);
+        assert_close("2pi", 2.0 * PI);
+        assert_close("3(pi/4)", 3.0 * PI / 4.0);
+    }
+
+    #[test]
+    fn test_operator_precedence() {
+        assert_close("1 + 2 * 3", 7.0);
+        assert_close("(1 + 2) * 3", 9.0);
+        assert_close("1 - 2 - 3", -4.0);
+        assert_close("8 / 2 / 2", 2.0);
+        assert_close("--1", 1.0);
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        let signs = "-".repeat(100_000);
+        assert_close(&format!("{}1", signs), 1.0);
+        assert_close(&format!("{}+-1", signs), -1.0);
+
+        let nested = |depth: usize| format!("{}pi{}", "(".repeat(depth), ")".repeat(depth));
+        assert_close(&nested(MAX_NESTING), PI);
+        assert_eq!(parse_expression(&nested(MAX_NESTING + 1)), None);
+        assert_eq!(parse_expression(&nested(100_000)), None);
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        assert_eq!(parse_expression(""), None);
+        assert_eq!(parse_expression("pi/"), None);
+        assert_eq!(parse_expression("(pi"), None);
+        assert_eq!(parse_expression("pi)"), None);
+        assert_eq!(parse_expression("theta"), None);
+        assert_eq!(parse_expression("1..2"), None);
+        assert_eq!(parse_expression("1/0"), None);
+    }
+}
@@ -0,0 +1,377 @@
+// Gates defined by the clients next to the built-in gates of circuit::Gate
+// A custom gate is defined by its unitary or by a sub-circuit and is then used in the grid by its name,
+// like a built-in gate: "NAME" for a single-qubit gate and "NAME-1", "NAME-2", ... for the parts of a larger one
+// The matrix is stored, so a sub-circuit is only simulated once when the gate is defined
+
+use crate::simulation::circuit::Gate;
+use crate::simulation::circuit_validator::QuantumCircuitError;
+use crate::simulation::quantum_gate::QuantumGate;
+use crate::simulation::unitary::circuit_unitary;
+use crate::simulation::utils::{format_matrix, matrix_to_little_endian};
+use crate::ComplexContainer;
+use ndarray::Array2;
+use num::Complex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+// A gate defined by a client, the matrix uses the same ordering as the built-in gates (part 1 is the first qubit)
+#[derive(Debug, PartialEq)]
+pub struct CustomGate {
+    pub name: String,
+    pub matrix: QuantumGate,
+}
+
+// The definition of a custom gate as sent by a client, with either a matrix or a circuit
+// The matrix is little endian like the unitaries of /unitary, so qubit i of the matrix is part i + 1,
+// and row i of the circuit is part i + 1
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GateDefinition {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matrix: Option<Vec<Vec<ComplexContainer>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub circuit: Option<Vec<Vec<String>>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GateRegistry {
+    gates: BTreeMap<String, Arc<CustomGate>>,
+}
+
+impl GateRegistry {
+    // Look up a built-in or custom gate by the name used in the grid
+    pub fn get(&self, name: &str) -> Option<Gate> {
+        Gate::from_name(name).or_else(|| self.gates.get(name).cloned().map(Gate::Custom))
+    }
+
+    pub fn custom_gates(&self) -> impl Iterator<Item = &CustomGate> {
+        self.gates.values().map(|gate| gate.as_ref())
+    }
+
+    // Add a gate to the registry, a name that is already taken is rejected, so a gate never changes
+    // under the circuits of other clients
+    // A sub-circuit may use the custom gates that are already registered
+    // The gate can have at most max_qubits qubits, since its full matrix is kept
+    pub fn define(
+        &mut self,
+        definition: &GateDefinition,
+        max_qubits: usize,
+    ) -> Result<&CustomGate, QuantumCircuitError> {
+        if !is_valid_name(&definition.name) {
+            return Err(invalid(
+                "the name must start with a letter and only contain letters, digits and _, and can not be a built-in gate",
+            ));
+        }
+        if self.gates.contains_key(&definition.name) {
+            return Err(invalid("a gate with this name already exists"));
+        }
+
+        let matrix = match (&definition.matrix, &definition.circuit) {
+            (Some(matrix), None) => parse_matrix(matrix, max_qubits)?,
+            (None, Some(circuit)) => {
+                let grid: Vec<Vec<&str>> = circuit
+                    .iter()
+                    .map(|row| row.iter().map(|cell| cell.as_str()).collect())
+                    .collect();
+                circuit_unitary(grid, max_qubits, self)?
+            }
+            _ => return Err(invalid("a gate needs either a matrix or a circuit")),
+        };
+
+        let name = definition.name.clone();
+        let gate = Arc::new(CustomGate {
+            name: name.clone(),
+            matrix,
+        });
+        self.gates.insert(name.clone(), gate);
+
+        Ok(&self.gates[&name])
+    }
+
+    // Remove a custom gate, returns whether it was registered
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.gates.remove(name).is_some()
+    }
+
+    // The definitions of all custom gates with their matrices, to store the registry and define them again later
+    pub fn definitions(&self) -> Vec<GateDefinition> {
+        self.custom_gates()
+            .map(|gate| GateDefinition {
+                name: gate.name.clone(),
+                matrix: Some(format_matrix(&matrix_to_little_endian(&gate.matrix.matrix))),
+                circuit: None,
+            })
+            .collect()
+    }
+
+    // Read a registry written by save, a missing file gives an empty registry
+    pub fn load(path: &Path, max_qubits: usize) -> Result<GateRegistry, String> {
+        let mut registry = GateRegistry::default();
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(registry),
+            Err(err) => return Err(err.to_string()),
+        };
+
+        let definitions: Vec<GateDefinition> =
+            serde_json::from_str(&source).map_err(|err| err.to_string())?;
+        for definition in &definitions {
+            registry
+                .define(definition, max_qubits)
+                .map_err(|err| format!("{}: {:?}", definition.name, err))?;
+        }
+
+        Ok(registry)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_string(&self.definitions())?)
+    }
+}
+
+fn invalid(reason: &str) -> QuantumCircuitError {
+    QuantumCircuitError::InvalidCustomGate(reason.to_string())
+}
+
+// A name can not clash with the grid syntax, the control markers, measurement or a built-in gate
+fn is_valid_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !matches!(name, "C" | "O")
+        && Gate::from_name(name).is_none()
+}
+
+// Read a little-endian matrix, which must be a unitary on 1 to max_qubits qubits
+fn parse_matrix(
+    rows: &[Vec<ComplexContainer>],
+    max_qubits: usize,
+) -> Result<QuantumGate, QuantumCircuitError> {
+    let dim = rows.len();
+    if dim < 2 || !dim.is_power_of_two() || rows.iter().any(|row| row.len() != dim) {
+        return Err(invalid(
+            "the matrix must be square with a power of two rows, at least 2",
+        ));
+    }
+
+    let size = dim.ilog2() as usize;
+    if size > max_qubits {
+        return Err(invalid("the gate has too many qubits"));
+    }
+
+    let matrix = Array2::from_shape_fn((dim, dim), |(row, column)| {
+        Complex::new(rows[row][column].re, rows[row][column].im)
+    });
+    // Reversing the bits of the indices turns little endian into the ordering of the other gates and back
+    let gate = QuantumGate {
+        matrix: matrix_to_little_endian(&matrix),
+        size,
+    };
+
+    match gate.is_unitary() {
+        true => Ok(gate),
+        false => Err(invalid("the matrix is not unitary")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::circuit_parser::parse_circuit;
+
+    fn definition(name: &str, circuit: &[&[&str]]) -> GateDefinition {
+        GateDefinition {
+            name: name.to_string(),
+            matrix: None,
+            circuit: Some(
+                circuit
+                    .iter()
+                    .map(|row| row.iter().map(|cell| cell.to_string()).collect())
+                    .collect(),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_define_from_matrix() {
+        let mut registry = GateRegistry::default();
+        let sqrt_x = GateDefinition {
+            name: "SQRTX".to_string(),
+            matrix: Some(vec![
+                vec![
+                    ComplexContainer { re: 0.5, im: 0.5 },
+                    ComplexContainer { re: 0.5, im: -0.5 },
+                ],
+                vec![
+                    ComplexContainer { re: 0.5, im: -0.5 },
+                    ComplexContainer { re: 0.5, im: 0.5 },
+                ],
+            ]),
+            circuit: None,
+        };
+
+        let gate = registry.define(&sqrt_x, 4).unwrap();
+        assert_eq!(gate.matrix.size, 1);
+
+        // Two square roots of X make an X
+        let circuit = parse_circuit(&[vec!["SQRTX", "SQRTX"]], &registry).unwrap();
+        let x = circuit_unitary(vec![vec!["SQRTX", "SQRTX"]], 4, &registry).unwrap();
+        assert_eq!(circuit.moments[0].operations[0].gate.name(), "SQRTX");
+        for (a, b) in x.matrix.iter().zip(QuantumGate::x_gate().matrix.iter()) {
+            assert!((a - b).norm() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_matrix_is_little_endian() {
+        // The unitary of a CNOT with the control on part 2, written like /unitary writes it
+        let unitary = circuit_unitary(
+            vec![vec!["CNOT-2"], vec!["CNOT-1"]],
+            4,
+            &GateRegistry::default(),
+        )
+        .unwrap();
+        let mut registry = GateRegistry::default();
+        let reversed = GateDefinition {
+            name: "RCNOT".to_string(),
+            matrix: Some(format_matrix(&matrix_to_little_endian(&unitary.matrix))),
+            circuit: None,
+        };
+
+        let gate = registry.define(&reversed, 4).unwrap();
+
+        assert_eq!(gate.matrix.matrix, unitary.matrix);
+    }
+
+    #[test]
+    fn test_define_from_circuit() {
+        let mut registry = GateRegistry::default();
+        registry
+            .define(
+                &definition("BELL", &[&["H", "CNOT-1"], &["I", "CNOT-2"]]),
+                4,
+            )
+            .unwrap();
+        // A sub-circuit can use custom gates defined before it
+        registry
+            .define(
+                &definition(
+                    "BELL2",
+                    &[&["BELL-1#0"], &["BELL-2#0"], &["BELL-1#1"], &["BELL-2#1"]],
+                ),
+                4,
+            )
+            .unwrap();
+
+        let grid = vec![
+            vec!["BELL-2", "C"],
+            vec!["I", "BELL2-1"],
+            vec!["BELL-1", "BELL2-2"],
+            vec!["I", "BELL2-3"],
+            vec!["I", "BELL2-4"],
+        ];
+        let circuit = parse_circuit(&grid, &registry).unwrap();
+
+        assert_eq!(circuit.moments[0].operations[0].targets, vec![2, 0]);
+        assert_eq!(
+            circuit.moments[1].operations[0].qubits(),
+            vec![0, 1, 2, 3, 4]
+        );
+        assert_eq!(registry.custom_gates().count(), 2);
+    }
+
+    #[test]
+    fn test_invalid_definitions() {
+        let mut registry = GateRegistry::default();
+        let matrix = |rows: Vec<Vec<f64>>| GateDefinition {
+            name: "G".to_string(),
+            matrix: Some(
+                rows.into_iter()
+                    .map(|row| {
+                        row.into_iter()
+                            .map(|re| ComplexContainer { re, im: 0.0 })
+                            .collect()
+                    })
+                    .collect(),
+            ),
+            circuit: None,
+        };
+
+        let not_unitary = matrix(vec![vec![1.0, 1.0], vec![0.0, 1.0]]);
+        let not_square = matrix(vec![vec![1.0, 0.0], vec![0.0]]);
+        let three_rows = matrix(vec![vec![1.0; 3]; 3]);
+        let too_large = matrix(
+            (0..8)
+                .map(|row| (0..8).map(|column| (row == column) as u8 as f64).collect())
+                .collect(),
+        );
+
+        for definition in [not_unitary, not_square, three_rows, too_large] {
+            assert!(matches!(
+                registry.define(&definition, 2),
+                Err(QuantumCircuitError::InvalidCustomGate(_))
+            ));
+        }
+
+        for name in ["H", "CNOT", "M", "C", "1G", "G-1", "G#", ""] {
+            assert!(matches!(
+                registry.define(&definition(name, &[&["X"]]), 2),
+                Err(QuantumCircuitError::InvalidCustomGate(_))
+            ));
+        }
+
+        assert_eq!(
+            registry
+                .define(&definition("G", &[&["H", "M"]]), 2)
+                .unwrap_err(),
+            QuantumCircuitError::NonUnitaryOperation
+        );
+        assert_eq!(registry.custom_gates().count(), 0);
+    }
+
+    #[test]
+    fn test_names_can_not_be_redefined() {
+        let mut registry = GateRegistry::default();
+        registry.define(&definition("G", &[&["H"]]), 2).unwrap();
+
+        assert_eq!(
+            registry.define(&definition("G", &[&["X"]]), 2).unwrap_err(),
+            QuantumCircuitError::InvalidCustomGate(
+                "a gate with this name already exists".to_string()
+            )
+        );
+        let gate = registry.custom_gates().next().unwrap();
+        assert_eq!(gate.matrix.matrix, QuantumGate::h_gate().matrix);
+
+        assert!(registry.remove("G"));
+        assert!(!registry.remove("G"));
+        assert!(registry.define(&definition("G", &[&["X"]]), 2).is_ok());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("gates-{}.json", std::process::id()));
+        let mut registry = GateRegistry::default();
+        registry
+            .define(&definition("SH", &[&["S", "H"]]), 4)
+            .unwrap();
+
+        registry.save(&path).unwrap();
+        let loaded = GateRegistry::load(&path, 4).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let original = registry.custom_gates().next().unwrap();
+        let gate = loaded.custom_gates().next().unwrap();
+        assert_eq!(gate.name, "SH");
+        for (a, b) in gate.matrix.matrix.iter().zip(original.matrix.matrix.iter()) {
+            assert!((a - b).norm() < 1e-12);
+        }
+        assert_eq!(
+            GateRegistry::load(&path, 4).unwrap().custom_gates().count(),
+            0
+        );
+    }
+}
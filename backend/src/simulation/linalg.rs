@@ -0,0 +1,357 @@
+// Small dense linear algebra routines for the analysis and simulation backends that need more than ndarray offers
+
+use ndarray::Array2;
+use num::Complex;
+use std::f64::consts::PI;
+
+const MAX_SWEEPS: usize = 100;
+const TOLERANCE: f64 = 1e-9;
+
+// Calculate the eigenvalues and eigenvectors of a Hermitian matrix with the cyclic Jacobi method
+// The eigenvalues are sorted from largest to smallest, column i of the returned matrix is the eigenvector of eigenvalue i
+pub fn hermitian_eigen(matrix: &Array2<Complex<f64>>) -> (Vec<f64>, Array2<Complex<f64>>) {
+    let n = matrix.nrows();
+    let mut a = matrix.clone();
+    let mut vectors = Array2::<Complex<f64>>::eye(n);
+
+    let norm: f64 = a.iter().map(|value| value.norm_sqr()).sum::<f64>().sqrt();
+    let tolerance = 1e-15 * norm.max(f64::MIN_POSITIVE);
+
+    for _ in 0..MAX_SWEEPS {
+        let off_diagonal: f64 = a
+            .indexed_iter()
+            .filter(|((row, column), _)| row != column)
+            .map(|(_, value)| value.norm_sqr())
+            .sum::<f64>()
+            .sqrt();
+        if off_diagonal <= tolerance {
+            break;
+        }
+
+        for p in 0..n {
+            for q in p + 1..n {
+                if a[[p, q]].norm() <= tolerance / n as f64 {
+                    continue;
+                }
+                rotate(&mut a, &mut vectors, p, q);
+            }
+        }
+    }
+
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&i, &j| a[[j, j]].re.total_cmp(&a[[i, i]].re));
+
+    let values = order.iter().map(|&i| a[[i, i]].re).collect();
+    let sorted_vectors =
+        Array2::from_shape_fn((n, n), |(row, column)| vectors[[row, order[column]]]);
+
+    (values, sorted_vectors)
+}
+
+// Zero the entries (p, q) and (q, p) of a with the unitary V = D P, a -> V^dagger a V
+// D removes the phase of a[p][q] so that P can be a real Jacobi rotation
+fn rotate(a: &mut Array2<Complex<f64>>, vectors: &mut Array2<Complex<f64>>, p: usize, q: usize) {
+    let n = a.nrows();
+
+    let phase = Complex::from_polar(1.0, -a[[p, q]].arg());
+    for k in 0..n {
+        a[[k, q]] *= phase;
+        vectors[[k, q]] *= phase;
+    }
+    for k in 0..n {
+        a[[q, k]] *= phase.conj();
+    }
+
+    let a_pp = a[[p, p]].re;
+    let a_qq = a[[q, q]].re;
+    let a_pq = a[[p, q]].re;
+
+    let theta = (a_qq - a_pp) / (2.0 * a_pq);
+    let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+    let c = 1.0 / (t * t + 1.0).sqrt();
+    let s = t * c;
+
+    for k in 0..n {
+        let (kp, kq) = (a[[k, p]], a[[k, q]]);
+        a[[k, p]] = kp * c - kq * s;
+        a[[k, q]] = kp * s + kq * c;
+
+        let (kp, kq) = (vectors[[k, p]], vectors[[k, q]]);
+        vectors[[k, p]] = kp * c - kq * s;
+        vectors[[k, q]] = kp * s + kq * c;
+    }
+    for k in 0..n {
+        let (pk, qk) = (a[[p, k]], a[[q, k]]);
+        a[[p, k]] = pk * c - qk * s;
+        a[[q, k]] = pk * s + qk * c;
+    }
+
+    a[[p, q]] = Complex::new(0.0, 0.0);
+    a[[q, p]] = Complex::new(0.0, 0.0);
+}
+
+// Calculate the singular value decomposition M = U diag(values) V^dagger with the one-sided Jacobi method
+// For an m x n matrix with k = min(m, n), U is m x k, V^dagger is k x n and the k singular values are sorted
+// from largest to smallest, the columns of U belonging to zero singular values are zero
+pub fn svd(
+    matrix: &Array2<Complex<f64>>,
+) -> (Array2<Complex<f64>>, Vec<f64>, Array2<Complex<f64>>) {
+    // The method orthogonalises columns, so a wide matrix is decomposed through its adjoint
+    if matrix.nrows() < matrix.ncols() {
+        let (u, values, v_dagger) = svd(&adjoint(matrix));
+        return (adjoint(&v_dagger), values, adjoint(&u));
+    }
+
+    let (m, n) = matrix.dim();
+    let mut a = matrix.clone();
+    let mut v = Array2::<Complex<f64>>::eye(n);
+
+    for _ in 0..MAX_SWEEPS {
+        let mut rotated = false;
+
+        for p in 0..n {
+            for q in p + 1..n {
+                let alpha: f64 = a.column(p).iter().map(|value| value.norm_sqr()).sum();
+                let beta: f64 = a.column(q).iter().map(|value| value.norm_sqr()).sum();
+                let gamma: Complex<f64> = a
+                    .column(p)
+                    .iter()
+                    .zip(a.column(q).iter())
+                    .map(|(x, y)| x.conj() * y)
+                    .sum();
+
+                if gamma.norm() <= 1e-15 * (alpha * beta).sqrt() || gamma.norm() == 0.0 {
+                    continue;
+                }
+                rotated = true;
+
+                // Remove the phase of the overlap, then zero it with a real rotation like in hermitian_eigen
+                let phase = Complex::from_polar(1.0, -gamma.arg());
+                let zeta = (beta - alpha) / (2.0 * gamma.norm());
+                let t = zeta.signum() / (zeta.abs() + (zeta * zeta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+
+                for columns in [&mut a, &mut v] {
+                    for k in 0..columns.nrows() {
+                        let (kp, kq) = (columns[[k, p]], columns[[k, q]] * phase);
+                        columns[[k, p]] = kp * c - kq * s;
+                        columns[[k, q]] = kp * s + kq * c;
+                    }
+                }
+            }
+        }
+
+        if !rotated {
+            break;
+        }
+    }
+
+    let norms: Vec<f64> = (0..n)
+        .map(|column| {
+            a.column(column)
+                .iter()
+                .map(|value| value.norm_sqr())
+                .sum::<f64>()
+                .sqrt()
+        })
+        .collect();
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&i, &j| norms[j].total_cmp(&norms[i]));
+
+    let values: Vec<f64> = order.iter().map(|&i| norms[i]).collect();
+    let u = Array2::from_shape_fn((m, n), |(row, column)| match values[column] > 0.0 {
+        true => a[[row, order[column]]] / values[column],
+        false => Complex::new(0.0, 0.0),
+    });
+    let v_dagger = Array2::from_shape_fn((n, n), |(row, column)| v[[column, order[row]]].conj());
+
+    (u, values, v_dagger)
+}
+
+// The conjugate transpose of a matrix, in standard layout so that it can be reshaped
+pub fn adjoint(matrix: &Array2<Complex<f64>>) -> Array2<Complex<f64>> {
+    Array2::from_shape_fn((matrix.ncols(), matrix.nrows()), |(row, column)| {
+        matrix[[column, row]].conj()
+    })
+}
+
+// Whether the matrices are equal up to a global phase, first = e^(i phi) second
+pub fn equal_up_to_phase(first: &Array2<Complex<f64>>, second: &Array2<Complex<f64>>) -> bool {
+    // The phase is taken from the largest entry, so it is well defined
+    let (index, _) = second
+        .indexed_iter()
+        .max_by(|(_, a), (_, b)| a.norm().total_cmp(&b.norm()))
+        .unwrap();
+    let phase = first[index] / second[index];
+
+    (phase.norm() - 1.0).abs() < TOLERANCE
+        && first
+            .iter()
+            .zip(second.iter())
+            .all(|(a, b)| (a - phase * b).norm() < TOLERANCE)
+}
+
+// The angles of a 2x2 unitary with matrix = e^(i alpha) U3(theta, phi, lambda), which has a real, non-negative top left entry
+pub fn u3_angles(matrix: &Array2<Complex<f64>>) -> (f64, f64, f64) {
+    let (a, b, c, d) = (
+        matrix[[0, 0]],
+        matrix[[0, 1]],
+        matrix[[1, 0]],
+        matrix[[1, 1]],
+    );
+    let theta = 2.0 * c.norm().atan2(a.norm());
+
+    let (phi, lambda) = if a.norm() < TOLERANCE {
+        // cos(theta/2) is 0, so only phi - lambda matters and lambda is 0
+        (c.arg() - (-b).arg(), 0.0)
+    } else if c.norm() < TOLERANCE {
+        // sin(theta/2) is 0, so only phi + lambda matters and phi is 0
+        (0.0, d.arg() - a.arg())
+    } else {
+        (c.arg() - a.arg(), (-b).arg() - a.arg())
+    };
+
+    (theta, normalize_angle(phi), normalize_angle(lambda))
+}
+
+// The same angle in [-pi, pi), 0 if it is close to it
+pub fn normalize_angle(angle: f64) -> f64 {
+    let angle = (angle + PI).rem_euclid(2.0 * PI) - PI;
+    match angle.abs() < TOLERANCE {
+        true => 0.0,
+        false => angle,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::quantum_gate::QuantumGate;
+    use ndarray::arr2;
+
+    fn assert_matrix_close(actual: &Array2<Complex<f64>>, expected: &Array2<Complex<f64>>) {
+        for (a, b) in actual.iter().zip(expected.iter()) {
+            assert!((a - b).norm() < 1e-10, "{} != {}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn test_pauli_y_eigenvalues() {
+        let y = arr2(&[
+            [Complex::new(0.0, 0.0), Complex::new(0.0, -1.0)],
+            [Complex::new(0.0, 1.0), Complex::new(0.0, 0.0)],
+        ]);
+
+        let (values, vectors) = hermitian_eigen(&y);
+
+        assert!((values[0] - 1.0).abs() < 1e-12);
+        assert!((values[1] + 1.0).abs() < 1e-12);
+        let column = vectors.column(0).to_owned().into_shape((2, 1)).unwrap();
+        assert_matrix_close(&y.dot(&column), &column);
+    }
+
+    // A = V diag(values) V^dagger for a random-looking Hermitian matrix
+    #[test]
+    fn test_decomposition_reconstructs_matrix() {
+        let n = 6;
+        let mut matrix = Array2::<Complex<f64>>::zeros((n, n));
+        for row in 0..n {
+            for column in row..n {
+                let value = Complex::new(
+                    ((row * 7 + column * 3) % 5) as f64 - 2.0,
+                    if row == column {
+                        0.0
+                    } else {
+                        ((row + 2 * column) % 3) as f64 - 1.0
+                    },
+                );
+                matrix[[row, column]] = value;
+                matrix[[column, row]] = value.conj();
+            }
+        }
+
+        let (values, vectors) = hermitian_eigen(&matrix);
+
+        assert!(values.windows(2).all(|pair| pair[0] >= pair[1]));
+        let diagonal = Array2::from_shape_fn((n, n), |(row, column)| {
+            if row == column {
+                Complex::new(values[row], 0.0)
+            } else {
+                Complex::new(0.0, 0.0)
+            }
+        });
+        assert_matrix_close(&vectors.dot(&diagonal).dot(&adjoint(&vectors)), &matrix);
+        assert_matrix_close(&adjoint(&vectors).dot(&vectors), &Array2::eye(n));
+    }
+
+    fn reconstruct(
+        u: &Array2<Complex<f64>>,
+        values: &[f64],
+        v_dagger: &Array2<Complex<f64>>,
+    ) -> Array2<Complex<f64>> {
+        let k = values.len();
+        let diagonal = Array2::from_shape_fn((k, k), |(row, column)| match row == column {
+            true => Complex::new(values[row], 0.0),
+            false => Complex::new(0.0, 0.0),
+        });
+        u.dot(&diagonal).dot(v_dagger)
+    }
+
+    #[test]
+    fn test_svd_reconstructs_matrix() {
+        for (rows, columns) in [(4, 4), (6, 3), (2, 5)] {
+            let matrix = Array2::from_shape_fn((rows, columns), |(row, column)| {
+                Complex::new(
+                    ((row * 5 + column * 3) % 7) as f64 - 3.0,
+                    ((row + 4 * column) % 5) as f64 - 2.0,
+                )
+            });
+
+            let (u, values, v_dagger) = svd(&matrix);
+
+            assert_eq!(values.len(), rows.min(columns));
+            assert!(values.windows(2).all(|pair| pair[0] >= pair[1]));
+            assert_matrix_close(&reconstruct(&u, &values, &v_dagger), &matrix);
+            assert_matrix_close(&adjoint(&u).dot(&u), &Array2::eye(values.len()));
+            assert_matrix_close(
+                &v_dagger.dot(&adjoint(&v_dagger)),
+                &Array2::eye(values.len()),
+            );
+        }
+    }
+
+    #[test]
+    fn test_svd_of_rank_one_matrix() {
+        // The outer product of (1, i) and (1, 1, 0) has a single singular value |a| |b| = 2
+        let matrix = arr2(&[
+            [
+                Complex::new(1.0, 0.0),
+                Complex::new(1.0, 0.0),
+                Complex::new(0.0, 0.0),
+            ],
+            [
+                Complex::new(0.0, 1.0),
+                Complex::new(0.0, 1.0),
+                Complex::new(0.0, 0.0),
+            ],
+        ]);
+
+        let (u, values, v_dagger) = svd(&matrix);
+
+        assert!((values[0] - 2.0).abs() < 1e-12);
+        assert!(values[1].abs() < 1e-12);
+        assert_matrix_close(&reconstruct(&u, &values, &v_dagger), &matrix);
+    }
+
+    #[test]
+    fn test_u3_angles() {
+        for (theta, phi, lambda) in [(0.3, 1.2, -2.0), (PI, 0.5, 0.0), (0.0, 0.0, 0.7)] {
+            let gate = QuantumGate::u3(theta, phi, lambda).matrix * Complex::from_polar(1.0, 0.4);
+
+            let (t, p, l) = u3_angles(&gate);
+
+            assert!(equal_up_to_phase(&QuantumGate::u3(t, p, l).matrix, &gate));
+        }
+    }
+}
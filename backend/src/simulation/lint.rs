@@ -0,0 +1,397 @@
+// Warnings about circuits that are valid but probably not what was meant, they never stop a simulation
+// The lints only look at the structure of the circuit, nothing is simulated:
+// - RedundantPair: a self-inverse gate directly followed by the same gate on the same qubits, e.g. H H or CNOT CNOT
+// - NoEffect: a gate that can not change the state because its qubits are still |0>,
+//   e.g. Z on a fresh qubit or a gate controlled by a qubit that was never touched
+// - EmptyTrailingColumns: columns at the end of the grid without any gate
+// - GateAfterMeasurement: a gate on a qubit that has already been measured
+
+use crate::simulation::circuit::{Circuit, Gate, Operation};
+use crate::simulation::circuit_validator::{validate_circuit, QuantumCircuitError};
+use crate::simulation::gate_registry::GateRegistry;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Lint {
+    RedundantPair,
+    NoEffect,
+    EmptyTrailingColumns,
+    GateAfterMeasurement,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LintCell {
+    pub row: usize,
+    pub column: usize,
+}
+
+// A warning with every cell it is about, so the client can highlight them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LintWarning {
+    pub warning: Lint,
+    pub cells: Vec<LintCell>,
+    pub message: String,
+}
+
+// Validate the grid and lint the circuit
+pub fn lint_grid(
+    grid: &[Vec<&str>],
+    max_qubits: usize,
+    gates: &GateRegistry,
+) -> Result<Vec<LintWarning>, QuantumCircuitError> {
+    validate_circuit(grid, max_qubits, gates).map(|circuit| lint_circuit(&circuit))
+}
+
+// All warnings for the circuit, ordered by the column of the gate they are reported at
+pub fn lint_circuit(circuit: &Circuit) -> Vec<LintWarning> {
+    // The operations in the order they are applied, with their column
+    let operations: Vec<(usize, &Operation)> = circuit
+        .moments
+        .iter()
+        .enumerate()
+        .flat_map(|(column, moment)| {
+            moment
+                .operations
+                .iter()
+                .map(move |operation| (column, operation))
+        })
+        .filter(|(_, operation)| operation.gate != Gate::I)
+        .collect();
+
+    let mut warnings = redundant_pairs(circuit.qubits, &operations);
+    warnings.extend(no_effect(circuit.qubits, &operations));
+    warnings.extend(gates_after_measurement(circuit.qubits, &operations));
+    warnings.extend(empty_trailing_columns(circuit));
+    warnings.sort_by_key(|warning| warning.cells.last().map(|cell| cell.column));
+
+    warnings
+}
+
+// Two self-inverse gates cancel if no other gate touches their qubits in between
+// A gate that is part of a pair is not paired again, so H H H gives one warning
+fn redundant_pairs(qubits: usize, operations: &[(usize, &Operation)]) -> Vec<LintWarning> {
+    // The index of the last operation on every qubit that can still start a pair
+    let mut last: Vec<Option<usize>> = vec![None; qubits];
+    let mut warnings = Vec::new();
+
+    for (index, &(column, operation)) in operations.iter().enumerate() {
+        let qubits = operation.qubits();
+        let pair = last[qubits[0]].filter(|&previous| {
+            let (_, first) = operations[previous];
+            qubits.iter().all(|&qubit| last[qubit] == Some(previous))
+                && first.qubits().len() == qubits.len()
+                && first.cancels(operation)
+        });
+
+        match pair {
+            Some(previous) => {
+                let (first_column, first) = operations[previous];
+                let mut cells = cells(first_column, first);
+                cells.extend(self::cells(column, operation));
+                warnings.push(LintWarning {
+                    warning: Lint::RedundantPair,
+                    cells,
+                    message: format!(
+                        "{} in columns {} and {} cancel each other",
+                        describe(operation),
+                        first_column,
+                        column
+                    ),
+                });
+                qubits.iter().for_each(|&qubit| last[qubit] = None);
+            }
+            None => qubits.iter().for_each(|&qubit| last[qubit] = Some(index)),
+        }
+    }
+
+    warnings
+}
+
+// A qubit is fresh until a gate that can change it is applied, gates without effect keep it fresh
+fn no_effect(qubits: usize, operations: &[(usize, &Operation)]) -> Vec<LintWarning> {
+    let mut fresh = vec![true; qubits];
+    let mut warnings = Vec::new();
+
+    for &(column, operation) in operations {
+        let reason = match operation
+            .controls
+            .iter()
+            .find(|control| control.state && fresh[control.qubit])
+        {
+            Some(control) => Some(format!("its control qubit {} is still |0>", control.qubit)),
+            None => unchanged_zero(operation, &fresh),
+        };
+
+        match reason {
+            Some(reason) => warnings.push(LintWarning {
+                warning: Lint::NoEffect,
+                cells: cells(column, operation),
+                message: format!(
+                    "{} in column {} does nothing, {}",
+                    describe(operation),
+                    column,
+                    reason
+                ),
+            }),
+            // A measurement of |0> leaves it |0>
+            None if !operation.is_measurement() => operation
+                .targets
+                .iter()
+                .for_each(|&qubit| fresh[qubit] = false),
+            None => (),
+        }
+    }
+
+    warnings
+}
+
+// Why the gate leaves the |0> of its fresh target qubits unchanged, if it does
+// RZ only multiplies |0> with a global phase, which becomes a relative phase when it is controlled
+fn unchanged_zero(operation: &Operation, fresh: &[bool]) -> Option<String> {
+    let targets = &operation.targets;
+    let still_zero = |qubit: usize| format!("qubit {} is still |0>", qubit);
+
+    match operation.gate {
+        Gate::Z | Gate::S | Gate::T | Gate::P if fresh[targets[0]] => Some(still_zero(targets[0])),
+        Gate::RZ if fresh[targets[0]] && operation.controls.is_empty() => {
+            Some(still_zero(targets[0]))
+        }
+        Gate::Cnot | Gate::Ccnot => targets[..targets.len() - 1]
+            .iter()
+            .find(|&&qubit| fresh[qubit])
+            .map(|qubit| format!("its control qubit {} is still |0>", qubit)),
+        Gate::Cz => targets
+            .iter()
+            .find(|&&qubit| fresh[qubit])
+            .map(|&qubit| still_zero(qubit)),
+        Gate::Swap if targets.iter().all(|&qubit| fresh[qubit]) => {
+            Some("both qubits are still |0>".to_string())
+        }
+        _ => None,
+    }
+}
+
+// Only the first gate after every measurement is reported
+fn gates_after_measurement(qubits: usize, operations: &[(usize, &Operation)]) -> Vec<LintWarning> {
+    // The column of the last measurement of every qubit that has not been reported yet
+    let mut measured: Vec<Option<usize>> = vec![None; qubits];
+    let mut warnings = Vec::new();
+
+    for &(column, operation) in operations {
+        let after: Vec<usize> = operation
+            .qubits()
+            .into_iter()
+            .filter(|&qubit| measured[qubit].is_some())
+            .collect();
+
+        if let Some(&qubit) = after.first() {
+            warnings.push(LintWarning {
+                warning: Lint::GateAfterMeasurement,
+                cells: cells(column, operation),
+                message: format!(
+                    "{} in column {} acts on qubit {} after it is measured in column {}",
+                    describe(operation),
+                    column,
+                    qubit,
+                    measured[qubit].unwrap()
+                ),
+            });
+            after.iter().for_each(|&qubit| measured[qubit] = None);
+        }
+        if operation.is_measurement() {
+            measured[operation.targets[0]] = Some(column);
+        }
+    }
+
+    warnings
+}
+
+fn empty_trailing_columns(circuit: &Circuit) -> Vec<LintWarning> {
+    let columns = circuit.moments.len();
+    let used = circuit
+        .moments
+        .iter()
+        .rposition(|moment| !moment.operations.is_empty())
+        .map_or(0, |column| column + 1);
+
+    if used == columns {
+        return Vec::new();
+    }
+
+    let message = match columns - used {
+        1 => format!("column {} at the end is empty", used),
+        _ => format!("columns {} to {} at the end are empty", used, columns - 1),
+    };
+
+    vec![LintWarning {
+        warning: Lint::EmptyTrailingColumns,
+        cells: (used..columns)
+            .flat_map(|column| (0..circuit.qubits).map(move |row| LintCell { row, column }))
+            .collect(),
+        message,
+    }]
+}
+
+// The cells of all qubits of the operation, from top to bottom
+fn cells(column: usize, operation: &Operation) -> Vec<LintCell> {
+    let mut rows = operation.qubits();
+    rows.sort_unstable();
+
+    rows.into_iter()
+        .map(|row| LintCell { row, column })
+        .collect()
+}
+
+// e.g. "H on qubit 0" or "controlled Z on qubits 0, 1, 2"
+pub fn describe(operation: &Operation) -> String {
+    let mut qubits = operation.qubits();
+    qubits.sort_unstable();
+    let name = match operation.controls.is_empty() {
+        true => operation.gate.name().to_string(),
+        false => format!("controlled {}", operation.gate.name()),
+    };
+
+    match qubits.as_slice() {
+        [qubit] => format!("{} on qubit {}", name, qubit),
+        _ => format!(
+            "{} on qubits {}",
+            name,
+            qubits
+                .iter()
+                .map(|qubit| qubit.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lint(grid: &[Vec<&str>]) -> Vec<LintWarning> {
+        lint_grid(grid, 10, &GateRegistry::default()).unwrap()
+    }
+
+    fn kinds(warnings: &[LintWarning]) -> Vec<Lint> {
+        warnings.iter().map(|warning| warning.warning).collect()
+    }
+
+    #[test]
+    fn test_clean_circuit() {
+        let grid = vec![vec!["H", "CNOT-1", "M"], vec!["I", "CNOT-2", "M"]];
+
+        assert_eq!(lint(&grid), Vec::new());
+    }
+
+    #[test]
+    fn test_redundant_pairs() {
+        let grid = vec![
+            vec!["H", "H", "X", "CNOT-1", "CNOT-1"],
+            vec!["X", "I", "X", "CNOT-2", "CNOT-2"],
+        ];
+
+        let warnings = lint(&grid);
+
+        assert_eq!(
+            kinds(&warnings),
+            vec![
+                Lint::RedundantPair,
+                Lint::RedundantPair,
+                Lint::RedundantPair
+            ]
+        );
+        assert_eq!(
+            warnings[0].message,
+            "H on qubit 0 in columns 0 and 1 cancel each other"
+        );
+        assert_eq!(
+            warnings[0].cells,
+            vec![
+                LintCell { row: 0, column: 0 },
+                LintCell { row: 0, column: 1 }
+            ]
+        );
+        assert_eq!(
+            warnings[1].message,
+            "X on qubit 1 in columns 0 and 2 cancel each other"
+        );
+        assert_eq!(warnings[2].cells.len(), 4);
+    }
+
+    #[test]
+    fn test_pairs_need_the_same_qubits() {
+        // The CNOTs are reversed, the H gates are interrupted by the CNOTs and S is not self-inverse
+        let grid = vec![
+            vec!["H", "CNOT-1", "CNOT-2", "H", "S", "S"],
+            vec!["X", "CNOT-2", "CNOT-1", "I", "I", "I"],
+        ];
+
+        assert!(!kinds(&lint(&grid)).contains(&Lint::RedundantPair));
+
+        // Three H gates leave one H
+        let grid = vec![vec!["H", "H", "H"]];
+        assert_eq!(kinds(&lint(&grid)), vec![Lint::RedundantPair]);
+
+        // The parts of a SWAP are interchangeable
+        let grid = vec![vec!["X", "SWAP-1", "SWAP-2"], vec!["X", "SWAP-2", "SWAP-1"]];
+        assert_eq!(kinds(&lint(&grid)), vec![Lint::RedundantPair]);
+    }
+
+    #[test]
+    fn test_no_effect_on_fresh_qubits() {
+        let grid = vec![
+            vec!["Z", "H", "Z", "CNOT-2", "I"],
+            vec!["I", "I", "I", "CNOT-1", "C"],
+            vec!["T", "RZ(pi/2)", "I", "I", "X"],
+        ];
+
+        let warnings = lint(&grid);
+
+        assert_eq!(kinds(&warnings), vec![Lint::NoEffect; 5]);
+        assert_eq!(
+            warnings[0].message,
+            "Z on qubit 0 in column 0 does nothing, qubit 0 is still |0>"
+        );
+        assert_eq!(warnings[2].cells, vec![LintCell { row: 2, column: 1 }]);
+        assert_eq!(
+            warnings[3].message,
+            "CNOT on qubits 0, 1 in column 3 does nothing, its control qubit 1 is still |0>"
+        );
+        assert_eq!(
+            warnings[4].message,
+            "controlled X on qubits 1, 2 in column 4 does nothing, its control qubit 1 is still |0>"
+        );
+
+        // The CNOT acts on a qubit in |+>, so it is not reported
+        let grid = vec![vec!["H", "CNOT-1"], vec!["I", "CNOT-2"]];
+        assert_eq!(lint(&grid), Vec::new());
+    }
+
+    #[test]
+    fn test_empty_trailing_columns() {
+        let grid = vec![vec!["H", "I", "I", "I"], vec!["I", "X", "I", "I"]];
+
+        let warnings = lint(&grid);
+
+        assert_eq!(kinds(&warnings), vec![Lint::EmptyTrailingColumns]);
+        assert_eq!(warnings[0].message, "columns 2 to 3 at the end are empty");
+        assert_eq!(warnings[0].cells.len(), 4);
+    }
+
+    #[test]
+    fn test_gate_after_measurement() {
+        let grid = vec![
+            vec!["H", "M", "H", "X", "I"],
+            vec!["H", "I", "I", "I", "X?c0"],
+        ];
+
+        let warnings = lint(&grid);
+
+        assert_eq!(kinds(&warnings), vec![Lint::GateAfterMeasurement]);
+        assert_eq!(
+            warnings[0].message,
+            "H on qubit 0 in column 2 acts on qubit 0 after it is measured in column 1"
+        );
+    }
+}
@@ -0,0 +1,325 @@
+// Numbers that summarise a circuit, to compare different solutions to the same problem
+// Nothing is simulated, the metrics only look at the gates:
+// - The depth is the number of layers when every gate is applied as soon as its qubits are free
+// - The T-count counts the non-Clifford gates that fault-tolerant devices make expensive:
+//   T and P or RZ by an odd multiple of pi/4, which is a T or T^dagger up to Clifford gates,
+//   and the Toffoli gates (CCNOT, a CNOT with a control marker etc.) with the 7 T gates of their decomposition
+// - The T-depth is the largest number of T layers on one path through the circuit, 3 for a Toffoli
+//   (Amy et al., "A meet-in-the-middle algorithm for fast synthesis of depth-optimal quantum circuits")
+// - Other gates with control markers, like a controlled T or RY, have no exact decomposition into Clifford
+//   and T gates, so they are not in the T-count but counted on their own
+// - The critical path is a longest chain of gates that each have to wait for the one before,
+//   it has as many gates as the circuit is deep
+// - The state vector memory is 16 bytes (a complex number of two f64) for each of the 2^n amplitudes
+
+use crate::simulation::circuit::{Circuit, Gate, Operation};
+use crate::simulation::circuit_validator::{validate_circuit, QuantumCircuitError};
+use crate::simulation::gate_registry::GateRegistry;
+use crate::simulation::lint::describe;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+
+const TOLERANCE: f64 = 1e-9;
+const BYTES_PER_AMPLITUDE_LOG2: usize = 4;
+const MEMORY_UNITS: [&str; 9] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB", "ZiB", "YiB"];
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Metrics {
+    // The number of qubits
+    pub width: usize,
+    // The number of columns of the grid, which can be more than the depth
+    pub columns: usize,
+    pub depth: usize,
+    // Every gate except identities, measurements included
+    pub gates: usize,
+    // The number of gates by name, e.g. "H", "CNOT", "M" or "controlled Z" for a gate with control markers
+    pub gate_counts: BTreeMap<String, usize>,
+    // Gates on two and on more than two qubits, control markers included
+    pub two_qubit_gates: usize,
+    pub multi_qubit_gates: usize,
+    pub t_count: usize,
+    pub t_depth: usize,
+    // Non-Clifford gates with control markers that are not in the T-count
+    pub uncounted_controlled_gates: usize,
+    pub critical_path: Vec<PathGate>,
+    pub state_vector_bytes: f64,
+    // The state vector memory in binary units, e.g. "256 MiB"
+    pub state_vector_memory: String,
+}
+
+// A gate on the critical path, e.g. { "column": 1, "gate": "CNOT on qubits 0, 1" }
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PathGate {
+    pub column: usize,
+    pub gate: String,
+}
+
+// Validate the grid and summarise the circuit
+pub fn grid_metrics(
+    grid: &[Vec<&str>],
+    max_qubits: usize,
+    gates: &GateRegistry,
+) -> Result<Metrics, QuantumCircuitError> {
+    let circuit = validate_circuit(grid, max_qubits, gates)?;
+    Ok(circuit_metrics(&circuit))
+}
+
+pub fn circuit_metrics(circuit: &Circuit) -> Metrics {
+    let mut gate_counts: BTreeMap<String, usize> = BTreeMap::new();
+    for operation in circuit.operations() {
+        let name = match operation.controls.is_empty() {
+            true => operation.gate.name().to_string(),
+            false => format!("controlled {}", operation.gate.name()),
+        };
+        *gate_counts.entry(name).or_insert(0) += 1;
+    }
+    let sizes: Vec<usize> = circuit
+        .operations()
+        .map(|operation| operation.qubits().len())
+        .collect();
+
+    let (depth, t_depth, critical_path) = longest_paths(circuit);
+    let exponent = circuit.qubits + BYTES_PER_AMPLITUDE_LOG2;
+
+    Metrics {
+        width: circuit.qubits,
+        columns: circuit.moments.len(),
+        depth,
+        gates: sizes.len(),
+        gate_counts,
+        two_qubit_gates: sizes.iter().filter(|&&size| size == 2).count(),
+        multi_qubit_gates: sizes.iter().filter(|&&size| size > 2).count(),
+        t_count: circuit
+            .operations()
+            .map(|operation| t_cost(operation).count)
+            .sum(),
+        t_depth,
+        uncounted_controlled_gates: circuit
+            .operations()
+            .filter(|operation| is_uncounted(operation))
+            .count(),
+        critical_path,
+        state_vector_bytes: 2_f64.powi(exponent as i32),
+        state_vector_memory: match MEMORY_UNITS.get(exponent / 10) {
+            Some(unit) => format!("{} {}", 1_u64 << (exponent % 10), unit),
+            None => format!("2^{} B", exponent),
+        },
+    }
+}
+
+// The T gates of an operation and the T layers they take
+struct TCost {
+    count: usize,
+    depth: usize,
+}
+
+const NO_T: TCost = TCost { count: 0, depth: 0 };
+const ONE_T: TCost = TCost { count: 1, depth: 1 };
+const TOFFOLI: TCost = TCost { count: 7, depth: 3 };
+
+fn t_cost(operation: &Operation) -> TCost {
+    match (operation.controls.len(), &operation.gate) {
+        (0, Gate::T) => ONE_T,
+        (0, Gate::P | Gate::RZ) if is_odd_quarter(operation.params[0]) => ONE_T,
+        // Anti-controls are controls between X gates, and a CCZ, CCY or controlled SWAP is a CCNOT
+        // between Clifford gates
+        (0, Gate::Ccnot)
+        | (1, Gate::Cnot | Gate::Cz | Gate::Swap)
+        | (2, Gate::X | Gate::Y | Gate::Z) => TOFFOLI,
+        _ => NO_T,
+    }
+}
+
+// A gate with control markers that is neither a Clifford gate nor in the T-count
+fn is_uncounted(operation: &Operation) -> bool {
+    let clifford = operation.controls.len() == 1
+        && matches!(operation.gate, Gate::I | Gate::X | Gate::Y | Gate::Z);
+
+    !operation.controls.is_empty() && !clifford && t_cost(operation).count == 0
+}
+
+// Whether the angle is an odd multiple of pi/4, which is a T or T^dagger up to Clifford gates
+fn is_odd_quarter(angle: f64) -> bool {
+    let quarters = angle / (PI / 4.0);
+    (quarters - quarters.round()).abs() < TOLERANCE && quarters.round() % 2.0 != 0.0
+}
+
+// The depth, T-depth and a critical path, with the same layers as Circuit::depth
+// Every gate remembers the gate it waits for longest, the critical path follows those back from the deepest gate
+fn longest_paths(circuit: &Circuit) -> (usize, usize, Vec<PathGate>) {
+    let mut layers = vec![0; circuit.qubits];
+    let mut t_layers = vec![0; circuit.qubits];
+    // The index in gates of the last gate on every qubit
+    let mut last: Vec<Option<usize>> = vec![None; circuit.qubits];
+    // Every gate with its column, layer and the gate before it on its longest path
+    let mut gates: Vec<(usize, &Operation, usize, Option<usize>)> = Vec::new();
+
+    for (column, moment) in circuit.moments.iter().enumerate() {
+        for operation in &moment.operations {
+            let qubits = operation.qubits();
+            // A classically controlled gate also waits for the measurement that writes its bit
+            let inputs: Vec<usize> = qubits
+                .iter()
+                .chain(operation.condition.iter())
+                .copied()
+                .collect();
+            let slowest = *inputs.iter().max_by_key(|&&qubit| layers[qubit]).unwrap();
+            let layer = 1 + layers[slowest];
+            let t_layer = inputs.iter().map(|&qubit| t_layers[qubit]).max().unwrap()
+                + t_cost(operation).depth;
+
+            gates.push((column, operation, layer, last[slowest]));
+            for &qubit in &qubits {
+                layers[qubit] = layer;
+                t_layers[qubit] = t_layer;
+                last[qubit] = Some(gates.len() - 1);
+            }
+        }
+    }
+
+    let mut critical_path: Vec<PathGate> = Vec::new();
+    let mut current = (0..gates.len()).rev().max_by_key(|&index| gates[index].2);
+    while let Some(index) = current {
+        let (column, operation, _, previous) = gates[index];
+        critical_path.push(PathGate {
+            column,
+            gate: describe(operation),
+        });
+        current = previous;
+    }
+    critical_path.reverse();
+
+    (
+        layers.into_iter().max().unwrap_or(0),
+        t_layers.into_iter().max().unwrap_or(0),
+        critical_path,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics(grid: &[Vec<&str>]) -> Metrics {
+        grid_metrics(grid, 10, &GateRegistry::default()).unwrap()
+    }
+
+    #[test]
+    fn test_metrics() {
+        let grid = vec![
+            vec!["H", "CNOT-1", "T", "I", "C", "M"],
+            vec!["T", "CNOT-2", "I", "P(-pi/4)", "I", "I"],
+            vec!["T", "I", "I", "RZ(pi/2)", "Z", "I"],
+        ];
+
+        let metrics = metrics(&grid);
+
+        assert_eq!(metrics.width, 3);
+        assert_eq!(metrics.columns, 6);
+        assert_eq!(metrics.depth, 5);
+        assert_eq!(metrics.gates, 9);
+        assert_eq!(
+            metrics.gate_counts,
+            BTreeMap::from([
+                ("CNOT".to_string(), 1),
+                ("H".to_string(), 1),
+                ("M".to_string(), 1),
+                ("P".to_string(), 1),
+                ("RZ".to_string(), 1),
+                ("T".to_string(), 3),
+                ("controlled Z".to_string(), 1),
+            ])
+        );
+        assert_eq!(metrics.two_qubit_gates, 2);
+        assert_eq!(metrics.multi_qubit_gates, 0);
+        assert_eq!(metrics.t_count, 4);
+        assert_eq!(metrics.t_depth, 2);
+        assert_eq!(metrics.state_vector_bytes, 128.0);
+        assert_eq!(metrics.state_vector_memory, "128 B");
+    }
+
+    #[test]
+    fn test_critical_path() {
+        let grid = vec![
+            vec!["H", "CNOT-1", "T", "I", "C", "M"],
+            vec!["T", "CNOT-2", "I", "P(-pi/4)", "I", "I"],
+            vec!["T", "I", "I", "RZ(pi/2)", "Z", "I"],
+        ];
+
+        let path: Vec<(usize, String)> = metrics(&grid)
+            .critical_path
+            .into_iter()
+            .map(|gate| (gate.column, gate.gate))
+            .collect();
+
+        assert_eq!(
+            path,
+            vec![
+                (0, "T on qubit 1".to_string()),
+                (1, "CNOT on qubits 0, 1".to_string()),
+                (2, "T on qubit 0".to_string()),
+                (4, "controlled Z on qubits 0, 2".to_string()),
+                (5, "M on qubit 0".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_t_gates() {
+        // Only T, T^dagger and odd multiples of pi/4 count, a classically controlled T does too
+        let grid = vec![
+            vec!["RZ(5*pi/4)", "P(pi/2)", "C", "M", "I"],
+            vec!["P(pi/4 + pi)", "RZ(0.3)", "T", "I", "T?c0"],
+        ];
+
+        let metrics = metrics(&grid);
+
+        assert_eq!(metrics.t_count, 3);
+        assert_eq!(metrics.t_depth, 2);
+        assert_eq!(metrics.gate_counts["controlled T"], 1);
+        assert_eq!(metrics.uncounted_controlled_gates, 1);
+    }
+
+    #[test]
+    fn test_toffoli_gates() {
+        let grid = vec![
+            vec!["CCNOT-1", "C", "O", "I", "C"],
+            vec!["CCNOT-2", "CNOT-1", "C", "C", "Z"],
+            vec!["CCNOT-3", "CNOT-2", "X", "H", "I"],
+        ];
+
+        let metrics = metrics(&grid);
+
+        assert_eq!(metrics.t_count, 21);
+        assert_eq!(metrics.t_depth, 9);
+        // The controlled H has no exact T-count, the controlled Z is a CZ
+        assert_eq!(metrics.uncounted_controlled_gates, 1);
+    }
+
+    #[test]
+    fn test_empty_circuit() {
+        let metrics = metrics(&[vec!["I", "I"], vec!["I", "I"]]);
+
+        assert_eq!(metrics.depth, 0);
+        assert_eq!(metrics.gates, 0);
+        assert_eq!(metrics.t_depth, 0);
+        assert!(metrics.critical_path.is_empty());
+    }
+
+    #[test]
+    fn test_state_vector_memory() {
+        let memory = |qubits: usize| {
+            circuit_metrics(&Circuit {
+                qubits,
+                moments: Vec::new(),
+            })
+            .state_vector_memory
+        };
+
+        assert_eq!(memory(6), "1 KiB");
+        assert_eq!(memory(24), "256 MiB");
+        assert_eq!(memory(30), "16 GiB");
+        assert_eq!(memory(100), "2^104 B");
+    }
+}
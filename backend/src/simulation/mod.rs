@@ -1,6 +1,26 @@
+pub mod circuit;
 mod circuit_parser;
 pub mod circuit_validator;
+pub mod density_matrix;
+pub mod entanglement;
+pub mod equivalence;
+mod expression_parser;
+pub mod gate_registry;
+pub mod linalg;
+pub mod lint;
+pub mod metrics;
+pub mod mps;
+pub mod noise;
+pub mod observable;
+pub mod optimizer;
+pub mod qasm;
 pub mod quantum_gate;
 pub mod quantum_state;
+pub mod routing;
+pub mod sampler;
+pub mod scheduler;
 pub mod simulator;
+pub mod stabilizer;
+pub mod transpiler;
+pub mod unitary;
 pub mod utils;
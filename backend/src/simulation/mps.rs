@@ -0,0 +1,635 @@
+// Matrix product state simulation for circuits with little entanglement
+// Qubit i is stored as a tensor A[left][bit][right], the amplitude of a basis state is the product of the matrices
+// A[bit] of all qubits, so the memory grows with the bond dimensions between the qubits instead of with 2^n
+// Gates on several qubits are applied by contracting their tensors, which are split again with SVDs that keep
+// at most max_bond_dimension singular values and drop the smallest while their weight is below the threshold
+
+use crate::simulation::density_matrix::DensityMatrix;
+use crate::simulation::linalg::svd;
+use crate::simulation::observable::{Pauli, PauliString};
+use crate::simulation::quantum_gate::QuantumGate;
+use ndarray::{Array2, Array3, Axis};
+use num::Complex;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+// Settings of the matrix product state simulation, given in the request as "mps"
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MpsOptions {
+    // The largest number of singular values kept between two neighbouring qubits
+    pub max_bond_dimension: usize,
+    // The largest weight (sum of squared singular values) that can be dropped in one SVD
+    pub truncation_threshold: f64,
+}
+
+impl Default for MpsOptions {
+    fn default() -> Self {
+        MpsOptions {
+            max_bond_dimension: 64,
+            truncation_threshold: 1e-10,
+        }
+    }
+}
+
+impl MpsOptions {
+    pub fn is_valid(&self) -> bool {
+        self.max_bond_dimension >= 1 && (0.0..1.0).contains(&self.truncation_threshold)
+    }
+}
+
+// The state is kept in mixed canonical form around the center: the tensors left of it are left-orthonormal
+// and the tensors right of it right-orthonormal, so the norm of the state is the norm of the center tensor
+// truncation_error is the total weight dropped by all SVDs so far, the fidelity with the exact state is at
+// least about 1 - truncation_error
+#[derive(Debug, Clone)]
+pub struct MatrixProductState {
+    tensors: Vec<Array3<Complex<f64>>>,
+    center: usize,
+    options: MpsOptions,
+    pub truncation_error: f64,
+}
+
+impl MatrixProductState {
+    // Create the product state of a list of bits, panics if there are no qubits or if the bits are not 0 or 1
+    pub fn new(bits: &[usize], options: MpsOptions) -> MatrixProductState {
+        if bits.is_empty() {
+            panic!("Number of qubits must be at least 1");
+        }
+
+        let tensors = bits
+            .iter()
+            .map(|&bit| {
+                if bit != 0 && bit != 1 {
+                    panic!("Bits must be 0 or 1");
+                }
+                let mut tensor = Array3::<Complex<f64>>::zeros((1, 2, 1));
+                tensor[[0, bit, 0]] = Complex::new(1.0, 0.0);
+                tensor
+            })
+            .collect();
+
+        MatrixProductState {
+            tensors,
+            center: 0,
+            options,
+            truncation_error: 0.0,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.tensors.len()
+    }
+
+    // The largest bond dimension between two neighbouring qubits
+    pub fn bond_dimension(&self) -> usize {
+        self.tensors
+            .iter()
+            .map(|tensor| tensor.dim().2)
+            .max()
+            .unwrap()
+    }
+
+    // Apply a QuantumGate to the given qubits, the first qubit in the list is the most significant bit of the gate
+    // Qubits that are not next to each other are brought together with SWAPs, which are undone afterwards
+    // Panics if the number of qubits does not match the gate size or a qubit is out of range
+    pub fn apply_gate_to_qubits(
+        mut self,
+        gate: &QuantumGate,
+        qubits: &[usize],
+    ) -> MatrixProductState {
+        if gate.size != qubits.len() {
+            panic!(
+                "Trying to apply a gate for {} qubits to {} qubits",
+                gate.size,
+                qubits.len()
+            )
+        }
+        if let Some(qubit) = qubits.iter().find(|&&qubit| qubit >= self.size()) {
+            panic!(
+                "Qubit {} is out of range for a state with {} qubits",
+                qubit,
+                self.size()
+            )
+        }
+
+        // A single-qubit unitary keeps the tensor orthonormal, so it can be applied anywhere
+        if let &[qubit] = qubits {
+            self.tensors[qubit] = apply_to_bits(&gate.matrix, &self.tensors[qubit]);
+            return self;
+        }
+
+        // Move qubits[j] to the position start + j by swapping neighbours, every qubit only moves to the left
+        let start = *qubits.iter().min().unwrap();
+        let mut positions: Vec<usize> = (0..self.size()).collect();
+        let mut swaps = vec![];
+        for (offset, qubit) in qubits.iter().enumerate() {
+            let mut position = positions.iter().position(|q| q == qubit).unwrap();
+            while position > start + offset {
+                self.apply_to_block(&QuantumGate::swap_gate().matrix, position - 1, 2);
+                positions.swap(position - 1, position);
+                swaps.push(position - 1);
+                position -= 1;
+            }
+        }
+
+        self.apply_to_block(&gate.matrix, start, qubits.len());
+
+        for &position in swaps.iter().rev() {
+            self.apply_to_block(&QuantumGate::swap_gate().matrix, position, 2);
+        }
+
+        self
+    }
+
+    // Apply a matrix to the neighbouring qubits start..start + count by contracting them into one tensor
+    // and splitting it again from left to right, which leaves the center on the last of the qubits
+    fn apply_to_block(&mut self, matrix: &Array2<Complex<f64>>, start: usize, count: usize) {
+        self.move_center_to(start);
+
+        let mut block = self.tensors[start].clone();
+        for tensor in &self.tensors[start + 1..start + count] {
+            block = contract(&block, tensor);
+        }
+        let mut block = apply_to_bits(matrix, &block);
+
+        for position in start..start + count - 1 {
+            let (left, dim, right) = block.dim();
+            let matrix = block
+                .into_shape((left * 2, dim / 2 * right))
+                .expect("block is stored contiguously");
+
+            let (u, values, v_dagger) = svd(&matrix);
+            let kept = self.truncate(&values);
+            let norm = values[..kept]
+                .iter()
+                .map(|value| value * value)
+                .sum::<f64>()
+                .sqrt();
+
+            self.tensors[position] = u
+                .slice(ndarray::s![.., ..kept])
+                .to_owned()
+                .into_shape((left, 2, kept))
+                .expect("tensor is stored contiguously");
+            block = Array2::from_shape_fn((kept, dim / 2 * right), |(row, column)| {
+                v_dagger[[row, column]] * values[row] / norm
+            })
+            .into_shape((kept, dim / 2, right))
+            .expect("block is stored contiguously");
+        }
+
+        self.tensors[start + count - 1] = block;
+        self.center = start + count - 1;
+    }
+
+    // The number of singular values to keep, adding the weight of the dropped ones to the truncation error
+    fn truncate(&mut self, values: &[f64]) -> usize {
+        let total: f64 = values.iter().map(|value| value * value).sum();
+        let mut kept = values.len().min(self.options.max_bond_dimension);
+        let mut dropped: f64 = values[kept..].iter().map(|value| value * value).sum();
+
+        while kept > 1
+            && (dropped + values[kept - 1] * values[kept - 1]) / total
+                <= self.options.truncation_threshold
+        {
+            kept -= 1;
+            dropped += values[kept] * values[kept];
+        }
+
+        self.truncation_error += dropped / total;
+        kept
+    }
+
+    // Move the center of the canonical form with SVDs, which are exact as no singular values are dropped
+    fn move_center_to(&mut self, position: usize) {
+        while self.center < position {
+            let (left, _, right) = self.tensors[self.center].dim();
+            let matrix = self.tensors[self.center]
+                .clone()
+                .into_shape((left * 2, right))
+                .expect("tensor is stored contiguously");
+            let (u, values, v_dagger) = svd(&matrix);
+            let kept = values.len();
+
+            self.tensors[self.center] = u
+                .into_shape((left, 2, kept))
+                .expect("tensor is stored contiguously");
+            let remainder = Array2::from_shape_fn((kept, right), |(row, column)| {
+                v_dagger[[row, column]] * values[row]
+            });
+            self.tensors[self.center + 1] =
+                multiply_left(&remainder, &self.tensors[self.center + 1]);
+            self.center += 1;
+        }
+
+        while self.center > position {
+            let (left, _, right) = self.tensors[self.center].dim();
+            let matrix = self.tensors[self.center]
+                .clone()
+                .into_shape((left, 2 * right))
+                .expect("tensor is stored contiguously");
+            let (u, values, v_dagger) = svd(&matrix);
+            let kept = values.len();
+
+            self.tensors[self.center] = v_dagger
+                .into_shape((kept, 2, right))
+                .expect("tensor is stored contiguously");
+            let remainder = Array2::from_shape_fn((left, kept), |(row, column)| {
+                u[[row, column]] * values[column]
+            });
+            self.tensors[self.center - 1] =
+                multiply_right(&self.tensors[self.center - 1], &remainder);
+            self.center -= 1;
+        }
+    }
+
+    // Apply a noise channel given by its Kraus operators to a qubit by sampling one of the operators,
+    // weighted by the probability it gives, and renormalising (a single quantum trajectory like the state vector)
+    pub fn apply_channel<R: Rng + ?Sized>(
+        mut self,
+        kraus_operators: &[QuantumGate],
+        qubit: usize,
+        rng: &mut R,
+    ) -> MatrixProductState {
+        self.move_center_to(qubit);
+
+        let sample = rng.gen::<f64>();
+        let mut cumulative_probability = 0.0;
+        let mut chosen: Option<(Array3<Complex<f64>>, f64)> = None;
+
+        for operator in kraus_operators {
+            let candidate = apply_to_bits(&operator.matrix, &self.tensors[qubit]);
+            let probability: f64 = candidate.iter().map(|value| value.norm_sqr()).sum();
+
+            if probability > 0.0 {
+                chosen = Some((candidate, probability));
+            }
+
+            cumulative_probability += probability;
+            if sample < cumulative_probability && chosen.is_some() {
+                break;
+            }
+        }
+
+        let (tensor, probability) = chosen.expect("a noise channel must have a non-zero operator");
+        self.tensors[qubit] = tensor.mapv(|value| value / probability.sqrt());
+        self
+    }
+
+    // Measure a qubit in the computational basis, the outcome is sampled from the norm of the center tensor
+    pub fn measure<R: Rng + ?Sized>(
+        mut self,
+        qubit: usize,
+        rng: &mut R,
+    ) -> (MatrixProductState, u8) {
+        self.move_center_to(qubit);
+
+        let tensor = &mut self.tensors[qubit];
+        let probability_of_one: f64 = tensor
+            .index_axis(Axis(1), 1)
+            .iter()
+            .map(|value| value.norm_sqr())
+            .sum();
+        let outcome: u8 = if rng.gen::<f64>() < probability_of_one {
+            1
+        } else {
+            0
+        };
+
+        let norm = if outcome == 1 {
+            probability_of_one.sqrt()
+        } else {
+            (1.0 - probability_of_one).sqrt()
+        };
+        for ((_, bit, _), value) in tensor.indexed_iter_mut() {
+            if bit == usize::from(outcome) {
+                *value /= norm;
+            } else {
+                *value = Complex::new(0.0, 0.0);
+            }
+        }
+
+        (self, outcome)
+    }
+
+    // Calculate <psi| O_0 x O_1 x ... |psi> for one 2x2 operator per qubit
+    fn product_expectation_value(&self, operators: &[Array2<Complex<f64>>]) -> Complex<f64> {
+        let environment = self
+            .tensors
+            .iter()
+            .zip(operators)
+            .fold(Array2::eye(1), |environment, (tensor, operator)| {
+                transfer(&environment, tensor, operator)
+            });
+
+        environment[[0, 0]]
+    }
+
+    // Calculate the expectation value of a Pauli string by contracting the state with itself from left to right
+    pub fn expectation_value(&self, pauli_string: &PauliString) -> f64 {
+        let operators: Vec<Array2<Complex<f64>>> = pauli_string
+            .paulis
+            .iter()
+            .map(|pauli| match pauli {
+                Pauli::I => QuantumGate::i_gate().matrix,
+                Pauli::X => QuantumGate::x_gate().matrix,
+                Pauli::Y => QuantumGate::y_gate().matrix,
+                Pauli::Z => QuantumGate::z_gate().matrix,
+            })
+            .collect();
+
+        pauli_string.coefficient * self.product_expectation_value(&operators).re
+    }
+
+    // Calculate the density matrix of the given qubits, the first listed qubit is the most significant bit
+    // Entry (i, j) is the expectation value of |j><i| on the qubits, which needs one contraction per entry
+    pub fn reduced_density_matrix(&self, qubits: &[usize]) -> DensityMatrix {
+        let dim = 1_usize << qubits.len();
+        let bit = |index: usize, k: usize| (index >> (qubits.len() - k - 1)) & 1;
+
+        let matrix = Array2::from_shape_fn((dim, dim), |(row, column)| {
+            let mut operators = vec![QuantumGate::i_gate().matrix; self.size()];
+            for (k, &qubit) in qubits.iter().enumerate() {
+                let mut operator = Array2::zeros((2, 2));
+                operator[[bit(column, k), bit(row, k)]] = Complex::new(1.0, 0.0);
+                operators[qubit] = operator;
+            }
+            self.product_expectation_value(&operators)
+        });
+
+        DensityMatrix { matrix }
+    }
+
+    // Calculate the density matrix of every qubit on its own, reusing the contractions of the qubits
+    // left and right of it, which is much faster than calling reduced_density_matrix for every qubit
+    pub fn single_qubit_density_matrices(&self) -> Vec<DensityMatrix> {
+        let identity = QuantumGate::i_gate().matrix;
+
+        let mut left_environments = vec![Array2::eye(1)];
+        for tensor in &self.tensors[..self.size() - 1] {
+            let environment = transfer(left_environments.last().unwrap(), tensor, &identity);
+            left_environments.push(environment);
+        }
+
+        let mut right_environments = vec![Array2::eye(1)];
+        for tensor in self.tensors[1..].iter().rev() {
+            let environment = transfer_right(right_environments.last().unwrap(), tensor);
+            right_environments.push(environment);
+        }
+        right_environments.reverse();
+
+        self.tensors
+            .iter()
+            .enumerate()
+            .map(|(qubit, tensor)| {
+                let matrix = Array2::from_shape_fn((2, 2), |(row, column)| {
+                    let mut operator = Array2::zeros((2, 2));
+                    operator[[column, row]] = Complex::new(1.0, 0.0);
+                    let environment = transfer(&left_environments[qubit], tensor, &operator);
+                    environment
+                        .iter()
+                        .zip(right_environments[qubit].iter())
+                        .map(|(value, right)| value * right)
+                        .sum()
+                });
+                DensityMatrix { matrix }
+            })
+            .collect()
+    }
+}
+
+// Apply a matrix to the bit index of a tensor, T'[l][i][r] = sum over j of M[i][j] T[l][j][r]
+fn apply_to_bits(
+    matrix: &Array2<Complex<f64>>,
+    tensor: &Array3<Complex<f64>>,
+) -> Array3<Complex<f64>> {
+    let (left, dim, right) = tensor.dim();
+
+    Array3::from_shape_fn((left, dim, right), |(l, i, r)| {
+        (0..dim).map(|j| matrix[[i, j]] * tensor[[l, j, r]]).sum()
+    })
+}
+
+// Contract two neighbouring tensors into one, the bits of the first become the most significant bits
+fn contract(first: &Array3<Complex<f64>>, second: &Array3<Complex<f64>>) -> Array3<Complex<f64>> {
+    let (left, first_dim, bond) = first.dim();
+    let (_, second_dim, right) = second.dim();
+
+    let first = first
+        .as_standard_layout()
+        .into_owned()
+        .into_shape((left * first_dim, bond))
+        .expect("tensor is stored contiguously");
+    let second = second
+        .as_standard_layout()
+        .into_owned()
+        .into_shape((bond, second_dim * right))
+        .expect("tensor is stored contiguously");
+
+    first
+        .dot(&second)
+        .into_shape((left, first_dim * second_dim, right))
+        .expect("product is stored contiguously")
+}
+
+// T'[l][i][r] = sum over k of M[l][k] T[k][i][r]
+fn multiply_left(
+    matrix: &Array2<Complex<f64>>,
+    tensor: &Array3<Complex<f64>>,
+) -> Array3<Complex<f64>> {
+    let (rows, columns) = matrix.dim();
+    contract(
+        &matrix.clone().into_shape((rows, 1, columns)).unwrap(),
+        tensor,
+    )
+}
+
+// T'[l][i][r] = sum over k of T[l][i][k] M[k][r]
+fn multiply_right(
+    tensor: &Array3<Complex<f64>>,
+    matrix: &Array2<Complex<f64>>,
+) -> Array3<Complex<f64>> {
+    let (rows, columns) = matrix.dim();
+    contract(
+        tensor,
+        &matrix.clone().into_shape((rows, 1, columns)).unwrap(),
+    )
+}
+
+// Extend the contraction of the state with itself by one tensor from the left,
+// E'[c][d] = sum over s, t of O[t][s] A[s]^T E conj(A[t]), with E[a][b] indexed by the ket and bra bonds
+fn transfer(
+    environment: &Array2<Complex<f64>>,
+    tensor: &Array3<Complex<f64>>,
+    operator: &Array2<Complex<f64>>,
+) -> Array2<Complex<f64>> {
+    let right = tensor.dim().2;
+    let mut result = Array2::<Complex<f64>>::zeros((right, right));
+
+    for s in 0..2 {
+        let ket = tensor.index_axis(Axis(1), s);
+        let half = ket.t().dot(environment);
+        for t in 0..2 {
+            let weight = operator[[t, s]];
+            if weight.norm() == 0.0 {
+                continue;
+            }
+            let bra = tensor.index_axis(Axis(1), t).mapv(|value| value.conj());
+            result = result + half.dot(&bra) * weight;
+        }
+    }
+
+    result
+}
+
+// Extend the contraction of the state with itself by one tensor from the right,
+// E'[a][b] = sum over s of A[s] E A[s]^dagger
+fn transfer_right(
+    environment: &Array2<Complex<f64>>,
+    tensor: &Array3<Complex<f64>>,
+) -> Array2<Complex<f64>> {
+    let left = tensor.dim().0;
+    let mut result = Array2::<Complex<f64>>::zeros((left, left));
+
+    for s in 0..2 {
+        let ket = tensor.index_axis(Axis(1), s);
+        result = result
+            + ket
+                .dot(environment)
+                .dot(&ket.t().mapv(|value| value.conj()));
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::observable::Observable;
+    use crate::simulation::quantum_state::QuantumState;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn assert_matrix_close(actual: &Array2<Complex<f64>>, expected: &Array2<Complex<f64>>) {
+        for (a, b) in actual.iter().zip(expected.iter()) {
+            assert!((a - b).norm() < 1e-9, "{} != {}", actual, expected);
+        }
+    }
+
+    // Gates on neighbouring and distant qubits, in both orders, and a three-qubit gate
+    fn gates() -> Vec<(QuantumGate, Vec<usize>)> {
+        vec![
+            (QuantumGate::h_gate(), vec![0]),
+            (QuantumGate::ry(0.7), vec![3]),
+            (QuantumGate::cnot_gate(), vec![0, 1]),
+            (QuantumGate::cnot_gate(), vec![3, 1]),
+            (QuantumGate::rx(1.1), vec![2]),
+            (QuantumGate::cz_gate(), vec![0, 3]),
+            (QuantumGate::t_gate(), vec![1]),
+            (QuantumGate::ccnot_gate(), vec![3, 0, 2]),
+            (QuantumGate::swap_gate(), vec![2, 0]),
+            (QuantumGate::h_gate(), vec![2]),
+        ]
+    }
+
+    #[test]
+    fn test_matches_state_vector() {
+        let (state, mps) = gates().iter().fold(
+            (
+                QuantumState::new(&[0, 0, 0, 0]),
+                MatrixProductState::new(&[0, 0, 0, 0], MpsOptions::default()),
+            ),
+            |(state, mps), (gate, qubits)| {
+                (
+                    state.apply_gate_to_qubits(gate, qubits),
+                    mps.apply_gate_to_qubits(gate, qubits),
+                )
+            },
+        );
+
+        assert!(mps.truncation_error < 1e-12);
+        for qubits in [vec![1], vec![0, 3], vec![2, 1]] {
+            assert_matrix_close(
+                &mps.reduced_density_matrix(&qubits).matrix,
+                &state.reduced_density_matrix(&qubits).matrix,
+            );
+        }
+        for (qubit, density_matrix) in mps.single_qubit_density_matrices().iter().enumerate() {
+            assert_matrix_close(
+                &density_matrix.matrix,
+                &state.reduced_density_matrix(&[qubit]).matrix,
+            );
+        }
+
+        let observable = Observable::parse("0.5*XZIY + ZZZZ - IXIX").unwrap();
+        assert!(
+            (observable.expectation_value(&mps) - observable.expectation_value(&state)).abs()
+                < 1e-9
+        );
+    }
+
+    #[test]
+    fn test_measurement_of_bell_state() {
+        let mut rng = StdRng::seed_from_u64(4);
+
+        for _ in 0..10 {
+            let mps = MatrixProductState::new(&[0, 0, 0], MpsOptions::default())
+                .apply_gate_to_qubits(&QuantumGate::h_gate(), &[0])
+                .apply_gate_to_qubits(&QuantumGate::cnot_gate(), &[0, 2]);
+
+            let (mps, first) = mps.measure(0, &mut rng);
+            let (mps, second) = mps.measure(2, &mut rng);
+            let (_, third) = mps.measure(1, &mut rng);
+            assert_eq!(first, second);
+            assert_eq!(third, 0);
+        }
+    }
+
+    #[test]
+    fn test_bond_dimension_is_limited() {
+        let options = MpsOptions {
+            max_bond_dimension: 2,
+            truncation_threshold: 0.0,
+        };
+        let mut mps = MatrixProductState::new(&[0; 6], options);
+        let mut state = QuantumState::new(&[0; 6]);
+        for qubit in 0..6 {
+            mps = mps.apply_gate_to_qubits(&QuantumGate::h_gate(), &[qubit]);
+            state = state.apply_gate_to_qubits(&QuantumGate::h_gate(), &[qubit]);
+        }
+        // Entangle the left half with the right half more than a bond dimension of 2 can hold
+        for (first, second) in [(2, 3), (1, 4), (0, 5)] {
+            mps = mps.apply_gate_to_qubits(&QuantumGate::rz(1.3).controlled(1), &[first, second]);
+            state =
+                state.apply_gate_to_qubits(&QuantumGate::rz(1.3).controlled(1), &[first, second]);
+            mps = mps.apply_gate_to_qubits(&QuantumGate::ry(0.4), &[first]);
+            state = state.apply_gate_to_qubits(&QuantumGate::ry(0.4), &[first]);
+        }
+
+        assert!(mps.bond_dimension() <= 2);
+        assert!(mps.truncation_error > 1e-6);
+
+        // The state stays normalised after truncation
+        let trace: Complex<f64> = mps.reduced_density_matrix(&[0]).matrix.diag().sum();
+        assert!((trace.re - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_long_chain() {
+        // A GHZ state on 50 qubits only needs a bond dimension of 2
+        let n = 50;
+        let mut mps = MatrixProductState::new(&vec![0; n], MpsOptions::default())
+            .apply_gate_to_qubits(&QuantumGate::h_gate(), &[0]);
+        for qubit in 1..n {
+            mps = mps.apply_gate_to_qubits(&QuantumGate::cnot_gate(), &[qubit - 1, qubit]);
+        }
+
+        assert_eq!(mps.bond_dimension(), 2);
+        let word = format!("Z{}Z", "I".repeat(n - 2));
+        let observable = Observable::parse(&word).unwrap();
+        assert!((observable.expectation_value(&mps) - 1.0).abs() < 1e-9);
+        let density_matrices = mps.single_qubit_density_matrices();
+        assert!((density_matrices[n - 1].matrix[[1, 1]].re - 0.5).abs() < 1e-9);
+    }
+}
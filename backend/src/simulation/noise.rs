@@ -0,0 +1,160 @@
+use crate::simulation::quantum_gate::QuantumGate;
+use ndarray::arr2;
+use num::Complex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// The kinds of single-qubit noise channels that can be applied after a gate
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelKind {
+    BitFlip,
+    PhaseFlip,
+    Depolarizing,
+    AmplitudeDamping,
+    PhaseDamping,
+}
+
+// A noise channel with its error rate, e.g. { "channel": "bit_flip", "probability": 0.1 }
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NoiseChannel {
+    pub channel: ChannelKind,
+    pub probability: f64,
+}
+
+// Noise applied after every gate to each qubit the gate acts on
+// Channels listed for a gate name (e.g. "H", "CNOT", "RX") replace the global channels for that gate
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NoiseModel {
+    #[serde(default)]
+    pub global: Vec<NoiseChannel>,
+    #[serde(default)]
+    pub gates: HashMap<String, Vec<NoiseChannel>>,
+}
+
+impl NoiseModel {
+    // The channels to apply after a gate with the given name
+    pub fn channels_for(&self, gate_name: &str) -> &[NoiseChannel] {
+        self.gates.get(gate_name).unwrap_or(&self.global)
+    }
+
+    // All error rates must be probabilities
+    pub fn is_valid(&self) -> bool {
+        self.global
+            .iter()
+            .chain(self.gates.values().flatten())
+            .all(|channel| (0.0..=1.0).contains(&channel.probability))
+    }
+}
+
+impl NoiseChannel {
+    // The Kraus operators of the channel, the channel maps rho to the sum of K rho K^dagger
+    pub fn kraus_operators(&self) -> Vec<QuantumGate> {
+        let p = self.probability;
+        let scale = |gate: QuantumGate, factor: f64| QuantumGate {
+            matrix: gate.matrix.mapv(|value| value * factor),
+            size: gate.size,
+        };
+
+        match self.channel {
+            ChannelKind::BitFlip => vec![
+                scale(QuantumGate::i_gate(), (1.0 - p).sqrt()),
+                scale(QuantumGate::x_gate(), p.sqrt()),
+            ],
+            ChannelKind::PhaseFlip => vec![
+                scale(QuantumGate::i_gate(), (1.0 - p).sqrt()),
+                scale(QuantumGate::z_gate(), p.sqrt()),
+            ],
+            // With probability p the qubit is replaced by the maximally mixed state
+            ChannelKind::Depolarizing => vec![
+                scale(QuantumGate::i_gate(), (1.0 - 3.0 * p / 4.0).sqrt()),
+                scale(QuantumGate::x_gate(), (p / 4.0).sqrt()),
+                scale(QuantumGate::y_gate(), (p / 4.0).sqrt()),
+                scale(QuantumGate::z_gate(), (p / 4.0).sqrt()),
+            ],
+            ChannelKind::AmplitudeDamping => vec![
+                single_qubit_operator([[1.0, 0.0], [0.0, (1.0 - p).sqrt()]]),
+                single_qubit_operator([[0.0, p.sqrt()], [0.0, 0.0]]),
+            ],
+            ChannelKind::PhaseDamping => vec![
+                single_qubit_operator([[1.0, 0.0], [0.0, (1.0 - p).sqrt()]]),
+                single_qubit_operator([[0.0, 0.0], [0.0, p.sqrt()]]),
+            ],
+        }
+    }
+}
+
+fn single_qubit_operator(values: [[f64; 2]; 2]) -> QuantumGate {
+    QuantumGate {
+        matrix: arr2(&values).mapv(|value| Complex::new(value, 0.0)),
+        size: 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::Array2;
+
+    // The Kraus operators of a channel must satisfy sum of K^dagger K = I
+    #[test]
+    fn test_kraus_operators_are_complete() {
+        let kinds = [
+            ChannelKind::BitFlip,
+            ChannelKind::PhaseFlip,
+            ChannelKind::Depolarizing,
+            ChannelKind::AmplitudeDamping,
+            ChannelKind::PhaseDamping,
+        ];
+
+        for channel in kinds {
+            let operators = NoiseChannel {
+                channel,
+                probability: 0.3,
+            }
+            .kraus_operators();
+
+            let sum =
+                operators
+                    .iter()
+                    .fold(Array2::<Complex<f64>>::zeros((2, 2)), |acc, operator| {
+                        acc + operator
+                            .matrix
+                            .t()
+                            .mapv(|value| value.conj())
+                            .dot(&operator.matrix)
+                    });
+
+            for (value, expected) in sum.iter().zip(Array2::<Complex<f64>>::eye(2).iter()) {
+                assert!((value - expected).norm() < 1e-12, "{:?}", channel);
+            }
+        }
+    }
+
+    #[test]
+    fn test_channels_for_gate() {
+        let noise: NoiseModel = serde_json::from_str(
+            r#"{
+                "global": [{ "channel": "depolarizing", "probability": 0.01 }],
+                "gates": { "H": [{ "channel": "bit_flip", "probability": 0.2 }] }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(noise.channels_for("H")[0].channel, ChannelKind::BitFlip);
+        assert_eq!(
+            noise.channels_for("X")[0].channel,
+            ChannelKind::Depolarizing
+        );
+        assert!(noise.is_valid());
+
+        let invalid = NoiseModel {
+            global: vec![NoiseChannel {
+                channel: ChannelKind::BitFlip,
+                probability: 1.5,
+            }],
+            gates: HashMap::new(),
+        };
+        assert!(!invalid.is_valid());
+    }
+}
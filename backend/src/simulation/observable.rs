@@ -0,0 +1,437 @@
+// Observables written as sums of Pauli strings, e.g. "ZZI" or "0.5*XIX + YYZ"
+// Letter i of a Pauli string acts on qubit i (row i of the grid), so "XZ" is X on qubit 0 and Z on qubit 1
+// A coefficient is any expression accepted by the angle parser, e.g. "-0.5*ZZ" or "pi/4*XX"
+
+use crate::simulation::circuit::Circuit;
+use crate::simulation::circuit_validator::{validate_circuit, QuantumCircuitError};
+use crate::simulation::density_matrix::DensityMatrix;
+use crate::simulation::expression_parser::parse_expression;
+use crate::simulation::gate_registry::GateRegistry;
+use crate::simulation::mps::MatrixProductState;
+use crate::simulation::quantum_state::QuantumState;
+use crate::simulation::simulator::{
+    run_operations, SimulationMode, SimulationOptions, SimulationState,
+};
+use crate::simulation::stabilizer::Tableau;
+use num::Complex;
+use rand::Rng;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pauli {
+    I,
+    X,
+    Y,
+    Z,
+}
+
+// A tensor product of Pauli operators with a real coefficient
+#[derive(Debug, Clone, PartialEq)]
+pub struct PauliString {
+    pub coefficient: f64,
+    pub paulis: Vec<Pauli>,
+}
+
+// A Hermitian observable as a sum of Pauli strings on the same number of qubits
+#[derive(Debug, Clone, PartialEq)]
+pub struct Observable {
+    pub terms: Vec<PauliString>,
+}
+
+impl PauliString {
+    // Parse a word of Pauli letters such as "XIZ", returns None if it has another letter
+    pub fn parse(word: &str, coefficient: f64) -> Option<PauliString> {
+        let paulis = word
+            .chars()
+            .map(|letter| match letter {
+                'I' => Some(Pauli::I),
+                'X' => Some(Pauli::X),
+                'Y' => Some(Pauli::Y),
+                'Z' => Some(Pauli::Z),
+                _ => None,
+            })
+            .collect::<Option<Vec<Pauli>>>()?;
+
+        if paulis.is_empty() {
+            return None;
+        }
+
+        Some(PauliString {
+            coefficient,
+            paulis,
+        })
+    }
+
+    // The bits flipped by the X and Y operators, the bits whose value gives a sign (Y and Z),
+    // and the number of Y operators, using the state index order where qubit 0 is the most significant bit
+    fn masks(&self) -> (usize, usize, usize) {
+        let no_of_qubits = self.paulis.len();
+        let mut flip_mask = 0;
+        let mut sign_mask = 0;
+        let mut no_of_y = 0;
+
+        for (qubit, pauli) in self.paulis.iter().enumerate() {
+            let bit = 1 << (no_of_qubits - qubit - 1);
+            match pauli {
+                Pauli::I => (),
+                Pauli::X => flip_mask |= bit,
+                Pauli::Y => {
+                    flip_mask |= bit;
+                    sign_mask |= bit;
+                    no_of_y += 1;
+                }
+                Pauli::Z => sign_mask |= bit,
+            }
+        }
+
+        (flip_mask, sign_mask, no_of_y)
+    }
+
+    // The factor of P|index> = factor |index ^ flip_mask>, since X|b> = |1-b>, Z|b> = (-1)^b |b>
+    // and Y|b> = i (-1)^b |1-b>, without the coefficient
+    fn factor(index: usize, sign_mask: usize, no_of_y: usize) -> Complex<f64> {
+        let phase = match no_of_y % 4 {
+            0 => Complex::new(1.0, 0.0),
+            1 => Complex::new(0.0, 1.0),
+            2 => Complex::new(-1.0, 0.0),
+            _ => Complex::new(0.0, -1.0),
+        };
+
+        if (index & sign_mask).count_ones() % 2 == 1 {
+            -phase
+        } else {
+            phase
+        }
+    }
+
+    // Apply the Pauli string with its coefficient to a state without building its matrix
+    pub fn apply(&self, state: &QuantumState) -> QuantumState {
+        let (flip_mask, sign_mask, no_of_y) = self.masks();
+        let mut col = state.col.clone();
+
+        for (index, amplitude) in state.col.iter().enumerate() {
+            col[[index ^ flip_mask, 0]] =
+                amplitude * Self::factor(index, sign_mask, no_of_y) * self.coefficient;
+        }
+
+        QuantumState { col }
+    }
+
+    // Calculate <psi|P|psi>
+    pub fn expectation_value(&self, state: &QuantumState) -> f64 {
+        let applied = self.apply(state);
+
+        state
+            .col
+            .iter()
+            .zip(applied.col.iter())
+            .map(|(amplitude, applied)| amplitude.conj() * applied)
+            .sum::<Complex<f64>>()
+            .re
+    }
+
+    // Calculate Tr(P rho) = sum over j of factor(j) rho[j][j ^ flip_mask]
+    pub fn expectation_value_of_density_matrix(&self, density_matrix: &DensityMatrix) -> f64 {
+        let (flip_mask, sign_mask, no_of_y) = self.masks();
+
+        (0..density_matrix.matrix.nrows())
+            .map(|index| {
+                Self::factor(index, sign_mask, no_of_y)
+                    * density_matrix.matrix[[index, index ^ flip_mask]]
+            })
+            .sum::<Complex<f64>>()
+            .re
+            * self.coefficient
+    }
+}
+
+impl Observable {
+    // Parse a sum of Pauli strings such as "0.5*XIX + YYZ - 2*ZZI"
+    // Returns None if a term is invalid or the terms act on a different number of qubits
+    pub fn parse(input: &str) -> Option<Observable> {
+        let input: String = input.chars().filter(|c| !c.is_whitespace()).collect();
+
+        // A term ends with its Pauli word, so a sign right after a Pauli letter starts the next term
+        let mut terms: Vec<(f64, String)> = Vec::new();
+        let mut current = String::new();
+        let mut sign = 1.0;
+        for c in input.chars() {
+            if (c == '+' || c == '-') && current.ends_with(['I', 'X', 'Y', 'Z']) {
+                terms.push((sign, current.clone()));
+                current.clear();
+                sign = if c == '-' { -1.0 } else { 1.0 };
+            } else {
+                current.push(c);
+            }
+        }
+        terms.push((sign, current));
+
+        let terms = terms
+            .into_iter()
+            .map(|(sign, term)| {
+                let word_start = term
+                    .rfind(|c| !matches!(c, 'I' | 'X' | 'Y' | 'Z'))
+                    .map_or(0, |i| i + 1);
+                let (coefficient, word) = term.split_at(word_start);
+                let coefficient = match coefficient.strip_suffix('*').unwrap_or(coefficient) {
+                    "" => 1.0,
+                    "-" => -1.0,
+                    "+" => 1.0,
+                    expression => parse_expression(expression)?,
+                };
+
+                PauliString::parse(word, sign * coefficient)
+            })
+            .collect::<Option<Vec<PauliString>>>()?;
+
+        let no_of_qubits = terms[0].paulis.len();
+        if terms.iter().any(|term| term.paulis.len() != no_of_qubits) {
+            return None;
+        }
+
+        Some(Observable { terms })
+    }
+
+    pub fn no_of_qubits(&self) -> usize {
+        self.terms[0].paulis.len()
+    }
+
+    // The expectation value of the observable for a state of the simulation
+    pub fn expectation_value<S: SimulationState>(&self, state: &S) -> f64 {
+        self.terms
+            .iter()
+            .map(|term| state.expectation_value(term))
+            .sum()
+    }
+}
+
+// Simulate the circuit and calculate the expectation values of the observables after every step
+// Returns one list of values per step, or only the values for the final state if per_step is false
+pub fn circuit_expectation_values<R: Rng + ?Sized>(
+    incoming_data: Vec<Vec<&str>>,
+    max_qubits: usize,
+    gates: &GateRegistry,
+    observables: &[String],
+    options: &SimulationOptions,
+    per_step: bool,
+    rng: &mut R,
+) -> Result<Vec<Vec<f64>>, QuantumCircuitError> {
+    let circuit = validate_circuit(&incoming_data, max_qubits, gates)?;
+    options.validate(&circuit)?;
+
+    let observables = observables
+        .iter()
+        .map(|observable| {
+            Observable::parse(observable)
+                .filter(|observable| observable.no_of_qubits() == circuit.qubits)
+                .ok_or(QuantumCircuitError::InvalidObservable)
+        })
+        .collect::<Result<Vec<Observable>, QuantumCircuitError>>()?;
+    if observables.is_empty() {
+        return Err(QuantumCircuitError::InvalidObservable);
+    }
+
+    let bits = vec![0_usize; circuit.qubits];
+    let values = match options.simulation_mode {
+        SimulationMode::StateVector => run_observables(
+            &circuit,
+            QuantumState::new(&bits),
+            &observables,
+            options,
+            per_step,
+            rng,
+        ),
+        SimulationMode::DensityMatrix => run_observables(
+            &circuit,
+            DensityMatrix::new(&bits),
+            &observables,
+            options,
+            per_step,
+            rng,
+        ),
+        SimulationMode::Stabilizer => run_observables(
+            &circuit,
+            Tableau::new(bits.len()),
+            &observables,
+            options,
+            per_step,
+            rng,
+        ),
+        SimulationMode::Mps => run_observables(
+            &circuit,
+            MatrixProductState::new(&bits, options.mps),
+            &observables,
+            options,
+            per_step,
+            rng,
+        ),
+    };
+
+    Ok(values)
+}
+
+fn run_observables<S: SimulationState, R: Rng + ?Sized>(
+    circuit: &Circuit,
+    state: S,
+    observables: &[Observable],
+    options: &SimulationOptions,
+    per_step: bool,
+    rng: &mut R,
+) -> Vec<Vec<f64>> {
+    let mut values: Vec<Vec<f64>> = Vec::new();
+    let evaluate = |state: &S| {
+        observables
+            .iter()
+            .map(|observable| observable.expectation_value(state))
+            .collect()
+    };
+
+    let (final_state, _) = run_operations(
+        circuit,
+        state,
+        options.noise.as_ref(),
+        rng,
+        |_, state, _, _| {
+            if per_step {
+                values.push(evaluate(state));
+            }
+        },
+    );
+
+    if !per_step {
+        values.push(evaluate(&final_state));
+    }
+
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::quantum_gate::QuantumGate;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-12,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_parse_observable() {
+        let observable = Observable::parse("0.5*XIX + YYZ - pi*ZII").unwrap();
+
+        assert_eq!(observable.terms.len(), 3);
+        assert_eq!(observable.terms[0].coefficient, 0.5);
+        assert_eq!(
+            observable.terms[0].paulis,
+            vec![Pauli::X, Pauli::I, Pauli::X]
+        );
+        assert_eq!(observable.terms[1].coefficient, 1.0);
+        assert_close(observable.terms[2].coefficient, -std::f64::consts::PI);
+        assert_eq!(Observable::parse("-ZZ").unwrap().terms[0].coefficient, -1.0);
+
+        assert_eq!(Observable::parse(""), None);
+        assert_eq!(Observable::parse("XA"), None);
+        assert_eq!(Observable::parse("XX + Z"), None);
+        assert_eq!(Observable::parse("theta*XX"), None);
+    }
+
+    // Test that applying a Pauli string matches applying its gates one by one
+    #[test]
+    fn test_apply_matches_gates() {
+        let state = QuantumState::new(&[0, 1, 0])
+            .apply_gate_to_qubits(&QuantumGate::h_gate(), &[0])
+            .apply_gate_to_qubits(&QuantumGate::rx(0.4), &[2]);
+
+        let applied = PauliString::parse("YZX", 2.0).unwrap().apply(&state);
+        let expected = state
+            .apply_gate_to_qubits(&QuantumGate::y_gate(), &[0])
+            .apply_gate_to_qubits(&QuantumGate::z_gate(), &[1])
+            .apply_gate_to_qubits(&QuantumGate::x_gate(), &[2]);
+
+        for (a, b) in applied.col.iter().zip(expected.col.iter()) {
+            assert!((a - b * 2.0).norm() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_bell_state_expectation_values() {
+        let state = QuantumState::new(&[0, 0])
+            .apply_gate_to_qubits(&QuantumGate::h_gate(), &[0])
+            .apply_gate_to_qubits(&QuantumGate::cnot_gate(), &[0, 1]);
+        let density_matrix = DensityMatrix::from_state(&state);
+
+        for (input, expected) in [
+            ("ZZ", 1.0),
+            ("XX", 1.0),
+            ("YY", -1.0),
+            ("ZI", 0.0),
+            ("0.5*XX - 2*YY", 2.5),
+        ] {
+            let observable = Observable::parse(input).unwrap();
+            assert_close(observable.expectation_value(&state), expected);
+            assert_close(observable.expectation_value(&density_matrix), expected);
+        }
+    }
+
+    #[test]
+    fn test_circuit_expectation_values() {
+        let grid = vec![vec!["H", "S"]];
+        let observables = vec!["X".to_string(), "Y".to_string(), "Z".to_string()];
+
+        let values = circuit_expectation_values(
+            grid.clone(),
+            6,
+            &GateRegistry::default(),
+            &observables,
+            &SimulationOptions::default(),
+            true,
+            &mut StdRng::seed_from_u64(0),
+        )
+        .unwrap();
+
+        assert_eq!(values.len(), 3);
+        assert_close(values[0][2], 1.0);
+        assert_close(values[1][0], 1.0);
+        assert_close(values[2][1], 1.0);
+
+        let options = SimulationOptions {
+            simulation_mode: SimulationMode::DensityMatrix,
+            ..Default::default()
+        };
+        let values = circuit_expectation_values(
+            grid,
+            6,
+            &GateRegistry::default(),
+            &observables,
+            &options,
+            false,
+            &mut StdRng::seed_from_u64(0),
+        )
+        .unwrap();
+        assert_eq!(values.len(), 1);
+        assert_close(values[0][1], 1.0);
+    }
+
+    #[test]
+    fn test_invalid_observable() {
+        let grid = vec![vec!["H"], vec!["I"]];
+
+        assert_eq!(
+            circuit_expectation_values(
+                grid,
+                6,
+                &GateRegistry::default(),
+                &["ZZZ".to_string()],
+                &SimulationOptions::default(),
+                false,
+                &mut StdRng::seed_from_u64(0),
+            ),
+            Err(QuantumCircuitError::InvalidObservable)
+        );
+    }
+}
@@ -0,0 +1,353 @@
+// Rewrite a circuit into a smaller one with the same unitary, up to global phase
+// The passes are repeated until the circuit stops shrinking:
+// - Inverse pairs: a self-inverse gate and the same gate on the same qubits later on are both removed
+// - Fusion: consecutive single-qubit gates on a qubit are multiplied into one gate,
+//   written as a named gate if it is one, otherwise as P or U3, and left out if it is the identity
+// Both passes look past gates that commute with the gate they move, which are gates that are diagonal
+// on the shared qubits, e.g. a Z moves through the control of a CNOT but not through its target
+// Finally the columns without gates are dropped, every other gate stays in its column or moves to a later one
+
+use crate::simulation::circuit::{Circuit, Gate, Moment, Operation};
+use crate::simulation::circuit_validator::{validate_circuit, QuantumCircuitError};
+use crate::simulation::equivalence::{compare_rewritten, Comparison};
+use crate::simulation::gate_registry::GateRegistry;
+use crate::simulation::linalg::{equal_up_to_phase, normalize_angle, u3_angles};
+use ndarray::Array2;
+use num::Complex;
+use serde::Serialize;
+
+const TOLERANCE: f64 = 1e-9;
+
+#[derive(Debug, Serialize)]
+pub struct Optimization {
+    pub circuit_matrix: Vec<Vec<String>>,
+    pub gates_before: usize,
+    pub gates_after: usize,
+    pub depth_before: usize,
+    pub depth_after: usize,
+    // The optimised circuit compared with the original, only for circuits that have a unitary
+    // and at most max_unitary_qubits rows
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub equivalence: Option<Comparison>,
+}
+
+// Validate and optimise the grid and check the result against the original if it is small enough
+pub fn optimize_grid(
+    grid: &[Vec<&str>],
+    max_qubits: usize,
+    max_unitary_qubits: usize,
+    gates: &GateRegistry,
+) -> Result<Optimization, QuantumCircuitError> {
+    let circuit = validate_circuit(grid, max_qubits, gates)?;
+    let optimized = optimize_circuit(&circuit);
+    let circuit_matrix = optimized.to_grid();
+
+    let equivalence = compare_rewritten(grid, &circuit_matrix, max_unitary_qubits, gates)?;
+
+    Ok(Optimization {
+        gates_before: gate_count(&circuit),
+        gates_after: gate_count(&optimized),
+        depth_before: circuit.depth(),
+        depth_after: optimized.depth(),
+        circuit_matrix,
+        equivalence,
+    })
+}
+
+pub fn optimize_circuit(circuit: &Circuit) -> Circuit {
+    // Every operation with its column, identity gates do nothing even when classically controlled
+    let mut operations: Vec<(usize, Operation)> = circuit
+        .moments
+        .iter()
+        .enumerate()
+        .flat_map(|(column, moment)| {
+            moment
+                .operations
+                .iter()
+                .map(move |operation| (column, operation.clone()))
+        })
+        .filter(|(_, operation)| operation.gate != Gate::I)
+        .collect();
+
+    loop {
+        let count = operations.len();
+        operations = fuse_single_qubit_gates(cancel_inverse_pairs(operations));
+        if operations.len() == count {
+            break;
+        }
+    }
+
+    let mut moments = vec![Moment::default(); circuit.moments.len()];
+    for (column, operation) in operations {
+        moments[column].operations.push(operation);
+    }
+    moments.retain(|moment| !moment.operations.is_empty());
+
+    Circuit {
+        qubits: circuit.qubits,
+        moments,
+    }
+}
+
+fn gate_count(circuit: &Circuit) -> usize {
+    circuit
+        .operations()
+        .filter(|operation| operation.gate != Gate::I)
+        .count()
+}
+
+// Remove pairs of operations that cancel, with only commuting operations on their qubits in between
+fn cancel_inverse_pairs(operations: Vec<(usize, Operation)>) -> Vec<(usize, Operation)> {
+    let mut removed = vec![false; operations.len()];
+
+    for first in 0..operations.len() {
+        if removed[first] {
+            continue;
+        }
+        let (_, operation) = &operations[first];
+        let qubits = operation.qubits();
+
+        for second in first + 1..operations.len() {
+            let (_, other) = &operations[second];
+            if removed[second] || !other.qubits().iter().any(|qubit| qubits.contains(qubit)) {
+                continue;
+            }
+            if operation.cancels(other) {
+                removed[first] = true;
+                removed[second] = true;
+                break;
+            }
+            if !commutes(operation, other) {
+                break;
+            }
+        }
+    }
+
+    operations
+        .into_iter()
+        .zip(removed)
+        .filter(|(_, removed)| !removed)
+        .map(|(operation, _)| operation)
+        .collect()
+}
+
+// Multiply runs of single-qubit gates on the same qubit into one gate in the column of the last gate of the run
+// The product so far can only move past another operation if it is diagonal and that operation is diagonal on the qubit
+fn fuse_single_qubit_gates(operations: Vec<(usize, Operation)>) -> Vec<(usize, Operation)> {
+    let mut operations: Vec<Option<(usize, Operation)>> =
+        operations.into_iter().map(Some).collect();
+
+    for start in 0..operations.len() {
+        let Some((_, first)) = &operations[start] else {
+            continue;
+        };
+        if !is_fusable(first) {
+            continue;
+        }
+        let qubit = first.targets[0];
+        let mut matrix = first.matrix().matrix;
+        let mut run = vec![start];
+
+        for (index, next) in operations.iter().enumerate().skip(start + 1) {
+            let Some((_, operation)) = next else {
+                continue;
+            };
+            if !operation.qubits().contains(&qubit) {
+                continue;
+            }
+            if is_fusable(operation) {
+                matrix = operation.matrix().matrix.dot(&matrix);
+                run.push(index);
+            } else if !is_diagonal(&matrix)
+                || operation.condition.is_some()
+                || !is_diagonal_on(operation, qubit)
+            {
+                break;
+            }
+        }
+
+        if run.len() == 1 {
+            continue;
+        }
+        let last = *run.last().unwrap();
+        let column = operations[last].as_ref().unwrap().0;
+        run.iter().for_each(|&index| operations[index] = None);
+        operations[last] = single_qubit_operation(&matrix, qubit).map(|fused| (column, fused));
+    }
+
+    operations.into_iter().flatten().collect()
+}
+
+// A gate that can be multiplied with its neighbours, measurements, controls and conditions can not
+fn is_fusable(operation: &Operation) -> bool {
+    operation.gate.size() == 1
+        && !operation.is_measurement()
+        && operation.controls.is_empty()
+        && operation.condition.is_none()
+}
+
+// Two operations commute if both are diagonal on every qubit they share,
+// since then both are block diagonal in the basis states of the shared qubits
+fn commutes(first: &Operation, second: &Operation) -> bool {
+    let second_qubits = second.qubits();
+
+    first.condition.is_none()
+        && second.condition.is_none()
+        && first
+            .qubits()
+            .into_iter()
+            .filter(|qubit| second_qubits.contains(qubit))
+            .all(|qubit| is_diagonal_on(first, qubit) && is_diagonal_on(second, qubit))
+}
+
+// Whether the operation only applies phases to or is controlled by the basis states of the qubit
+fn is_diagonal_on(operation: &Operation, qubit: usize) -> bool {
+    let targets = &operation.targets;
+
+    operation
+        .controls
+        .iter()
+        .any(|control| control.qubit == qubit)
+        || match operation.gate {
+            Gate::I | Gate::Z | Gate::S | Gate::T | Gate::RZ | Gate::P | Gate::Cz => true,
+            Gate::Cnot | Gate::Ccnot => targets[..targets.len() - 1].contains(&qubit),
+            _ => false,
+        }
+}
+
+fn is_diagonal(matrix: &Array2<Complex<f64>>) -> bool {
+    matrix[[0, 1]].norm() < TOLERANCE && matrix[[1, 0]].norm() < TOLERANCE
+}
+
+// The gate for a 2x2 unitary, None for the identity
+fn single_qubit_operation(matrix: &Array2<Complex<f64>>, qubit: usize) -> Option<Operation> {
+    if equal_up_to_phase(matrix, &Gate::I.matrix(&[]).matrix) {
+        return None;
+    }
+
+    let named = [Gate::X, Gate::Y, Gate::Z, Gate::H, Gate::S, Gate::T]
+        .into_iter()
+        .find(|gate| equal_up_to_phase(matrix, &gate.matrix(&[]).matrix));
+    if let Some(gate) = named {
+        return Some(Operation::new(gate, vec![qubit], Vec::new()));
+    }
+
+    let (theta, phi, lambda) = u3_angles(matrix);
+    Some(match theta.abs() < TOLERANCE {
+        true => Operation::new(Gate::P, vec![qubit], vec![normalize_angle(phi + lambda)]),
+        false => Operation::new(Gate::U3, vec![qubit], vec![theta, phi, lambda]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::equivalence::Equivalence;
+
+    fn optimize(grid: &[Vec<&str>]) -> Optimization {
+        optimize_grid(grid, 10, 6, &GateRegistry::default()).unwrap()
+    }
+
+    fn assert_equivalent(optimization: &Optimization) {
+        assert_ne!(
+            optimization.equivalence.as_ref().unwrap().equivalence,
+            Equivalence::Different
+        );
+    }
+
+    #[test]
+    fn test_cancel_inverse_pairs() {
+        let grid = vec![
+            vec!["H", "H", "CNOT-1", "X", "CNOT-1"],
+            vec!["I", "I", "CNOT-2", "I", "CNOT-2"],
+            vec!["X", "I", "I", "I", "I"],
+        ];
+
+        let optimization = optimize(&grid);
+
+        // The CNOTs do not cancel, the X on the control is in between
+        assert_eq!(
+            optimization.circuit_matrix,
+            vec![
+                vec!["I", "CNOT-1", "X", "CNOT-1"],
+                vec!["I", "CNOT-2", "I", "CNOT-2"],
+                vec!["X", "I", "I", "I"],
+            ]
+        );
+        assert_eq!(optimization.gates_before, 6);
+        assert_eq!(optimization.gates_after, 4);
+        assert_eq!(optimization.depth_before, 5);
+        assert_eq!(optimization.depth_after, 3);
+        assert_equivalent(&optimization);
+    }
+
+    #[test]
+    fn test_nested_pairs_cancel() {
+        let grid = vec![
+            vec!["X", "CNOT-1", "SWAP-1", "SWAP-2", "CNOT-1", "X"],
+            vec!["I", "CNOT-2", "SWAP-2", "SWAP-1", "CNOT-2", "I"],
+        ];
+
+        let optimization = optimize(&grid);
+
+        assert_eq!(optimization.circuit_matrix, vec![Vec::<String>::new(); 2]);
+        assert_eq!(optimization.gates_after, 0);
+        assert_eq!(optimization.depth_after, 0);
+        assert_equivalent(&optimization);
+    }
+
+    #[test]
+    fn test_fuse_single_qubit_gates() {
+        let grid = vec![
+            vec!["T", "T", "H", "RX(pi/3)"],
+            vec!["H", "Z", "H", "I"],
+            vec!["S", "S", "Z", "I"],
+        ];
+
+        let optimization = optimize(&grid);
+
+        // H Z H is X, S S Z is the identity and the first row becomes a U3 in the last column
+        assert_eq!(optimization.circuit_matrix[1], vec!["X", "I"]);
+        assert_eq!(optimization.circuit_matrix[0][0], "I");
+        assert!(optimization.circuit_matrix[0][1].starts_with("U3("));
+        assert_eq!(optimization.circuit_matrix[2], vec!["I", "I"]);
+        assert_eq!(optimization.gates_after, 2);
+        assert_equivalent(&optimization);
+    }
+
+    #[test]
+    fn test_commute_diagonal_gates_through_controls() {
+        // The Z gates meet through the control of the CNOT, the CZ gates through both
+        let grid = vec![
+            vec!["Z", "CNOT-1", "Z", "CZ-1", "C", "CZ-2"],
+            vec!["I", "CNOT-2", "I", "CZ-2", "I", "CZ-1"],
+            vec!["I", "I", "I", "I", "H", "I"],
+        ];
+
+        let optimization = optimize(&grid);
+
+        assert_eq!(
+            optimization.circuit_matrix,
+            vec![vec!["CNOT-1", "C"], vec!["CNOT-2", "I"], vec!["I", "H"]]
+        );
+        assert_equivalent(&optimization);
+
+        // A Z does not move through the target of a CNOT
+        let grid = vec![vec!["I", "CNOT-1", "I"], vec!["Z", "CNOT-2", "Z"]];
+        assert_eq!(optimize(&grid).gates_after, 3);
+    }
+
+    #[test]
+    fn test_measurements_are_kept() {
+        // Classically controlled gates are neither cancelled nor fused
+        let grid = vec![vec!["H", "M", "H", "H"], vec!["I", "I", "X?c0", "X?c0"]];
+
+        let optimization = optimize(&grid);
+
+        assert_eq!(
+            optimization.circuit_matrix,
+            vec![vec!["H", "M", "I", "I"], vec!["I", "I", "X?c0", "X?c0"]]
+        );
+        assert_eq!(optimization.equivalence, None);
+    }
+}
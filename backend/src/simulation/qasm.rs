@@ -0,0 +1,1064 @@
+// Conversion between the circuit grid and OpenQASM 2.0 / 3
+// Export writes every column of the grid as a group of statements on the register q, measurements go to c
+// Import places the statements in columns in the order they appear, a statement starts a new column
+// when it touches a qubit that is already used in the current column or can not share it for another reason
+// Features the grid can not represent (gate definitions, reset, loops, measuring into another bit, ...) give an error
+
+use crate::simulation::circuit_validator::{
+    validate_circuit, validate_grid_input, QuantumCircuitError,
+};
+use crate::simulation::expression_parser::parse_expression;
+use crate::simulation::gate_registry::GateRegistry;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum QasmVersion {
+    #[default]
+    #[serde(rename = "2.0")]
+    Two,
+    #[serde(rename = "3.0")]
+    Three,
+}
+
+// Write a grid as an OpenQASM program
+pub fn export_qasm(
+    grid: &Vec<Vec<&str>>,
+    max_qubits: usize,
+    gates: &GateRegistry,
+    version: QasmVersion,
+) -> Result<String, QuantumCircuitError> {
+    let circuit = validate_circuit(grid, max_qubits, gates)?;
+
+    let no_of_qubits = circuit.qubits;
+    let has_measurements = circuit.has_measurements();
+    let has_conditions = circuit.has_conditions();
+
+    let mut lines = match version {
+        QasmVersion::Two => vec![
+            "OPENQASM 2.0;".to_string(),
+            "include \"qelib1.inc\";".to_string(),
+            format!("qreg q[{}];", no_of_qubits),
+        ],
+        QasmVersion::Three => vec![
+            "OPENQASM 3.0;".to_string(),
+            "include \"stdgates.inc\";".to_string(),
+            format!("qubit[{}] q;", no_of_qubits),
+        ],
+    };
+
+    // OpenQASM 2.0 can only condition on a whole register, so every bit gets its own register when it is read
+    match version {
+        QasmVersion::Two if has_conditions => {
+            lines.extend((0..no_of_qubits).map(|bit| format!("creg c{}[1];", bit)))
+        }
+        QasmVersion::Two if has_measurements => lines.push(format!("creg c[{}];", no_of_qubits)),
+        QasmVersion::Three if has_measurements || has_conditions => {
+            lines.push(format!("bit[{}] c;", no_of_qubits))
+        }
+        _ => (),
+    }
+
+    let classical_bit = |bit: usize| match version {
+        QasmVersion::Two if has_conditions => format!("c{}[0]", bit),
+        _ => format!("c[{}]", bit),
+    };
+
+    for operation in circuit.operations() {
+        let qubit = operation.targets[0];
+
+        if operation.is_measurement() {
+            lines.push(match version {
+                QasmVersion::Two => format!("measure q[{}] -> {};", qubit, classical_bit(qubit)),
+                QasmVersion::Three => format!("{} = measure q[{}];", classical_bit(qubit), qubit),
+            });
+            continue;
+        }
+
+        // The validator guarantees that only gates without conditions have controls
+        let controls: Vec<(usize, bool)> = operation
+            .controls
+            .iter()
+            .map(|control| (control.qubit, control.state))
+            .collect();
+        let statements = export_gate(
+            operation.gate.name(),
+            &operation.params,
+            &controls,
+            &operation.targets,
+            version,
+        )?;
+
+        match operation.condition {
+            Some(bit) => lines.push(match version {
+                QasmVersion::Two => format!("if(c{}==1) {}", bit, statements[0]),
+                QasmVersion::Three => format!("if ({}) {}", classical_bit(bit), statements[0]),
+            }),
+            None => lines.extend(statements),
+        }
+    }
+
+    Ok(lines.join("\n") + "\n")
+}
+
+// Write one gate with its controls, anti-controls are written as controls between X gates in OpenQASM 2.0
+fn export_gate(
+    name: &str,
+    parameters: &[f64],
+    controls: &[(usize, bool)],
+    qubits: &[usize],
+    version: QasmVersion,
+) -> Result<Vec<String>, QuantumCircuitError> {
+    let base = match (name, version) {
+        ("H", _) => "h",
+        ("X", _) => "x",
+        ("Y", _) => "y",
+        ("Z", _) => "z",
+        ("S", _) => "s",
+        ("T", _) => "t",
+        ("SX", _) => "sx",
+        ("RX", _) => "rx",
+        ("RY", _) => "ry",
+        ("RZ", _) => "rz",
+        ("P", QasmVersion::Two) => "u1",
+        ("P", QasmVersion::Three) => "p",
+        ("U3", _) => "u3",
+        ("CNOT", _) => "cx",
+        ("CZ", _) => "cz",
+        ("SWAP", _) => "swap",
+        ("CCNOT", _) => "ccx",
+        // Custom gates are only known to this server
+        _ => {
+            return Err(QuantumCircuitError::UnsupportedQasm(format!(
+                "{} has no OpenQASM equivalent",
+                name
+            )))
+        }
+    };
+
+    let arguments = if parameters.is_empty() {
+        String::new()
+    } else {
+        let angles: Vec<String> = parameters
+            .iter()
+            .map(|angle| format_angle(*angle))
+            .collect();
+        format!("({})", angles.join(","))
+    };
+
+    let operands: Vec<String> = controls
+        .iter()
+        .map(|&(qubit, _)| qubit)
+        .chain(qubits.iter().copied())
+        .map(|qubit| format!("q[{}]", qubit))
+        .collect();
+    let operands = operands.join(",");
+
+    if controls.is_empty() {
+        return Ok(vec![format!("{}{} {};", base, arguments, operands)]);
+    }
+
+    match version {
+        QasmVersion::Three => {
+            let modifiers: String = controls
+                .iter()
+                .map(|&(_, state)| if state { "ctrl @ " } else { "negctrl @ " })
+                .collect();
+            Ok(vec![format!(
+                "{}{}{} {};",
+                modifiers, base, arguments, operands
+            )])
+        }
+        QasmVersion::Two => {
+            let controlled = match (base, controls.len()) {
+                ("x", 1) => "cx",
+                ("y", 1) => "cy",
+                ("z", 1) => "cz",
+                ("h", 1) => "ch",
+                ("rx", 1) => "crx",
+                ("ry", 1) => "cry",
+                ("rz", 1) => "crz",
+                ("u1", 1) => "cu1",
+                ("u3", 1) => "cu3",
+                ("swap", 1) => "cswap",
+                ("cx", 1) | ("x", 2) => "ccx",
+                _ => {
+                    return Err(QuantumCircuitError::UnsupportedQasm(format!(
+                        "{} with {} control(s) has no OpenQASM 2.0 equivalent, export as OpenQASM 3.0 instead",
+                        name,
+                        controls.len()
+                    )))
+                }
+            };
+
+            let flips: Vec<String> = controls
+                .iter()
+                .filter(|&&(_, state)| !state)
+                .map(|&(qubit, _)| format!("x q[{}];", qubit))
+                .collect();
+
+            let mut statements = flips.clone();
+            statements.push(format!("{}{} {};", controlled, arguments, operands));
+            statements.extend(flips);
+            Ok(statements)
+        }
+    }
+}
+
+// Write an angle as a multiple of pi when it is one, e.g. "pi/4" or "-3*pi/2", otherwise as a decimal
+pub fn format_angle(angle: f64) -> String {
+    if angle.abs() < 1e-12 {
+        return "0".to_string();
+    }
+
+    for denominator in 1..=16 {
+        let numerator = (angle * denominator as f64 / PI).round();
+        if numerator == 0.0 || (numerator * PI / denominator as f64 - angle).abs() > 1e-10 {
+            continue;
+        }
+
+        let numerator = numerator as i64;
+        let multiple = match numerator {
+            1 => "pi".to_string(),
+            -1 => "-pi".to_string(),
+            _ => format!("{}*pi", numerator),
+        };
+        return match denominator {
+            1 => multiple,
+            _ => format!("{}/{}", multiple, denominator),
+        };
+    }
+
+    format!("{}", angle)
+}
+
+// A quantum or classical register, the bits of all registers are numbered in declaration order
+struct Register {
+    name: String,
+    offset: usize,
+    size: usize,
+}
+
+// A statement converted to cells of the grid, with what it needs from the column it is placed in
+struct Placement {
+    cells: Vec<(usize, String)>,
+    has_controls: bool,
+    multi_qubit_gate: Option<&'static str>,
+    reads_bit: Option<usize>,
+    writes_bit: Option<usize>,
+}
+
+// The columns of the grid being built, with what the last column already holds
+struct GridBuilder {
+    no_of_qubits: usize,
+    columns: Vec<Vec<String>>,
+    has_controls: bool,
+    multi_qubit_gates: Vec<&'static str>,
+    read_bits: Vec<usize>,
+    written_bits: Vec<usize>,
+}
+
+impl GridBuilder {
+    fn new_column(&mut self) {
+        self.columns.push(vec!["I".to_string(); self.no_of_qubits]);
+        self.has_controls = false;
+        self.multi_qubit_gates.clear();
+        self.read_bits.clear();
+        self.written_bits.clear();
+    }
+
+    // A column can only have one controlled gate and one gate of each multi-qubit kind,
+    // and a classical bit can not be read in the column it is written in
+    fn fits(&self, placement: &Placement) -> bool {
+        let Some(column) = self.columns.last() else {
+            return false;
+        };
+        let is_empty = column.iter().all(|cell| cell == "I");
+
+        placement
+            .cells
+            .iter()
+            .all(|(qubit, _)| column[*qubit] == "I")
+            && (!placement.has_controls || is_empty)
+            && !self.has_controls
+            && placement
+                .multi_qubit_gate
+                .is_none_or(|name| !self.multi_qubit_gates.contains(&name))
+            && placement
+                .reads_bit
+                .is_none_or(|bit| !self.written_bits.contains(&bit))
+            && placement
+                .writes_bit
+                .is_none_or(|bit| !self.read_bits.contains(&bit))
+    }
+
+    fn place(&mut self, placement: Placement) {
+        if !self.fits(&placement) {
+            self.new_column();
+        }
+
+        let column = self.columns.last_mut().unwrap();
+        for (qubit, cell) in placement.cells {
+            column[qubit] = cell;
+        }
+        self.has_controls |= placement.has_controls;
+        self.multi_qubit_gates.extend(placement.multi_qubit_gate);
+        self.read_bits.extend(placement.reads_bit);
+        self.written_bits.extend(placement.writes_bit);
+    }
+
+    // Turn the columns into rows, a circuit without gates still gets one column
+    fn into_grid(mut self) -> Vec<Vec<String>> {
+        if self.columns.is_empty() {
+            self.new_column();
+        }
+
+        (0..self.no_of_qubits)
+            .map(|qubit| {
+                self.columns
+                    .iter()
+                    .map(|column| column[qubit].clone())
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+// Read an OpenQASM 2.0 or 3 program into a grid
+pub fn import_qasm(
+    source: &str,
+    max_qubits: usize,
+) -> Result<Vec<Vec<String>>, QuantumCircuitError> {
+    let mut quantum_registers: Vec<Register> = Vec::new();
+    let mut classical_registers: Vec<Register> = Vec::new();
+    let mut builder: Option<GridBuilder> = None;
+
+    for (line, statement) in split_statements(source) {
+        let invalid =
+            |message: &str| QuantumCircuitError::InvalidQasm(format!("line {}: {}", line, message));
+        let unsupported = |message: &str| {
+            QuantumCircuitError::UnsupportedQasm(format!("line {}: {}", line, message))
+        };
+
+        if statement.starts_with("OPENQASM") {
+            match statement.trim_start_matches("OPENQASM").trim() {
+                "2" | "2.0" | "3" | "3.0" => continue,
+                _ => return Err(unsupported("only OpenQASM 2.0 and 3 are supported")),
+            }
+        }
+        if statement.starts_with("include") {
+            continue;
+        }
+
+        let (keyword, rest) =
+            match statement.split_once(|c: char| c.is_whitespace() || c == '[' || c == '(') {
+                Some((keyword, _)) => (keyword, statement[keyword.len()..].trim()),
+                None => (statement.as_str(), ""),
+            };
+
+        match keyword {
+            "qreg" | "creg" | "qubit" | "bit" => {
+                if builder.is_some() && (keyword == "qreg" || keyword == "qubit") {
+                    return Err(unsupported(
+                        "qubits must be declared before the first operation",
+                    ));
+                }
+                let (name, size) = parse_declaration(keyword, rest)
+                    .ok_or_else(|| invalid("invalid register declaration"))?;
+                let is_quantum = keyword == "qreg" || keyword == "qubit";
+                let registers = if is_quantum {
+                    &mut quantum_registers
+                } else {
+                    &mut classical_registers
+                };
+                let offset: usize = registers.iter().map(|register| register.size).sum();
+                // The qubit count is checked here, before a row is built for every qubit
+                match offset.checked_add(size) {
+                    Some(total) if is_quantum && total > max_qubits => {
+                        return Err(QuantumCircuitError::TooManyQubits)
+                    }
+                    None if is_quantum => return Err(QuantumCircuitError::TooManyQubits),
+                    None => return Err(invalid("the classical registers are too large")),
+                    Some(_) => (),
+                }
+                registers.push(Register { name, offset, size });
+                continue;
+            }
+            "gate" | "def" | "opaque" => {
+                return Err(unsupported(
+                    "custom gate definitions can not be represented in the grid",
+                ))
+            }
+            "reset" => return Err(unsupported("reset can not be represented in the grid")),
+            "for" | "while" | "else" => {
+                return Err(unsupported(
+                    "control flow can not be represented in the grid",
+                ))
+            }
+            _ => (),
+        }
+
+        let no_of_qubits: usize = quantum_registers.iter().map(|register| register.size).sum();
+        let builder = builder.get_or_insert_with(|| GridBuilder {
+            no_of_qubits,
+            columns: Vec::new(),
+            has_controls: false,
+            multi_qubit_gates: Vec::new(),
+            read_bits: Vec::new(),
+            written_bits: Vec::new(),
+        });
+
+        if keyword == "barrier" {
+            builder.new_column();
+            continue;
+        }
+
+        if keyword == "measure" || statement.contains("measure") {
+            let (qubits, bits) = parse_measurement(&statement)
+                .and_then(|(qubit, bit)| {
+                    Some((
+                        resolve_operand(qubit, &quantum_registers)?,
+                        resolve_operand(bit, &classical_registers)?,
+                    ))
+                })
+                .ok_or_else(|| invalid("invalid measurement"))?;
+            if qubits.len() != bits.len() {
+                return Err(invalid(
+                    "the qubit and classical operands of a measurement differ in size",
+                ));
+            }
+            for (qubit, bit) in qubits.into_iter().zip(bits) {
+                if qubit != bit {
+                    return Err(unsupported(&format!(
+                        "qubit {} is measured into classical bit {}, the grid always measures a qubit into the bit with the same index",
+                        qubit, bit
+                    )));
+                }
+                builder.place(Placement {
+                    cells: vec![(qubit, "M".to_string())],
+                    has_controls: false,
+                    multi_qubit_gate: None,
+                    reads_bit: None,
+                    writes_bit: Some(bit),
+                });
+            }
+            continue;
+        }
+
+        let (condition, statement) = match keyword {
+            "if" => {
+                let (condition, statement) =
+                    split_condition(&statement).ok_or_else(|| invalid("invalid if statement"))?;
+                let bit = parse_condition(condition, &classical_registers).ok_or_else(|| {
+                    unsupported("only conditions on a single classical bit being 1 can be represented in the grid")
+                })?;
+                (Some(bit), statement)
+            }
+            _ => (None, statement.as_str()),
+        };
+
+        let placements =
+            parse_gate_statement(statement, &quantum_registers).map_err(|error| match error {
+                GateError::Invalid(message) => invalid(&message),
+                GateError::Unsupported(message) => unsupported(&message),
+            })?;
+
+        for mut placement in placements {
+            if let Some(bit) = condition {
+                if placement.cells.len() != 1 || placement.has_controls {
+                    return Err(unsupported(
+                        "only single-qubit gates can be classically controlled in the grid",
+                    ));
+                }
+                placement.cells[0].1 = format!("{}?c{}", placement.cells[0].1, bit);
+                placement.reads_bit = Some(bit);
+            }
+            builder.place(placement);
+        }
+    }
+
+    let grid = match builder {
+        Some(builder) => builder.into_grid(),
+        None => {
+            let no_of_qubits = quantum_registers.iter().map(|register| register.size).sum();
+            vec![vec!["I".to_string()]; no_of_qubits]
+        }
+    };
+
+    validate_grid_input(
+        &grid
+            .iter()
+            .map(|row| row.iter().map(|cell| cell.as_str()).collect())
+            .collect(),
+        max_qubits,
+    )?;
+
+    Ok(grid)
+}
+
+// Split the program into statements with the line they start on, comments are removed
+fn split_statements(source: &str) -> Vec<(usize, String)> {
+    let mut statements = Vec::new();
+    let mut current = String::new();
+    let mut start_line = 1;
+
+    for (index, line) in source.lines().enumerate() {
+        let line = match line.split_once("//") {
+            Some((code, _)) => code,
+            None => line,
+        };
+
+        for c in line.chars() {
+            if current.trim().is_empty() {
+                start_line = index + 1;
+            }
+            match c {
+                ';' => {
+                    statements.push((start_line, current.trim().to_string()));
+                    current.clear();
+                }
+                // Braces only appear in gate definitions and control flow, which are reported by their keyword
+                '{' => {
+                    statements.push((start_line, current.trim().to_string()));
+                    current.clear();
+                }
+                _ => current.push(c),
+            }
+        }
+        current.push(' ');
+    }
+
+    statements.retain(|(_, statement)| !statement.is_empty() && statement != "}");
+    statements
+}
+
+// "qreg q[3]" / "creg c[3]" or "qubit[3] q" / "bit[3] c" / "qubit q"
+fn parse_declaration(keyword: &str, rest: &str) -> Option<(String, usize)> {
+    let (name, size) = match keyword {
+        "qreg" | "creg" => {
+            let (name, size) = rest.split_once('[')?;
+            (name.trim(), size.trim().strip_suffix(']')?)
+        }
+        _ => match rest.strip_prefix('[') {
+            Some(rest) => {
+                let (size, name) = rest.split_once(']')?;
+                (name.trim(), size)
+            }
+            None => (rest, "1"),
+        },
+    };
+
+    let size = size.trim().parse::<usize>().ok()?;
+    if name.is_empty() || size == 0 {
+        return None;
+    }
+
+    Some((name.to_string(), size))
+}
+
+// The bits an operand refers to, "q[2]" is one bit and "q" is the whole register
+fn resolve_operand(operand: &str, registers: &[Register]) -> Option<Vec<usize>> {
+    let operand = operand.trim();
+    let (name, index) = match operand.split_once('[') {
+        Some((name, index)) => (
+            name.trim(),
+            Some(
+                index
+                    .trim()
+                    .strip_suffix(']')?
+                    .trim()
+                    .parse::<usize>()
+                    .ok()?,
+            ),
+        ),
+        None => (operand, None),
+    };
+
+    let register = registers.iter().find(|register| register.name == name)?;
+    match index {
+        Some(index) if index < register.size => Some(vec![register.offset + index]),
+        Some(_) => None,
+        None => Some((register.offset..register.offset + register.size).collect()),
+    }
+}
+
+// "measure q[0] -> c[0]" or "c[0] = measure q[0]", returns the qubit and classical operands
+fn parse_measurement(statement: &str) -> Option<(&str, &str)> {
+    if let Some(rest) = statement.strip_prefix("measure") {
+        return rest.split_once("->");
+    }
+
+    let (bit, rest) = statement.split_once('=')?;
+    Some((rest.trim().strip_prefix("measure")?, bit))
+}
+
+// Split "if(c==1) x q[0]" into the condition and the statement
+fn split_condition(statement: &str) -> Option<(&str, &str)> {
+    let rest = statement
+        .strip_prefix("if")?
+        .trim_start()
+        .strip_prefix('(')?;
+    let mut depth = 1;
+
+    for (i, c) in rest.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((rest[..i].trim(), rest[i + 1..].trim()));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    None
+}
+
+// The classical bit a condition reads, for "c[0]", "c[0] == 1" or "c == 1" where c is a single-bit register
+fn parse_condition(condition: &str, registers: &[Register]) -> Option<usize> {
+    let operand = match condition.split_once("==") {
+        Some((operand, value)) if value.trim() == "1" || value.trim() == "true" => operand,
+        Some(_) => return None,
+        None => condition,
+    };
+
+    match resolve_operand(operand, registers)?.as_slice() {
+        [bit] => Some(*bit),
+        _ => None,
+    }
+}
+
+enum GateError {
+    Invalid(String),
+    Unsupported(String),
+}
+
+// The grid gate for an OpenQASM gate name, with the number of controls the name implies
+// e.g. "crx" is "RX" with one control and "ccx" is "CCNOT" without controls since the grid has it
+fn grid_gate(name: &str, parameters: &[String]) -> Result<(String, usize), GateError> {
+    let angle = |index: usize| -> Result<String, GateError> {
+        let expression = parameters
+            .get(index)
+            .ok_or_else(|| GateError::Invalid(format!("{} is missing a parameter", name)))?;
+        parse_expression(expression)
+            .map(format_angle)
+            .ok_or_else(|| GateError::Invalid(format!("invalid angle \"{}\"", expression)))
+    };
+
+    let expected_parameters = match name {
+        "rx" | "ry" | "rz" | "p" | "u1" | "phase" | "crx" | "cry" | "crz" | "cp" | "cu1"
+        | "cphase" => 1,
+        "u2" => 2,
+        "u3" | "u" | "U" | "cu3" => 3,
+        _ => 0,
+    };
+    if parameters.len() != expected_parameters {
+        return Err(GateError::Invalid(format!(
+            "{} takes {} parameter(s), found {}",
+            name,
+            expected_parameters,
+            parameters.len()
+        )));
+    }
+
+    let gate = match name {
+        "id" | "i" => ("I".to_string(), 0),
+        "h" => ("H".to_string(), 0),
+        "x" => ("X".to_string(), 0),
+        "y" => ("Y".to_string(), 0),
+        "z" => ("Z".to_string(), 0),
+        "s" => ("S".to_string(), 0),
+        "t" => ("T".to_string(), 0),
+        "sx" => ("SX".to_string(), 0),
+        "sdg" => ("P(-pi/2)".to_string(), 0),
+        "tdg" => ("P(-pi/4)".to_string(), 0),
+        "rx" => (format!("RX({})", angle(0)?), 0),
+        "ry" => (format!("RY({})", angle(0)?), 0),
+        "rz" => (format!("RZ({})", angle(0)?), 0),
+        "p" | "u1" | "phase" => (format!("P({})", angle(0)?), 0),
+        "u2" => (format!("U3(pi/2,{},{})", angle(0)?, angle(1)?), 0),
+        "u3" | "u" | "U" => (format!("U3({},{},{})", angle(0)?, angle(1)?, angle(2)?), 0),
+        "cx" | "CX" | "cnot" => ("CNOT".to_string(), 0),
+        "cz" => ("CZ".to_string(), 0),
+        "swap" => ("SWAP".to_string(), 0),
+        "ccx" | "toffoli" => ("CCNOT".to_string(), 0),
+        "cy" => ("Y".to_string(), 1),
+        "ch" => ("H".to_string(), 1),
+        "crx" => (format!("RX({})", angle(0)?), 1),
+        "cry" => (format!("RY({})", angle(0)?), 1),
+        "crz" => (format!("RZ({})", angle(0)?), 1),
+        "cp" | "cu1" | "cphase" => (format!("P({})", angle(0)?), 1),
+        "cu3" => (format!("U3({},{},{})", angle(0)?, angle(1)?, angle(2)?), 1),
+        "cswap" => ("SWAP".to_string(), 1),
+        _ => {
+            return Err(GateError::Unsupported(format!(
+                "gate \"{}\" is not available in the grid",
+                name
+            )))
+        }
+    };
+
+    Ok(gate)
+}
+
+// Parse a gate call with optional ctrl/negctrl modifiers, e.g. "cx q[0],q[1]" or "negctrl @ h q[0], q[1]"
+// A single-qubit gate on whole registers is applied to every qubit of them
+fn parse_gate_statement(
+    statement: &str,
+    registers: &[Register],
+) -> Result<Vec<Placement>, GateError> {
+    let mut control_states: Vec<bool> = Vec::new();
+    let mut rest = statement;
+
+    while let Some((modifier, remainder)) = rest.split_once('@') {
+        let modifier = modifier.trim();
+        let (kind, count) = match modifier.split_once('(') {
+            Some((kind, count)) => (
+                kind.trim(),
+                count
+                    .trim()
+                    .strip_suffix(')')
+                    .and_then(|count| count.trim().parse::<usize>().ok())
+                    .ok_or_else(|| {
+                        GateError::Invalid(format!("invalid modifier \"{}\"", modifier))
+                    })?,
+            ),
+            None => (modifier, 1),
+        };
+        match kind {
+            "ctrl" => control_states.extend(std::iter::repeat_n(true, count)),
+            "negctrl" => control_states.extend(std::iter::repeat_n(false, count)),
+            _ => {
+                return Err(GateError::Unsupported(format!(
+                    "the \"{}\" modifier can not be represented in the grid",
+                    kind
+                )))
+            }
+        }
+        rest = remainder.trim();
+    }
+
+    let (call, operands) = match rest.find('(') {
+        Some(open)
+            if rest[..open]
+                .trim()
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_') =>
+        {
+            let close = rest
+                .rfind(')')
+                .ok_or_else(|| GateError::Invalid("unclosed parameter list".to_string()))?;
+            (&rest[..=close], rest[close + 1..].trim())
+        }
+        _ => match rest.split_once(char::is_whitespace) {
+            Some((name, operands)) => (name, operands.trim()),
+            None => {
+                return Err(GateError::Invalid(format!(
+                    "\"{}\" is not a statement",
+                    rest
+                )))
+            }
+        },
+    };
+
+    let (name, parameters) = match call.split_once('(') {
+        Some((name, arguments)) => (
+            name.trim(),
+            arguments
+                .strip_suffix(')')
+                .unwrap_or(arguments)
+                .split(',')
+                .map(|argument| argument.trim().to_string())
+                .filter(|argument| !argument.is_empty())
+                .collect(),
+        ),
+        None => (call.trim(), Vec::new()),
+    };
+
+    let (gate, implied_controls) = grid_gate(name, &parameters)?;
+    // The controls a gate name implies, like the control of "ch", come after the modifier controls
+    control_states.extend(std::iter::repeat_n(true, implied_controls));
+
+    let operands: Vec<Vec<usize>> = operands
+        .split(',')
+        .map(|operand| {
+            resolve_operand(operand, registers)
+                .ok_or_else(|| GateError::Invalid(format!("unknown qubit \"{}\"", operand.trim())))
+        })
+        .collect::<Result<_, _>>()?;
+
+    let multi_qubit_size = match gate.as_str() {
+        "CNOT" | "CZ" | "SWAP" => 2,
+        "CCNOT" => 3,
+        _ => 1,
+    };
+    if operands.len() != control_states.len() + multi_qubit_size {
+        return Err(GateError::Invalid(format!(
+            "{} expects {} qubit(s), found {}",
+            name,
+            control_states.len() + multi_qubit_size,
+            operands.len()
+        )));
+    }
+
+    // Broadcasting over registers is only supported for plain single-qubit gates
+    if operands.len() == 1 {
+        return Ok(operands[0]
+            .iter()
+            .filter(|_| gate != "I")
+            .map(|&qubit| Placement {
+                cells: vec![(qubit, gate.clone())],
+                has_controls: false,
+                multi_qubit_gate: None,
+                reads_bit: None,
+                writes_bit: None,
+            })
+            .collect());
+    }
+
+    let qubits: Vec<usize> = operands
+        .iter()
+        .map(|bits| match bits.as_slice() {
+            [qubit] => Ok(*qubit),
+            _ => Err(GateError::Unsupported(
+                "multi-qubit gates on whole registers can not be represented in the grid"
+                    .to_string(),
+            )),
+        })
+        .collect::<Result<_, _>>()?;
+
+    let mut sorted = qubits.clone();
+    sorted.sort_unstable();
+    sorted.dedup();
+    if sorted.len() != qubits.len() {
+        return Err(GateError::Invalid(format!(
+            "{} uses the same qubit twice",
+            name
+        )));
+    }
+
+    let (control_qubits, target_qubits) = qubits.split_at(control_states.len());
+    let mut cells: Vec<(usize, String)> = control_qubits
+        .iter()
+        .zip(&control_states)
+        .map(|(&qubit, &state)| (qubit, if state { "C" } else { "O" }.to_string()))
+        .collect();
+
+    let multi_qubit_gate = match gate.as_str() {
+        "CNOT" => Some("CNOT"),
+        "CZ" => Some("CZ"),
+        "SWAP" => Some("SWAP"),
+        "CCNOT" => Some("CCNOT"),
+        _ => None,
+    };
+    match multi_qubit_gate {
+        Some(name) => cells.extend(
+            target_qubits
+                .iter()
+                .enumerate()
+                .map(|(part, &qubit)| (qubit, format!("{}-{}", name, part + 1))),
+        ),
+        None => cells.push((target_qubits[0], gate)),
+    }
+
+    Ok(vec![Placement {
+        cells,
+        has_controls: !control_states.is_empty(),
+        multi_qubit_gate,
+        reads_bit: None,
+        writes_bit: None,
+    }])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_grid(grid: &[Vec<String>]) -> Vec<Vec<&str>> {
+        grid.iter()
+            .map(|row| row.iter().map(|cell| cell.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_export_bell_state() {
+        let grid = vec![vec!["H", "CNOT-1", "M"], vec!["I", "CNOT-2", "M"]];
+
+        assert_eq!(
+            export_qasm(&grid, 6, &GateRegistry::default(), QasmVersion::Two).unwrap(),
+            "OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[2];\ncreg c[2];\nh q[0];\ncx q[0],q[1];\nmeasure q[0] -> c[0];\nmeasure q[1] -> c[1];\n"
+        );
+        assert_eq!(
+            export_qasm(&grid, 6, &GateRegistry::default(), QasmVersion::Three).unwrap(),
+            "OPENQASM 3.0;\ninclude \"stdgates.inc\";\nqubit[2] q;\nbit[2] c;\nh q[0];\ncx q[0],q[1];\nc[0] = measure q[0];\nc[1] = measure q[1];\n"
+        );
+    }
+
+    #[test]
+    fn test_export_parameterised_and_controlled_gates() {
+        let grid = vec![vec!["RX(pi/2)", "O"], vec!["U3(pi, 0, 2pi/3)", "H"]];
+
+        assert_eq!(
+            export_qasm(&grid, 6, &GateRegistry::default(), QasmVersion::Two).unwrap(),
+            "OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[2];\nrx(pi/2) q[0];\nu3(pi,0,2*pi/3) q[1];\nx q[0];\nch q[0],q[1];\nx q[0];\n"
+        );
+        assert!(
+            export_qasm(&grid, 6, &GateRegistry::default(), QasmVersion::Three)
+                .unwrap()
+                .contains("negctrl @ h q[0],q[1];")
+        );
+    }
+
+    #[test]
+    fn test_export_unsupported_in_version_two() {
+        let grid = vec![vec!["C"], vec!["C"], vec!["H"]];
+
+        assert!(matches!(
+            export_qasm(&grid, 6, &GateRegistry::default(), QasmVersion::Two),
+            Err(QuantumCircuitError::UnsupportedQasm(_))
+        ));
+        assert!(
+            export_qasm(&grid, 6, &GateRegistry::default(), QasmVersion::Three)
+                .unwrap()
+                .contains("ctrl @ ctrl @ h q[0],q[1],q[2];")
+        );
+    }
+
+    #[test]
+    fn test_export_classical_condition() {
+        let grid = vec![vec!["H", "M", "I"], vec!["I", "I", "X?c0"]];
+
+        let qasm = export_qasm(&grid, 6, &GateRegistry::default(), QasmVersion::Two).unwrap();
+        assert!(qasm.contains("creg c0[1];\ncreg c1[1];"));
+        assert!(qasm.contains("measure q[0] -> c0[0];"));
+        assert!(qasm.contains("if(c0==1) x q[1];"));
+
+        let qasm = export_qasm(&grid, 6, &GateRegistry::default(), QasmVersion::Three).unwrap();
+        assert!(qasm.contains("if (c[0]) x q[1];"));
+    }
+
+    #[test]
+    fn test_import_bell_state() {
+        let source = "OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[2];\ncreg c[2];\nh q[0];\ncx q[0],q[1];\nmeasure q -> c;\n";
+
+        assert_eq!(
+            import_qasm(source, 6).unwrap(),
+            vec![vec!["H", "CNOT-1", "M"], vec!["I", "CNOT-2", "M"]]
+        );
+    }
+
+    #[test]
+    fn test_import_packs_independent_gates() {
+        let source = "OPENQASM 3.0;\nqubit[3] q;\nh q;\nx q[0]; // comment\nrz(pi/4) q[2];\nbarrier q;\ny q[1];";
+
+        assert_eq!(
+            import_qasm(source, 6).unwrap(),
+            vec![
+                vec!["H", "X", "I"],
+                vec!["H", "I", "Y"],
+                vec!["H", "RZ(pi/4)", "I"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_import_controls_and_conditions() {
+        let source = "OPENQASM 3.0;\nqubit[3] q;\nbit[3] c;\nnegctrl @ ctrl @ x q[0], q[1], q[2];\nc[0] = measure q[0];\nif (c[0] == 1) z q[2];\ncswap q[2], q[0], q[1];";
+
+        assert_eq!(
+            import_qasm(source, 6).unwrap(),
+            vec![
+                vec!["O", "M", "I", "SWAP-1"],
+                vec!["C", "I", "I", "SWAP-2"],
+                vec!["X", "I", "Z?c0", "C"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let grid = vec![
+            vec!["H", "CNOT-1", "O", "M", "I"],
+            vec!["I", "CNOT-2", "CNOT-1", "I", "S?c0"],
+            vec!["T", "I", "CNOT-2", "RY(-pi/4)", "SX"],
+        ];
+
+        for version in [QasmVersion::Two, QasmVersion::Three] {
+            let qasm = export_qasm(&grid, 6, &GateRegistry::default(), version).unwrap();
+            let imported = import_qasm(&qasm, 6).unwrap();
+
+            // Anti-controls come back as X gates around a controlled gate in OpenQASM 2.0
+            if version == QasmVersion::Three {
+                assert_eq!(to_grid(&imported), grid);
+            } else {
+                assert_eq!(
+                    export_qasm(&to_grid(&imported), 6, &GateRegistry::default(), version).unwrap(),
+                    qasm
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_import_errors() {
+        let unsupported = |source: &str| {
+            matches!(
+                import_qasm(source, 6),
+                Err(QuantumCircuitError::UnsupportedQasm(_))
+            )
+        };
+        let invalid = |source: &str| {
+            matches!(
+                import_qasm(source, 6),
+                Err(QuantumCircuitError::InvalidQasm(_))
+            )
+        };
+
+        assert!(unsupported("qreg q[1];\ngate g a { h a; }\ng q[0];"));
+        assert!(unsupported("qreg q[1];\nreset q[0];"));
+        assert!(unsupported("qreg q[2];\ncreg c[2];\nmeasure q[0] -> c[1];"));
+        assert!(unsupported("qreg q[2];\ncreg c[2];\nif(c==1) x q[0];"));
+        assert!(unsupported("qreg q[1];\nsxdg q[0];"));
+        assert!(unsupported("qubit[1] q;\ninv @ s q[0];"));
+        assert!(invalid("qreg q[1];\nh r[0];"));
+        assert!(invalid("qreg q[1];\nrx(theta) q[0];"));
+        assert!(invalid("qreg q[2];\ncx q[0];"));
+        assert_eq!(
+            import_qasm("qreg q[7];\nh q[0];", 6),
+            Err(QuantumCircuitError::TooManyQubits)
+        );
+        // Rejected when declared, before a grid is built or the sizes overflow
+        for source in [
+            "qreg q[10000000];",
+            "qreg q[4611686018427387904];",
+            "qreg q[9223372036854775808];\nqreg r[9223372036854775808];",
+            "qubit[3] q;\nqubit[4] r;",
+        ] {
+            assert_eq!(
+                import_qasm(source, 6),
+                Err(QuantumCircuitError::TooManyQubits)
+            );
+        }
+        assert!(invalid(
+            "qreg q[1];\ncreg c[9223372036854775808];\ncreg d[9223372036854775808];"
+        ));
+
+        match import_qasm("qreg q[1];\n\nfoo q[0];", 6) {
+            Err(QuantumCircuitError::UnsupportedQasm(message)) => {
+                assert!(message.starts_with("line 3:"))
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_format_angle() {
+        assert_eq!(format_angle(0.0), "0");
+        assert_eq!(format_angle(PI), "pi");
+        assert_eq!(format_angle(-PI / 2.0), "-pi/2");
+        assert_eq!(format_angle(3.0 * PI / 4.0), "3*pi/4");
+        assert_eq!(format_angle(0.5), "0.5");
+    }
+}
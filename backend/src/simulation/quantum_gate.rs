@@ -1,11 +1,12 @@
+use crate::simulation::linalg::adjoint;
 use ndarray::linalg::kron;
-use ndarray::{arr2, Array2};
+use ndarray::{arr2, s, Array2};
 use num::Complex;
 
 // QuantumGate struct
 // Matrix is a 2D array of Complex numbers that represents the gate
 // Size is the number of qubits the gate operates on
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct QuantumGate {
     pub matrix: Array2<Complex<f64>>,
     pub size: usize,
@@ -88,6 +89,90 @@ impl QuantumGate {
         }
     }
 
+    // The square root of X, native on many superconducting devices
+    pub fn sx_gate() -> QuantumGate {
+        QuantumGate {
+            matrix: arr2(&[
+                [Complex::new(0.5, 0.5), Complex::new(0.5, -0.5)],
+                [Complex::new(0.5, -0.5), Complex::new(0.5, 0.5)],
+            ]),
+            size: 1,
+        }
+    }
+
+    // Rotation of theta radians around the X axis of the Bloch sphere
+    pub fn rx(theta: f64) -> QuantumGate {
+        let cos = (theta / 2.0).cos();
+        let sin = (theta / 2.0).sin();
+
+        QuantumGate {
+            matrix: arr2(&[
+                [Complex::new(cos, 0.0), Complex::new(0.0, -sin)],
+                [Complex::new(0.0, -sin), Complex::new(cos, 0.0)],
+            ]),
+            size: 1,
+        }
+    }
+
+    // Rotation of theta radians around the Y axis of the Bloch sphere
+    pub fn ry(theta: f64) -> QuantumGate {
+        let cos = (theta / 2.0).cos();
+        let sin = (theta / 2.0).sin();
+
+        QuantumGate {
+            matrix: arr2(&[
+                [Complex::new(cos, 0.0), Complex::new(-sin, 0.0)],
+                [Complex::new(sin, 0.0), Complex::new(cos, 0.0)],
+            ]),
+            size: 1,
+        }
+    }
+
+    // Rotation of theta radians around the Z axis of the Bloch sphere
+    pub fn rz(theta: f64) -> QuantumGate {
+        QuantumGate {
+            matrix: arr2(&[
+                [
+                    Complex::from_polar(1.0, -theta / 2.0),
+                    Complex::new(0.0, 0.0),
+                ],
+                [
+                    Complex::new(0.0, 0.0),
+                    Complex::from_polar(1.0, theta / 2.0),
+                ],
+            ]),
+            size: 1,
+        }
+    }
+
+    // Phase shift of lambda radians on |1>, phase(pi/2) is S and phase(pi/4) is T
+    pub fn phase(lambda: f64) -> QuantumGate {
+        QuantumGate {
+            matrix: arr2(&[
+                [Complex::new(1.0, 0.0), Complex::new(0.0, 0.0)],
+                [Complex::new(0.0, 0.0), Complex::from_polar(1.0, lambda)],
+            ]),
+            size: 1,
+        }
+    }
+
+    // General single-qubit gate, u3(pi/2, 0, pi) is H
+    pub fn u3(theta: f64, phi: f64, lambda: f64) -> QuantumGate {
+        let cos = (theta / 2.0).cos();
+        let sin = (theta / 2.0).sin();
+
+        QuantumGate {
+            matrix: arr2(&[
+                [Complex::new(cos, 0.0), -Complex::from_polar(sin, lambda)],
+                [
+                    Complex::from_polar(sin, phi),
+                    Complex::from_polar(cos, phi + lambda),
+                ],
+            ]),
+            size: 1,
+        }
+    }
+
     pub fn cnot_gate() -> QuantumGate {
         QuantumGate {
             matrix: arr2(&[
@@ -272,6 +357,44 @@ impl QuantumGate {
         }
     }
 
+    // Controlled version of the gate with n_controls control qubits before the gate's own qubits
+    // The gate is applied if all controls are |1>
+    #[cfg(test)]
+    pub fn controlled(&self, n_controls: usize) -> QuantumGate {
+        self.controlled_on(&vec![true; n_controls])
+    }
+
+    // Controlled version of the gate where every control is either a control on |1> (true)
+    // or an anti-control on |0> (false), the controls come before the gate's own qubits
+    pub fn controlled_on(&self, control_states: &[bool]) -> QuantumGate {
+        let gate_dim = 1_usize << self.size;
+        let size = control_states.len() + self.size;
+        let mut matrix = Array2::<Complex<f64>>::eye(1 << size);
+
+        // Index of the block where every control has its required state
+        let control_index = control_states
+            .iter()
+            .fold(0, |acc, &state| (acc << 1) | usize::from(state));
+        let offset = control_index * gate_dim;
+
+        matrix
+            .slice_mut(s![offset..offset + gate_dim, offset..offset + gate_dim])
+            .assign(&self.matrix);
+
+        QuantumGate { matrix, size }
+    }
+
+    // Whether U^dagger U is the identity, up to rounding errors
+    pub fn is_unitary(&self) -> bool {
+        let product = adjoint(&self.matrix).dot(&self.matrix);
+        let identity = Array2::<Complex<f64>>::eye(self.matrix.nrows());
+
+        product
+            .iter()
+            .zip(identity.iter())
+            .all(|(a, b)| (a - b).norm() < 1e-9)
+    }
+
     // Combine two gates using the Kronecker product
     pub fn kronecker(self, other: QuantumGate) -> QuantumGate {
         QuantumGate {
@@ -284,9 +407,22 @@ impl QuantumGate {
 #[cfg(test)]
 mod tests {
     use crate::simulation::quantum_state::QuantumState;
+    use std::f64::consts::PI;
 
     use super::*;
 
+    fn assert_matrix_close(actual: &QuantumGate, expected: &QuantumGate) {
+        assert_eq!(actual.size, expected.size);
+        for (a, b) in actual.matrix.iter().zip(expected.matrix.iter()) {
+            assert!(
+                (a - b).norm() < 1e-12,
+                "{} != {}",
+                actual.matrix,
+                expected.matrix
+            );
+        }
+    }
+
     #[test]
     fn test_x_gate() {
         // X|0> -> |1>
@@ -440,4 +576,90 @@ mod tests {
 
         assert_eq!(state.col, expected_result);
     }
+
+    #[test]
+    fn test_rotation_gates() {
+        // RX(pi) = -iX, RY(pi) = -iY, RZ(pi) = -iZ
+        let minus_i = Complex::new(0.0, -1.0);
+        let scale = |gate: QuantumGate| QuantumGate {
+            matrix: gate.matrix.mapv(|value| value * minus_i),
+            size: gate.size,
+        };
+
+        assert_matrix_close(&QuantumGate::rx(PI), &scale(QuantumGate::x_gate()));
+        assert_matrix_close(&QuantumGate::ry(PI), &scale(QuantumGate::y_gate()));
+        assert_matrix_close(&QuantumGate::rz(PI), &scale(QuantumGate::z_gate()));
+        assert_matrix_close(&QuantumGate::rx(0.0), &QuantumGate::i_gate());
+    }
+
+    #[test]
+    fn test_phase_gate() {
+        assert_matrix_close(&QuantumGate::phase(PI / 2.0), &QuantumGate::s_gate());
+        assert_matrix_close(&QuantumGate::phase(PI / 4.0), &QuantumGate::t_gate());
+        assert_matrix_close(&QuantumGate::phase(PI), &QuantumGate::z_gate());
+    }
+
+    #[test]
+    fn test_sx_gate() {
+        let x = QuantumGate::sx_gate()
+            .matrix
+            .dot(&QuantumGate::sx_gate().matrix);
+
+        assert_matrix_close(&QuantumGate { matrix: x, size: 1 }, &QuantumGate::x_gate());
+    }
+
+    #[test]
+    fn test_u3_gate() {
+        assert_matrix_close(&QuantumGate::u3(PI / 2.0, 0.0, PI), &QuantumGate::h_gate());
+        assert_matrix_close(&QuantumGate::u3(PI, 0.0, PI), &QuantumGate::x_gate());
+        assert_matrix_close(&QuantumGate::u3(0.0, 0.0, 0.3), &QuantumGate::phase(0.3));
+    }
+
+    #[test]
+    fn test_controlled_gates() {
+        assert_eq!(
+            QuantumGate::x_gate().controlled(1).matrix,
+            QuantumGate::cnot_gate().matrix
+        );
+        assert_eq!(
+            QuantumGate::z_gate().controlled(1).matrix,
+            QuantumGate::cz_gate().matrix
+        );
+        assert_eq!(
+            QuantumGate::x_gate().controlled(2).matrix,
+            QuantumGate::ccnot_gate().matrix
+        );
+        assert_eq!(QuantumGate::h_gate().controlled(4).size, 5);
+    }
+
+    #[test]
+    fn test_anti_controlled_gate() {
+        // X with an anti-control: |00> -> |01>, |10> -> |10>
+        let gate = QuantumGate::x_gate().controlled_on(&[false]);
+
+        let state = QuantumState::new(&[0, 0]).apply_gate(gate.clone());
+        assert_eq!(state.col, QuantumState::new(&[0, 1]).col);
+
+        let state = QuantumState::new(&[1, 0]).apply_gate(gate);
+        assert_eq!(state.col, QuantumState::new(&[1, 0]).col);
+    }
+
+    #[test]
+    fn test_controlled_swap_gate() {
+        // Fredkin gate: |101> -> |110>
+        let state =
+            QuantumState::new(&[1, 0, 1]).apply_gate(QuantumGate::swap_gate().controlled(1));
+        assert_eq!(state.col, QuantumState::new(&[1, 1, 0]).col);
+    }
+
+    #[test]
+    fn test_is_unitary() {
+        assert!(QuantumGate::u3(0.3, 1.2, -0.7).is_unitary());
+        assert!(QuantumGate::ccnot_gate().is_unitary());
+        assert!(!QuantumGate {
+            matrix: Array2::<Complex<f64>>::ones((2, 2)),
+            size: 1,
+        }
+        .is_unitary());
+    }
 }
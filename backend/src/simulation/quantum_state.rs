@@ -1,7 +1,10 @@
+use crate::simulation::circuit::Control;
+use crate::simulation::density_matrix::DensityMatrix;
 use crate::simulation::quantum_gate::QuantumGate;
 
 use ndarray::Array2;
 use num::{Complex, ToPrimitive};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 
 // QuantumState struct
@@ -11,12 +14,13 @@ pub struct QuantumState {
 }
 
 impl QuantumState {
-    // Create a QuantumState from a list of bits, panics if the number of qubits is not between 1 and 6 or if the bits are not 0 or 1
+    // Create a QuantumState from a list of bits, panics if there are no qubits or if the bits are not 0 or 1
+    // The qubit limit is enforced by the validator, so the state itself has no upper bound
     pub fn new(bits: &[usize]) -> QuantumState {
         let no_of_qubits = bits.len();
 
-        if !(1..=6).contains(&no_of_qubits) {
-            panic!("Number of qubits must be between 1 and 6, inclusive");
+        if no_of_qubits == 0 {
+            panic!("Number of qubits must be at least 1");
         }
 
         let mut index = 0_usize;
@@ -40,6 +44,7 @@ impl QuantumState {
     }
 
     // Apply a QuantumGate to a QuantumState, panic if gate and state are not of the same size, if the gate size is 0, return the state unchanged
+    #[cfg(test)]
     pub fn apply_gate(self, gate: QuantumGate) -> QuantumState {
         if gate.size == 0 {
             return self;
@@ -57,12 +62,214 @@ impl QuantumState {
 
         QuantumState { col }
     }
+
+    // Apply a QuantumGate directly to the amplitudes of the given qubits, without expanding it to the full state size
+    // The first qubit in the list corresponds to the most significant bit of the gate matrix,
+    // so a CNOT applied to [2, 0] uses qubit 2 as control and qubit 0 as target
+    // Panics if the number of qubits does not match the gate size or a qubit is out of range
+    pub fn apply_gate_to_qubits(self, gate: &QuantumGate, qubits: &[usize]) -> QuantumState {
+        self.apply_controlled_gate_to_qubits(gate, &[], qubits)
+    }
+
+    // Apply a QuantumGate to the given qubits only in the blocks of amplitudes where every control has its state,
+    // the same as applying the gate with the controls in its matrix, but without building the larger matrix
+    // Panics if the number of qubits does not match the gate size or a qubit is out of range
+    pub fn apply_controlled_gate_to_qubits(
+        mut self,
+        gate: &QuantumGate,
+        controls: &[Control],
+        qubits: &[usize],
+    ) -> QuantumState {
+        if gate.size != qubits.len() {
+            panic!(
+                "Trying to apply a gate for {} qubits to {} qubits",
+                gate.size,
+                qubits.len()
+            )
+        }
+
+        let no_of_qubits = self.size();
+        let control_qubits = controls.iter().map(|control| &control.qubit);
+        if let Some(qubit) = qubits
+            .iter()
+            .chain(control_qubits)
+            .find(|&&qubit| qubit >= no_of_qubits)
+        {
+            panic!(
+                "Qubit {} is out of range for a state with {} qubits",
+                qubit, no_of_qubits
+            )
+        }
+
+        let dim = 1_usize << gate.size;
+        let offsets = basis_offsets(qubits, no_of_qubits);
+        let mask = offsets[dim - 1];
+        let (control_mask, control_value) =
+            controls
+                .iter()
+                .fold((0, 0), |(control_mask, control_value), control| {
+                    let bit = 1_usize << (no_of_qubits - control.qubit - 1);
+                    let value = if control.state { bit } else { 0 };
+                    (control_mask | bit, control_value | value)
+                });
+
+        let amplitudes = self
+            .col
+            .as_slice_mut()
+            .expect("state vector is stored contiguously");
+        let mut local_amplitudes = vec![Complex::new(0.0, 0.0); dim];
+
+        // Every index with the gate's qubits set to zero is the start of one independent block of amplitudes,
+        // only the blocks where the controls have their state are changed
+        for base in 0..amplitudes.len() {
+            if base & mask != 0 || base & control_mask != control_value {
+                continue;
+            }
+
+            for (local, &offset) in offsets.iter().enumerate() {
+                local_amplitudes[local] = amplitudes[base | offset];
+            }
+
+            for (row, &offset) in offsets.iter().enumerate() {
+                let mut sum = Complex::new(0.0, 0.0);
+                for (column, amplitude) in local_amplitudes.iter().enumerate() {
+                    sum += gate.matrix[[row, column]] * amplitude;
+                }
+                amplitudes[base | offset] = sum;
+            }
+        }
+
+        self
+    }
+
+    // Apply a noise channel given by its Kraus operators to a qubit by sampling one of the operators,
+    // weighted by the probability it gives, and renormalising (a single quantum trajectory)
+    pub fn apply_channel<R: Rng + ?Sized>(
+        self,
+        kraus_operators: &[QuantumGate],
+        qubit: usize,
+        rng: &mut R,
+    ) -> QuantumState {
+        let sample = rng.gen::<f64>();
+        let mut cumulative_probability = 0.0;
+        let mut chosen: Option<(QuantumState, f64)> = None;
+
+        for operator in kraus_operators {
+            let candidate = self.clone().apply_gate_to_qubits(operator, &[qubit]);
+            let probability: f64 = candidate
+                .col
+                .iter()
+                .map(|amplitude| amplitude.norm_sqr())
+                .sum();
+
+            if probability > 0.0 {
+                chosen = Some((candidate, probability));
+            }
+
+            cumulative_probability += probability;
+            if sample < cumulative_probability && chosen.is_some() {
+                break;
+            }
+        }
+
+        let (mut state, probability) =
+            chosen.expect("a noise channel must have a non-zero operator");
+        state
+            .col
+            .mapv_inplace(|amplitude| amplitude / probability.sqrt());
+        state
+    }
+
+    // Calculate the probability that measuring a qubit gives 1
+    pub fn probability_of_one(&self, qubit: usize) -> f64 {
+        let mask = 1_usize << (self.size() - qubit - 1);
+
+        self.col
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| index & mask != 0)
+            .map(|(_, amplitude)| amplitude.norm_sqr())
+            .sum()
+    }
+
+    // Calculate the density matrix of the given qubits by tracing out all other qubits
+    // The first listed qubit is the most significant bit of the reduced density matrix
+    pub fn reduced_density_matrix(&self, qubits: &[usize]) -> DensityMatrix {
+        let dim = 1_usize << qubits.len();
+        let offsets = basis_offsets(qubits, self.size());
+        let mask = offsets[dim - 1];
+        let amplitudes = self
+            .col
+            .as_slice()
+            .expect("state vector is stored contiguously");
+        let mut entries = vec![Complex::new(0.0, 0.0); dim * dim];
+
+        // Every index with the kept qubits set to zero is one state of the other qubits,
+        // which adds the outer product of the kept qubits' amplitudes for that state
+        for base in (0..amplitudes.len()).filter(|base| base & mask == 0) {
+            for (row, &row_offset) in offsets.iter().enumerate() {
+                let amplitude = amplitudes[base | row_offset];
+                for (column, &column_offset) in offsets.iter().enumerate() {
+                    entries[row * dim + column] +=
+                        amplitude * amplitudes[base | column_offset].conj();
+                }
+            }
+        }
+
+        DensityMatrix {
+            matrix: Array2::from_shape_vec((dim, dim), entries).unwrap(),
+        }
+    }
+
+    // Measure a qubit in the computational basis, the outcome is sampled from the current amplitudes
+    // The state is collapsed onto the outcome and renormalised, the outcome (0 or 1) is returned with it
+    pub fn measure<R: Rng + ?Sized>(mut self, qubit: usize, rng: &mut R) -> (QuantumState, u8) {
+        let probability_of_one = self.probability_of_one(qubit);
+        let outcome: u8 = if rng.gen::<f64>() < probability_of_one {
+            1
+        } else {
+            0
+        };
+
+        let norm = if outcome == 1 {
+            probability_of_one.sqrt()
+        } else {
+            (1.0 - probability_of_one).sqrt()
+        };
+
+        let mask = 1_usize << (self.size() - qubit - 1);
+        for (index, amplitude) in self.col.iter_mut().enumerate() {
+            if u8::from(index & mask != 0) == outcome {
+                *amplitude /= norm;
+            } else {
+                *amplitude = Complex::new(0.0, 0.0);
+            }
+        }
+
+        (self, outcome)
+    }
+}
+
+// The offset into a state of n qubits for every basis state of the given qubits,
+// the first qubit is the most significant bit of the local basis state
+pub fn basis_offsets(qubits: &[usize], no_of_qubits: usize) -> Vec<usize> {
+    (0..1_usize << qubits.len())
+        .map(|local| {
+            qubits
+                .iter()
+                .enumerate()
+                .filter(|(k, _)| (local >> (qubits.len() - k - 1)) & 1 == 1)
+                .fold(0, |acc, (_, &qubit)| acc | 1 << (no_of_qubits - qubit - 1))
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use ndarray::arr2;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
 
     // Test that a simple state is correctly initialized
     #[test]
@@ -115,4 +322,154 @@ mod tests {
         let state = QuantumState::new(&[0, 0, 0, 0, 0]);
         assert_eq!(state.size(), 5);
     }
+
+    // Test that states larger than the old six qubit limit can be created
+    #[test]
+    fn test_state_above_six_qubits() {
+        let state = QuantumState::new(&[0; 20]);
+        assert_eq!(state.size(), 20);
+        assert_eq!(state.col[[0, 0]], Complex::new(1.0, 0.0));
+    }
+
+    // Test that applying a gate to a subset of qubits matches the expanded Kronecker product
+    #[test]
+    fn test_apply_gate_to_qubits_matches_kronecker() {
+        let gate = QuantumGate::i_gate()
+            .kronecker(QuantumGate::h_gate())
+            .kronecker(QuantumGate::i_gate());
+
+        let expected_state = QuantumState::new(&[1, 0, 1]).apply_gate(gate);
+        let state =
+            QuantumState::new(&[1, 0, 1]).apply_gate_to_qubits(&QuantumGate::h_gate(), &[1]);
+
+        assert_eq!(state.col, expected_state.col);
+    }
+
+    // Test a CNOT with the control below the target and a qubit in between
+    #[test]
+    fn test_apply_gate_to_non_adjacent_qubits() {
+        // CNOT with control on qubit 2 and target on qubit 0: |001> -> |101>
+        let state =
+            QuantumState::new(&[0, 0, 1]).apply_gate_to_qubits(&QuantumGate::cnot_gate(), &[2, 0]);
+
+        assert_eq!(state.col, QuantumState::new(&[1, 0, 1]).col);
+    }
+
+    // Test that applying a gate only where the controls match is the same as applying the controlled matrix
+    #[test]
+    fn test_apply_controlled_gate_matches_controlled_matrix() {
+        let controls = [
+            Control {
+                qubit: 3,
+                state: true,
+            },
+            Control {
+                qubit: 0,
+                state: false,
+            },
+        ];
+        let initial_state = QuantumState::new(&[0, 0, 0, 0])
+            .apply_gate_to_qubits(&QuantumGate::h_gate(), &[0])
+            .apply_gate_to_qubits(&QuantumGate::h_gate(), &[2])
+            .apply_gate_to_qubits(&QuantumGate::h_gate(), &[3]);
+
+        let expected_state = initial_state.clone().apply_gate_to_qubits(
+            &QuantumGate::ry(0.7).controlled_on(&[true, false]),
+            &[3, 0, 1],
+        );
+        let state =
+            initial_state.apply_controlled_gate_to_qubits(&QuantumGate::ry(0.7), &controls, &[1]);
+
+        for (a, b) in state.col.iter().zip(expected_state.col.iter()) {
+            assert!((a - b).norm() < 1e-12);
+        }
+    }
+
+    // Test that measuring a basis state always gives the same outcome and leaves the state unchanged
+    #[test]
+    fn test_measure_basis_state() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let (state, outcome) = QuantumState::new(&[0, 1]).measure(1, &mut rng);
+        assert_eq!(outcome, 1);
+        assert_eq!(state.col, QuantumState::new(&[0, 1]).col);
+
+        let (state, outcome) = state.measure(0, &mut rng);
+        assert_eq!(outcome, 0);
+        assert_eq!(state.col, QuantumState::new(&[0, 1]).col);
+    }
+
+    // Test that measuring one qubit of a Bell state collapses the other qubit as well
+    #[test]
+    fn test_measure_collapses_entangled_state() {
+        let bell_state = QuantumState::new(&[0, 0])
+            .apply_gate_to_qubits(&QuantumGate::h_gate(), &[0])
+            .apply_gate_to_qubits(&QuantumGate::cnot_gate(), &[0, 1]);
+        assert!((bell_state.probability_of_one(0) - 0.5).abs() < 1e-12);
+
+        for seed in 0..10 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let (state, outcome) = bell_state.clone().measure(0, &mut rng);
+
+            let expected_bits = [outcome as usize, outcome as usize];
+            let expected_state = QuantumState::new(&expected_bits);
+            for (amplitude, expected) in state.col.iter().zip(expected_state.col.iter()) {
+                assert!((amplitude - expected).norm() < 1e-12);
+            }
+        }
+    }
+
+    // Test that a noise channel trajectory picks a Kraus operator and keeps the state normalised
+    #[test]
+    fn test_apply_channel_trajectory() {
+        let bit_flip = [
+            QuantumGate {
+                matrix: QuantumGate::i_gate().matrix * Complex::new(0.5_f64.sqrt(), 0.0),
+                size: 1,
+            },
+            QuantumGate {
+                matrix: QuantumGate::x_gate().matrix * Complex::new(0.5_f64.sqrt(), 0.0),
+                size: 1,
+            },
+        ];
+
+        let mut flipped = 0;
+        for seed in 0..20 {
+            let state = QuantumState::new(&[0]).apply_channel(
+                &bit_flip,
+                0,
+                &mut StdRng::seed_from_u64(seed),
+            );
+
+            let norm: f64 = state.col.iter().map(|amplitude| amplitude.norm_sqr()).sum();
+            assert!((norm - 1.0).abs() < 1e-12);
+            if state.probability_of_one(0) > 0.5 {
+                flipped += 1;
+            }
+        }
+
+        assert!(flipped > 0 && flipped < 20);
+    }
+
+    // Test the reduced density matrices of a product state and a Bell state
+    #[test]
+    fn test_reduced_density_matrix() {
+        let product = QuantumState::new(&[1, 0]).apply_gate_to_qubits(&QuantumGate::h_gate(), &[1]);
+
+        let first = product.reduced_density_matrix(&[0]);
+        let second = product.reduced_density_matrix(&[1]);
+        assert!((first.matrix[[1, 1]].re - 1.0).abs() < 1e-12);
+        for value in second.matrix.iter() {
+            assert!((value.re - 0.5).abs() < 1e-12);
+        }
+
+        let bell = QuantumState::new(&[0, 0, 0])
+            .apply_gate_to_qubits(&QuantumGate::h_gate(), &[0])
+            .apply_gate_to_qubits(&QuantumGate::cnot_gate(), &[0, 2]);
+
+        let pair = bell.reduced_density_matrix(&[0, 2]);
+        assert!((pair.purity() - 1.0).abs() < 1e-12);
+        assert!((pair.matrix[[0, 3]].re - 0.5).abs() < 1e-12);
+        assert!((bell.reduced_density_matrix(&[2]).purity() - 0.5).abs() < 1e-12);
+    }
 }
@@ -0,0 +1,680 @@
+// Map the qubits of a circuit onto the physical qubits of a device where only some pairs are connected
+// Qubit i of the circuit starts on physical qubit i, the physical qubits after the circuit start in |0>
+// Every gate on two qubits that are not connected is preceded by SWAPs along a shortest path
+// between them, moving both qubits halfway so they meet in the middle
+// The SWAPs are not undone, so the qubits can end on other physical qubits, which is the final layout
+// Gates on more than two qubits have to be transpiled first
+// A classical bit belongs to the row that was measured, so a conditioned gate reads the row
+// its qubit was measured on, which fails if another qubit has been measured on that row since
+
+use crate::simulation::circuit::{Circuit, Gate, Operation};
+use crate::simulation::circuit_validator::{validate_circuit, QuantumCircuitError};
+use crate::simulation::gate_registry::GateRegistry;
+use crate::simulation::lint::describe;
+use crate::simulation::quantum_state::QuantumState;
+use crate::simulation::sampler::{has_mid_circuit_measurement, without_measurements};
+use crate::simulation::simulator::run_operations;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+const TOLERANCE: f64 = 1e-9;
+
+// The connected pairs of physical qubits of a device, e.g. { "kind": "grid", "rows": 3, "columns": 3 }
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CouplingMap {
+    // Qubit i is connected to qubit i + 1
+    Line { qubits: usize },
+    // A line where the last qubit is also connected to the first
+    Ring { qubits: usize },
+    // Qubit r * columns + c is connected to the qubits left, right, above and below it
+    Grid { rows: usize, columns: usize },
+    // Lines of columns qubits, with a bridge qubit between two lines every 4 columns,
+    // starting at column 0 after even lines and at column 2 after odd lines like IBM devices
+    // The bridges are numbered after the line above them
+    HeavyHex { rows: usize, columns: usize },
+    // Any pairs, the device has as many qubits as the largest index + 1
+    Edges { edges: Vec<(usize, usize)> },
+}
+
+impl CouplingMap {
+    // The number of qubits, worked out without the edges so a huge map is rejected before they are built
+    // None if the number does not fit in a usize
+    fn qubits(&self) -> Option<usize> {
+        match self {
+            CouplingMap::Line { qubits } | CouplingMap::Ring { qubits } => Some(*qubits),
+            CouplingMap::Grid { rows, columns } => rows.checked_mul(*columns),
+            // Below every line but the last is a bridge for every fourth column,
+            // starting at column 0 below the even lines and at column 2 below the odd ones
+            CouplingMap::HeavyHex { rows, columns } => {
+                let bridges = |start: usize| columns.saturating_sub(start).div_ceil(4);
+                let boundaries = rows.saturating_sub(1);
+
+                rows.checked_mul(*columns)?
+                    .checked_add(bridges(0).checked_mul(boundaries.div_ceil(2))?)?
+                    .checked_add(bridges(2).checked_mul(boundaries / 2)?)
+            }
+            CouplingMap::Edges { edges } => edges
+                .iter()
+                .map(|&(first, second)| first.max(second))
+                .max()
+                .map_or(Some(0), |qubit| qubit.checked_add(1)),
+        }
+    }
+
+    // The connected pairs, only built once qubits() is known to be small enough
+    fn edges(&self) -> Vec<(usize, usize)> {
+        let line = |start: usize, length: usize| {
+            (start..start + length.saturating_sub(1)).map(|qubit| (qubit, qubit + 1))
+        };
+
+        match self {
+            CouplingMap::Line { qubits } => line(0, *qubits).collect(),
+            CouplingMap::Ring { qubits } => {
+                let mut edges: Vec<(usize, usize)> = line(0, *qubits).collect();
+                if *qubits > 2 {
+                    edges.push((qubits - 1, 0));
+                }
+                edges
+            }
+            CouplingMap::Grid { rows, columns } => {
+                let mut edges: Vec<(usize, usize)> = Vec::new();
+                for row in 0..*rows {
+                    edges.extend(line(row * columns, *columns));
+                    if row + 1 < *rows {
+                        edges.extend(
+                            (0..*columns).map(|column| {
+                                (row * columns + column, (row + 1) * columns + column)
+                            }),
+                        );
+                    }
+                }
+                edges
+            }
+            CouplingMap::HeavyHex { rows, columns } => {
+                let mut qubits = 0;
+                let mut edges: Vec<(usize, usize)> = Vec::new();
+                // The bridges below the previous line and the columns they connect to
+                let mut bridges: Vec<(usize, usize)> = Vec::new();
+                for row in 0..*rows {
+                    let start = qubits;
+                    qubits += columns;
+                    edges.extend(line(start, *columns));
+                    edges.extend(
+                        bridges
+                            .iter()
+                            .map(|&(bridge, column)| (bridge, start + column)),
+                    );
+
+                    bridges.clear();
+                    if row + 1 < *rows {
+                        for column in (2 * (row % 2)..*columns).step_by(4) {
+                            edges.push((start + column, qubits));
+                            bridges.push((qubits, column));
+                            qubits += 1;
+                        }
+                    }
+                }
+                edges
+            }
+            CouplingMap::Edges { edges } => edges.clone(),
+        }
+    }
+}
+
+// A coupling map with the distance between every pair of physical qubits
+#[derive(Debug, Clone)]
+pub struct Topology {
+    pub qubits: usize,
+    neighbours: Vec<Vec<usize>>,
+    distances: Vec<Vec<usize>>,
+}
+
+impl Topology {
+    // The device can have at most max_qubits qubits, since every physical qubit is a row of the routed grid
+    pub fn new(map: &CouplingMap, max_qubits: usize) -> Result<Topology, QuantumCircuitError> {
+        let qubits = match map.qubits() {
+            Some(0) => return Err(invalid_map("the coupling map has no qubits".to_string())),
+            Some(qubits) if qubits <= max_qubits => qubits,
+            Some(qubits) => {
+                return Err(invalid_map(format!(
+                    "the coupling map has {} qubits, at most {} are supported",
+                    qubits, max_qubits
+                )))
+            }
+            None => {
+                return Err(invalid_map(format!(
+                    "the coupling map has too many qubits, at most {} are supported",
+                    max_qubits
+                )))
+            }
+        };
+
+        let mut neighbours = vec![Vec::new(); qubits];
+        for (first, second) in map.edges() {
+            if first == second {
+                return Err(invalid_map(format!(
+                    "qubit {} can not be connected to itself",
+                    first
+                )));
+            }
+            neighbours[first].push(second);
+            neighbours[second].push(first);
+        }
+        neighbours.iter_mut().for_each(|list| {
+            list.sort_unstable();
+            list.dedup();
+        });
+
+        let distances: Vec<Vec<usize>> = (0..qubits)
+            .map(|qubit| breadth_first_distances(&neighbours, qubit))
+            .collect();
+        if distances[0].contains(&usize::MAX) {
+            return Err(invalid_map("the coupling map is not connected".to_string()));
+        }
+
+        Ok(Topology {
+            qubits,
+            neighbours,
+            distances,
+        })
+    }
+
+    pub fn are_connected(&self, first: usize, second: usize) -> bool {
+        self.distances[first][second] == 1
+    }
+
+    // A shortest path from first to second including both, through the lowest numbered neighbours
+    fn path(&self, first: usize, second: usize) -> Vec<usize> {
+        let mut path = vec![first];
+        let mut current = first;
+        while current != second {
+            current = *self.neighbours[current]
+                .iter()
+                .find(|&&next| self.distances[next][second] + 1 == self.distances[current][second])
+                .unwrap();
+            path.push(current);
+        }
+        path
+    }
+}
+
+fn breadth_first_distances(neighbours: &[Vec<usize>], start: usize) -> Vec<usize> {
+    let mut distances = vec![usize::MAX; neighbours.len()];
+    distances[start] = 0;
+    let mut queue = VecDeque::from([start]);
+    while let Some(qubit) = queue.pop_front() {
+        for &next in &neighbours[qubit] {
+            if distances[next] == usize::MAX {
+                distances[next] = distances[qubit] + 1;
+                queue.push_back(next);
+            }
+        }
+    }
+    distances
+}
+
+fn invalid_map(reason: String) -> QuantumCircuitError {
+    QuantumCircuitError::InvalidCouplingMap(reason)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoutedCircuit {
+    // One row per physical qubit
+    pub circuit: Circuit,
+    pub swaps: usize,
+    // The physical qubit every qubit of the circuit ends on
+    pub final_layout: Vec<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Routing {
+    pub circuit_matrix: Vec<Vec<String>>,
+    pub swaps: usize,
+    pub final_layout: Vec<usize>,
+    // Whether the routed circuit gives the same distribution of measurement outcomes for the qubits of the circuit,
+    // only for circuits without mid-circuit measurements on devices with at most max_simulation_qubits qubits
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub same_distribution: Option<bool>,
+}
+
+// Validate and route the grid and check the result against the original if the device is small enough to simulate
+pub fn route_grid(
+    grid: &[Vec<&str>],
+    map: &CouplingMap,
+    max_qubits: usize,
+    max_simulation_qubits: usize,
+    gates: &GateRegistry,
+) -> Result<Routing, QuantumCircuitError> {
+    let circuit = validate_circuit(grid, max_qubits, gates)?;
+    let topology = Topology::new(map, max_qubits)?;
+    let routed = route_circuit(&circuit, &topology)?;
+
+    let same_distribution = (topology.qubits <= max_simulation_qubits
+        && !has_mid_circuit_measurement(&circuit))
+    .then(|| has_same_distribution(&circuit, &routed));
+
+    Ok(Routing {
+        circuit_matrix: routed.circuit.to_grid(),
+        swaps: routed.swaps,
+        final_layout: routed.final_layout,
+        same_distribution,
+    })
+}
+
+// The gates are packed into as few columns as possible, see Circuit::from_operations
+pub fn route_circuit(
+    circuit: &Circuit,
+    topology: &Topology,
+) -> Result<RoutedCircuit, QuantumCircuitError> {
+    if circuit.qubits > topology.qubits {
+        return Err(invalid_map(format!(
+            "the circuit has {} qubits, but the coupling map only {}",
+            circuit.qubits, topology.qubits
+        )));
+    }
+
+    let mut router = Router {
+        topology,
+        layout: (0..topology.qubits).collect(),
+        occupants: (0..topology.qubits).collect(),
+        measured_on: vec![0; circuit.qubits],
+        bits: vec![None; topology.qubits],
+        operations: Vec::new(),
+        swaps: 0,
+    };
+    for operation in circuit.operations() {
+        router.operation(operation)?;
+    }
+
+    Ok(RoutedCircuit {
+        circuit: Circuit::from_operations(topology.qubits, router.operations),
+        swaps: router.swaps,
+        final_layout: router.layout[..circuit.qubits].to_vec(),
+    })
+}
+
+struct Router<'a> {
+    topology: &'a Topology,
+    // The physical qubit of every qubit, the qubits after those of the circuit are the unused physical qubits
+    layout: Vec<usize>,
+    // The qubit on every physical qubit, the inverse of the layout
+    occupants: Vec<usize>,
+    // The row every qubit of the circuit was last measured on
+    measured_on: Vec<usize>,
+    // The qubit of the circuit whose measurement is in the classical bit of every row
+    bits: Vec<Option<usize>>,
+    operations: Vec<Operation>,
+    swaps: usize,
+}
+
+impl Router<'_> {
+    fn operation(&mut self, operation: &Operation) -> Result<(), QuantumCircuitError> {
+        match operation.qubits()[..] {
+            [_] => (),
+            [first, second] => self.connect(self.layout[first], self.layout[second]),
+            _ => {
+                return Err(QuantumCircuitError::UnroutableCircuit(format!(
+                    "{} acts on more than two qubits, transpile it first",
+                    describe(operation)
+                )))
+            }
+        }
+
+        let mut routed = operation.clone();
+        routed.targets = operation
+            .targets
+            .iter()
+            .map(|&qubit| self.layout[qubit])
+            .collect();
+        routed
+            .controls
+            .iter_mut()
+            .for_each(|control| control.qubit = self.layout[control.qubit]);
+
+        if let Some(bit) = operation.condition {
+            let row = self.measured_on[bit];
+            if self.bits[row] != Some(bit) {
+                return Err(QuantumCircuitError::UnroutableCircuit(format!(
+                    "the measurement of qubit {} that {} reads is overwritten by another measurement on row {}",
+                    bit,
+                    describe(operation),
+                    row
+                )));
+            }
+            routed.condition = Some(row);
+        }
+        if operation.is_measurement() {
+            self.measured_on[operation.targets[0]] = routed.targets[0];
+            self.bits[routed.targets[0]] = Some(operation.targets[0]);
+        }
+
+        self.operations.push(routed);
+        Ok(())
+    }
+
+    // Move the qubits on two physical qubits towards each other until they are connected
+    fn connect(&mut self, first: usize, second: usize) {
+        if self.topology.are_connected(first, second) {
+            return;
+        }
+
+        let path = self.topology.path(first, second);
+        let last = path.len() - 1;
+        let meeting = last / 2;
+        for index in 0..meeting {
+            self.swap(path[index], path[index + 1]);
+        }
+        for index in (meeting + 2..=last).rev() {
+            self.swap(path[index], path[index - 1]);
+        }
+    }
+
+    fn swap(&mut self, first: usize, second: usize) {
+        self.operations
+            .push(Operation::new(Gate::Swap, vec![first, second], Vec::new()));
+        self.swaps += 1;
+
+        self.occupants.swap(first, second);
+        self.layout[self.occupants[first]] = first;
+        self.layout[self.occupants[second]] = second;
+    }
+}
+
+// Compare the probabilities of the measurement outcomes of the original circuit with those of the routed one,
+// read from the physical qubits of the final layout, the unused physical qubits have to end in |0>
+fn has_same_distribution(circuit: &Circuit, routed: &RoutedCircuit) -> bool {
+    let probabilities = |circuit: &Circuit| -> Vec<f64> {
+        let (state, _) = run_operations(
+            &without_measurements(circuit),
+            QuantumState::new(&vec![0; circuit.qubits]),
+            None,
+            &mut rand::thread_rng(),
+            |_, _, _, _| (),
+        );
+        state
+            .col
+            .iter()
+            .map(|amplitude| amplitude.norm_sqr())
+            .collect()
+    };
+
+    // Qubit 0 is the most significant bit of an index
+    let physical_qubits = routed.circuit.qubits;
+    let bit = |index: usize, qubit: usize, qubits: usize| (index >> (qubits - 1 - qubit)) & 1;
+
+    let expected = probabilities(circuit);
+    let mut actual = vec![0.0; expected.len()];
+    for (index, probability) in probabilities(&routed.circuit).into_iter().enumerate() {
+        let logical_index = routed
+            .final_layout
+            .iter()
+            .enumerate()
+            .map(|(qubit, &physical)| {
+                bit(index, physical, physical_qubits) << (circuit.qubits - 1 - qubit)
+            })
+            .sum::<usize>();
+        let unused_bits = (0..physical_qubits)
+            .filter(|physical| !routed.final_layout.contains(physical))
+            .any(|physical| bit(index, physical, physical_qubits) == 1);
+
+        if unused_bits && probability > TOLERANCE {
+            return false;
+        }
+        actual[logical_index] += probability;
+    }
+
+    expected
+        .iter()
+        .zip(&actual)
+        .all(|(expected, actual)| (expected - actual).abs() < TOLERANCE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn topology(map: CouplingMap) -> Topology {
+        Topology::new(&map, 1000).unwrap()
+    }
+
+    fn route(grid: &[Vec<&str>], map: CouplingMap) -> Routing {
+        route_grid(grid, &map, 1000, 12, &GateRegistry::default()).unwrap()
+    }
+
+    // Every gate of the routed grid acts on connected qubits
+    fn assert_connected(routing: &Routing, map: CouplingMap) {
+        let topology = topology(map);
+        let grid: Vec<Vec<&str>> = routing
+            .circuit_matrix
+            .iter()
+            .map(|row| row.iter().map(|cell| cell.as_str()).collect())
+            .collect();
+        let circuit = validate_circuit(&grid, 1000, &GateRegistry::default()).unwrap();
+
+        for operation in circuit.operations() {
+            if let [first, second] = operation.qubits()[..] {
+                assert!(topology.are_connected(first, second), "{:?}", operation);
+            }
+        }
+    }
+
+    #[test]
+    fn test_coupling_maps() {
+        let ring = topology(CouplingMap::Ring { qubits: 6 });
+        assert!(ring.are_connected(5, 0));
+        assert_eq!(ring.distances[0][3], 3);
+
+        let grid = topology(CouplingMap::Grid {
+            rows: 3,
+            columns: 4,
+        });
+        assert_eq!(grid.qubits, 12);
+        assert!(grid.are_connected(1, 5));
+        assert!(!grid.are_connected(3, 4));
+        assert_eq!(grid.distances[0][11], 5);
+
+        // Two lines of 5 with bridges 5 and 6 at columns 0 and 4 form one hexagon of 12 qubits
+        let heavy_hex = topology(CouplingMap::HeavyHex {
+            rows: 2,
+            columns: 5,
+        });
+        assert_eq!(heavy_hex.qubits, 12);
+        assert_eq!(heavy_hex.neighbours[5], vec![0, 7]);
+        assert_eq!(heavy_hex.neighbours[6], vec![4, 11]);
+        assert_eq!(heavy_hex.distances[2][9], 6);
+        assert!((0..12).all(|qubit| heavy_hex.neighbours[qubit].len() <= 3));
+
+        // The bridges after the second line start at column 2
+        let heavy_hex = topology(CouplingMap::HeavyHex {
+            rows: 3,
+            columns: 7,
+        });
+        assert_eq!(heavy_hex.qubits, 21 + 2 + 2);
+        assert_eq!(heavy_hex.neighbours[16], vec![11, 20]);
+    }
+
+    #[test]
+    fn test_qubits_match_edges() {
+        // From 3 columns on every line is connected to the next, so the last qubit is in an edge
+        for rows in 1..6 {
+            for columns in 3..10 {
+                let map = CouplingMap::HeavyHex { rows, columns };
+                let largest = map.edges().iter().map(|&(a, b)| a.max(b)).max();
+
+                assert_eq!(map.qubits(), Some(largest.map_or(1, |qubit| qubit + 1)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_coupling_maps() {
+        for map in [
+            CouplingMap::Line { qubits: 0 },
+            CouplingMap::Edges { edges: vec![] },
+            CouplingMap::Edges {
+                edges: vec![(0, 1), (2, 3)],
+            },
+            CouplingMap::Edges {
+                edges: vec![(0, 1), (1, 1)],
+            },
+            CouplingMap::Grid {
+                rows: 40,
+                columns: 40,
+            },
+            // Too large to build the edges of, or to count the qubits of without overflow
+            CouplingMap::Line {
+                qubits: 1_000_000_000_000_000,
+            },
+            CouplingMap::Grid {
+                rows: usize::MAX,
+                columns: 2,
+            },
+            CouplingMap::HeavyHex {
+                rows: usize::MAX / 2,
+                columns: 3,
+            },
+            CouplingMap::Edges {
+                edges: vec![(0, usize::MAX)],
+            },
+        ] {
+            assert!(matches!(
+                Topology::new(&map, 1000),
+                Err(QuantumCircuitError::InvalidCouplingMap(_))
+            ));
+        }
+
+        let grid = vec![vec!["H"], vec!["H"], vec!["H"]];
+        assert!(matches!(
+            route_grid(
+                &grid,
+                &CouplingMap::Line { qubits: 2 },
+                10,
+                10,
+                &GateRegistry::default()
+            ),
+            Err(QuantumCircuitError::InvalidCouplingMap(_))
+        ));
+    }
+
+    #[test]
+    fn test_connected_gates_are_unchanged() {
+        let grid = vec![vec!["H", "CNOT-1"], vec!["I", "CNOT-2"]];
+
+        let routing = route(&grid, CouplingMap::Line { qubits: 2 });
+
+        assert_eq!(
+            routing.circuit_matrix,
+            vec![vec!["H", "CNOT-1"], vec!["I", "CNOT-2"]]
+        );
+        assert_eq!(routing.swaps, 0);
+        assert_eq!(routing.final_layout, vec![0, 1]);
+        assert_eq!(routing.same_distribution, Some(true));
+    }
+
+    #[test]
+    fn test_swaps_meet_in_the_middle() {
+        // Qubits 0 and 4 of a line are 4 apart, so qubit 0 moves to 2 and qubit 4 to 3
+        let grid = vec![
+            vec!["H", "CNOT-1"],
+            vec!["I", "I"],
+            vec!["I", "I"],
+            vec!["I", "I"],
+            vec!["I", "CNOT-2"],
+        ];
+
+        let routing = route(&grid, CouplingMap::Line { qubits: 5 });
+
+        assert_eq!(routing.swaps, 3);
+        assert_eq!(routing.final_layout, vec![2, 0, 1, 4, 3]);
+        assert_eq!(routing.same_distribution, Some(true));
+        assert_connected(&routing, CouplingMap::Line { qubits: 5 });
+    }
+
+    #[test]
+    fn test_route_onto_every_map() {
+        let grid = vec![
+            vec!["H", "CNOT-1", "I", "CZ-2", "SWAP-1", "C", "RX(0.3)", "M"],
+            vec!["RY(1)", "I", "CNOT-1", "I", "I", "I", "O", "M"],
+            vec!["T", "I", "I", "I", "SWAP-2", "I", "I", "M"],
+            vec!["I", "CNOT-2", "I", "CZ-1", "I", "I", "I", "M"],
+            vec!["X", "I", "CNOT-2", "I", "I", "Y", "I", "M"],
+        ];
+        let maps = [
+            CouplingMap::Line { qubits: 5 },
+            CouplingMap::Ring { qubits: 7 },
+            CouplingMap::Grid {
+                rows: 2,
+                columns: 3,
+            },
+            CouplingMap::HeavyHex {
+                rows: 2,
+                columns: 5,
+            },
+            CouplingMap::Edges {
+                edges: vec![(0, 2), (2, 4), (4, 1), (1, 3)],
+            },
+        ];
+
+        for map in maps {
+            let routing = route(&grid, map.clone());
+
+            assert!(routing.swaps > 0, "{:?}", map);
+            assert_eq!(routing.same_distribution, Some(true), "{:?}", map);
+            assert_connected(&routing, map);
+        }
+    }
+
+    #[test]
+    fn test_classical_conditions_follow_the_measured_row() {
+        // The CNOT moves qubit 0 next to qubit 2 after it was measured, the condition keeps reading row 0
+        let grid = vec![
+            vec!["H", "M", "CNOT-1", "I"],
+            vec!["I", "I", "I", "X?c0"],
+            vec!["I", "I", "CNOT-2", "I"],
+        ];
+
+        let routing = route(&grid, CouplingMap::Line { qubits: 3 });
+
+        assert_eq!(routing.same_distribution, None);
+        assert_eq!(routing.final_layout, vec![1, 0, 2]);
+        assert!(routing.circuit_matrix[0].contains(&"X?c0".to_string()));
+
+        // Qubit 1 moves to row 0 and is measured there too, so the bit of qubit 0 is gone
+        let grid = vec![
+            vec!["M", "CNOT-1", "I", "I"],
+            vec!["I", "I", "M", "I"],
+            vec!["I", "CNOT-2", "I", "X?c0"],
+        ];
+        assert!(matches!(
+            route_grid(
+                &grid,
+                &CouplingMap::Line { qubits: 3 },
+                10,
+                10,
+                &GateRegistry::default()
+            ),
+            Err(QuantumCircuitError::UnroutableCircuit(_))
+        ));
+    }
+
+    #[test]
+    fn test_gates_on_three_qubits_are_unroutable() {
+        let grid = vec![vec!["CCNOT-1"], vec!["CCNOT-2"], vec!["CCNOT-3"]];
+
+        assert_eq!(
+            route_grid(
+                &grid,
+                &CouplingMap::Line { qubits: 3 },
+                10,
+                10,
+                &GateRegistry::default()
+            )
+            .unwrap_err(),
+            QuantumCircuitError::UnroutableCircuit(
+                "CCNOT on qubits 0, 1, 2 acts on more than two qubits, transpile it first"
+                    .to_string()
+            )
+        );
+    }
+}
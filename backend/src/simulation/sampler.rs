@@ -0,0 +1,306 @@
+use crate::simulation::circuit::{Circuit, Moment};
+use crate::simulation::circuit_validator::{validate_circuit, QuantumCircuitError};
+use crate::simulation::gate_registry::GateRegistry;
+use crate::simulation::quantum_state::QuantumState;
+use crate::simulation::simulator::run_operations;
+use rand::Rng;
+use std::collections::BTreeMap;
+
+// Run the circuit for a number of shots and count how often every outcome occurs
+// An outcome is the bitstring from measuring all qubits at the end, with qubit 0 as the leftmost bit
+// Without mid-circuit measurements the circuit is simulated once and every shot is sampled from the final amplitudes,
+// otherwise every shot simulates its own trajectory, so shots * 2^n is limited by max_trajectory_amplitudes
+pub fn sample_circuit<R: Rng + ?Sized>(
+    incoming_data: Vec<Vec<&str>>,
+    max_qubits: usize,
+    gates: &GateRegistry,
+    shots: usize,
+    max_shots: usize,
+    max_trajectory_amplitudes: usize,
+    rng: &mut R,
+) -> Result<BTreeMap<String, usize>, QuantumCircuitError> {
+    let circuit = validate_circuit(&incoming_data, max_qubits, gates)?;
+
+    if shots == 0 || shots > max_shots {
+        return Err(QuantumCircuitError::InvalidShotCount);
+    }
+
+    let no_of_qubits = circuit.qubits;
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+
+    if has_mid_circuit_measurement(&circuit) {
+        let amplitudes = 1_usize
+            .checked_shl(no_of_qubits as u32)
+            .and_then(|dim| dim.checked_mul(shots));
+        if amplitudes.is_none_or(|amplitudes| amplitudes > max_trajectory_amplitudes) {
+            return Err(QuantumCircuitError::InvalidShotCount);
+        }
+
+        for _ in 0..shots {
+            let (state, _) = run_operations(
+                &circuit,
+                QuantumState::new(&vec![0; no_of_qubits]),
+                None,
+                rng,
+                |_, _, _, _| (),
+            );
+
+            let index = sample_indices(&state, 1, rng)[0];
+            *counts.entry(to_bitstring(index, no_of_qubits)).or_insert(0) += 1;
+        }
+    } else {
+        // Measurements at the end of a row give the same distribution as measuring the final state
+        let (state, _) = run_operations(
+            &without_measurements(&circuit),
+            QuantumState::new(&vec![0; no_of_qubits]),
+            None,
+            rng,
+            |_, _, _, _| (),
+        );
+
+        for index in sample_indices(&state, shots, rng) {
+            *counts.entry(to_bitstring(index, no_of_qubits)).or_insert(0) += 1;
+        }
+    }
+
+    Ok(counts)
+}
+
+// The circuit with its measurements left out
+pub fn without_measurements(circuit: &Circuit) -> Circuit {
+    Circuit {
+        qubits: circuit.qubits,
+        moments: circuit
+            .moments
+            .iter()
+            .map(|moment| Moment {
+                operations: moment
+                    .operations
+                    .iter()
+                    .filter(|operation| !operation.is_measurement())
+                    .cloned()
+                    .collect(),
+            })
+            .collect(),
+    }
+}
+
+// A measurement is mid-circuit if a classically controlled gate depends on it
+// or if its qubit has another operation after it
+pub fn has_mid_circuit_measurement(circuit: &Circuit) -> bool {
+    let mut measured = vec![false; circuit.qubits];
+
+    circuit.has_conditions()
+        || circuit.operations().any(|operation| {
+            let qubits = operation.qubits();
+            let after_measurement = qubits.iter().any(|&qubit| measured[qubit]);
+            if operation.is_measurement() {
+                measured[qubits[0]] = true;
+            }
+            after_measurement
+        })
+}
+
+// Sample basis state indices from the probabilities of the amplitudes
+fn sample_indices<R: Rng + ?Sized>(state: &QuantumState, shots: usize, rng: &mut R) -> Vec<usize> {
+    let cumulative: Vec<f64> = state
+        .col
+        .iter()
+        .scan(0.0, |total, amplitude| {
+            *total += amplitude.norm_sqr();
+            Some(*total)
+        })
+        .collect();
+    let total = *cumulative.last().unwrap();
+
+    (0..shots)
+        .map(|_| {
+            let sample = rng.gen::<f64>() * total;
+            cumulative
+                .partition_point(|&probability| probability <= sample)
+                .min(cumulative.len() - 1)
+        })
+        .collect()
+}
+
+// Qubit 0 is the most significant bit of the index, so it becomes the leftmost bit
+fn to_bitstring(index: usize, no_of_qubits: usize) -> String {
+    format!("{:0width$b}", index, width = no_of_qubits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::circuit_parser::parse_circuit;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_deterministic_circuit() {
+        let grid = vec![vec!["X"], vec!["I"], vec!["X"]];
+
+        let counts = sample_circuit(
+            grid,
+            6,
+            &GateRegistry::default(),
+            100,
+            1000,
+            1 << 20,
+            &mut StdRng::seed_from_u64(0),
+        )
+        .unwrap();
+
+        assert_eq!(counts, BTreeMap::from([("101".to_string(), 100)]));
+    }
+
+    #[test]
+    fn test_bell_state_counts() {
+        let grid = vec![vec!["H", "CNOT-1", "M"], vec!["I", "CNOT-2", "M"]];
+
+        let counts = sample_circuit(
+            grid,
+            6,
+            &GateRegistry::default(),
+            1000,
+            1000,
+            1 << 20,
+            &mut StdRng::seed_from_u64(0),
+        )
+        .unwrap();
+
+        assert_eq!(counts.keys().collect::<Vec<_>>(), vec!["00", "11"]);
+        assert_eq!(counts.values().sum::<usize>(), 1000);
+        assert!(counts["00"] > 400 && counts["11"] > 400);
+    }
+
+    #[test]
+    fn test_seed_is_reproducible() {
+        let grid = vec![vec!["H"], vec!["H"]];
+
+        let first = sample_circuit(
+            grid.clone(),
+            6,
+            &GateRegistry::default(),
+            50,
+            1000,
+            1 << 20,
+            &mut StdRng::seed_from_u64(7),
+        );
+        let second = sample_circuit(
+            grid,
+            6,
+            &GateRegistry::default(),
+            50,
+            1000,
+            1 << 20,
+            &mut StdRng::seed_from_u64(7),
+        );
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_mid_circuit_measurement() {
+        // Measure |+>, then flip qubit 1 if the outcome was 1, so both qubits always agree
+        let grid = vec![vec!["H", "M", "I"], vec!["I", "I", "X?c0"]];
+        assert!(has_mid_circuit_measurement(
+            &parse_circuit(&grid, &GateRegistry::default()).unwrap()
+        ));
+
+        let counts = sample_circuit(
+            grid,
+            6,
+            &GateRegistry::default(),
+            200,
+            1000,
+            1 << 20,
+            &mut StdRng::seed_from_u64(3),
+        )
+        .unwrap();
+
+        assert_eq!(counts.keys().collect::<Vec<_>>(), vec!["00", "11"]);
+    }
+
+    #[test]
+    fn test_trajectory_budget() {
+        let grid = vec![vec!["H", "M", "I"], vec!["I", "I", "X?c0"]];
+
+        assert!(sample_circuit(
+            grid.clone(),
+            6,
+            &GateRegistry::default(),
+            256,
+            1000,
+            1 << 10,
+            &mut StdRng::seed_from_u64(0)
+        )
+        .is_ok());
+        assert_eq!(
+            sample_circuit(
+                grid,
+                6,
+                &GateRegistry::default(),
+                257,
+                1000,
+                1 << 10,
+                &mut StdRng::seed_from_u64(0)
+            ),
+            Err(QuantumCircuitError::InvalidShotCount)
+        );
+
+        // Sampling from the final amplitudes is not limited
+        assert!(sample_circuit(
+            vec![vec!["H", "M"], vec!["H", "M"]],
+            6,
+            &GateRegistry::default(),
+            1000,
+            1000,
+            1 << 10,
+            &mut StdRng::seed_from_u64(0)
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_measurement_at_end_is_not_mid_circuit() {
+        let grid = vec![vec!["H", "M", "I"], vec!["X", "I", "M"]];
+        assert!(!has_mid_circuit_measurement(
+            &parse_circuit(&grid, &GateRegistry::default()).unwrap()
+        ));
+
+        let grid = vec![vec!["M", "H"]];
+        assert!(has_mid_circuit_measurement(
+            &parse_circuit(&grid, &GateRegistry::default()).unwrap()
+        ));
+    }
+
+    #[test]
+    fn test_invalid_shot_count() {
+        let grid = vec![vec!["H"]];
+
+        assert_eq!(
+            sample_circuit(
+                grid.clone(),
+                6,
+                &GateRegistry::default(),
+                0,
+                1000,
+                1 << 20,
+                &mut StdRng::seed_from_u64(0)
+            ),
+            Err(QuantumCircuitError::InvalidShotCount)
+        );
+        assert_eq!(
+            sample_circuit(
+                grid,
+                6,
+                &GateRegistry::default(),
+                1001,
+                1000,
+                1 << 20,
+                &mut StdRng::seed_from_u64(0)
+            ),
+            Err(QuantumCircuitError::InvalidShotCount)
+        );
+    }
+}
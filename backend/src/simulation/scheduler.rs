@@ -0,0 +1,206 @@
+// Pack the gates of a circuit into as few columns as possible, without changing the order of gates on a qubit
+// - Asap: every gate goes in the first column after the gates it depends on, see Circuit::from_operations
+// - Alap: every gate goes in the last column before the gates that depend on it, the circuit is packed
+//   backwards and reversed, so gates that only prepare later ones move next to them
+// The grid rules are kept: a gate with control markers gets a column of its own, a classically controlled gate
+// comes after the measurement of its bit and a measurement after the gates that read its bit
+// A gate with control markers can still delay the gates that could share a column with it
+
+use crate::simulation::circuit::{Circuit, Moment, Operation};
+use crate::simulation::circuit_validator::{validate_circuit, QuantumCircuitError};
+use crate::simulation::gate_registry::GateRegistry;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Schedule {
+    #[default]
+    Asap,
+    Alap,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Scheduling {
+    pub circuit_matrix: Vec<Vec<String>>,
+    pub columns_before: usize,
+    pub columns_after: usize,
+}
+
+// Validate and compact the grid
+pub fn schedule_grid(
+    grid: &[Vec<&str>],
+    schedule: Schedule,
+    max_qubits: usize,
+    gates: &GateRegistry,
+) -> Result<Scheduling, QuantumCircuitError> {
+    let circuit = validate_circuit(grid, max_qubits, gates)?;
+    let scheduled = schedule_circuit(&circuit, schedule);
+
+    Ok(Scheduling {
+        circuit_matrix: scheduled.to_grid(),
+        columns_before: circuit.moments.len(),
+        columns_after: scheduled.moments.len(),
+    })
+}
+
+// A circuit without gates keeps one empty column if it had any, so scheduling never adds a column
+pub fn schedule_circuit(circuit: &Circuit, schedule: Schedule) -> Circuit {
+    let operations: Vec<Operation> = circuit.operations().cloned().collect();
+
+    let mut scheduled = match schedule {
+        Schedule::Asap => Circuit::from_operations(circuit.qubits, operations),
+        // Reading the circuit backwards a measurement still comes after the gates that read it
+        // and before the gates that read the measurement before it, so the rules are the same
+        Schedule::Alap => {
+            let mut reversed =
+                Circuit::from_operations(circuit.qubits, operations.into_iter().rev().collect());
+            reversed.moments.reverse();
+            reversed
+        }
+    };
+    if scheduled.moments.is_empty() && !circuit.moments.is_empty() {
+        scheduled.moments.push(Moment::default());
+    }
+
+    scheduled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(grid: &[Vec<&str>], schedule: Schedule) -> Scheduling {
+        schedule_grid(grid, schedule, 10, &GateRegistry::default()).unwrap()
+    }
+
+    #[test]
+    fn test_asap() {
+        let grid = vec![
+            vec!["H", "I", "I", "CNOT-1", "I"],
+            vec!["I", "X", "I", "CNOT-2", "I"],
+            vec!["I", "I", "Y", "I", "Z"],
+        ];
+
+        let scheduling = schedule(&grid, Schedule::Asap);
+
+        assert_eq!(
+            scheduling.circuit_matrix,
+            vec![vec!["H", "CNOT-1"], vec!["X", "CNOT-2"], vec!["Y", "Z"],]
+        );
+        assert_eq!(scheduling.columns_before, 5);
+        assert_eq!(scheduling.columns_after, 2);
+    }
+
+    #[test]
+    fn test_alap() {
+        let grid = vec![
+            vec!["H", "I", "CNOT-1", "H"],
+            vec!["I", "I", "CNOT-2", "I"],
+            vec!["X", "I", "I", "I"],
+        ];
+
+        let scheduling = schedule(&grid, Schedule::Alap);
+
+        assert_eq!(
+            scheduling.circuit_matrix,
+            vec![
+                vec!["H", "CNOT-1", "H"],
+                vec!["I", "CNOT-2", "I"],
+                vec!["I", "I", "X"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_gates_of_the_same_kind_are_grouped() {
+        let grid = vec![
+            vec!["CNOT-1", "I"],
+            vec!["CNOT-2", "I"],
+            vec!["I", "CNOT-2"],
+            vec!["I", "CNOT-1"],
+        ];
+
+        let scheduling = schedule(&grid, Schedule::Asap);
+
+        assert_eq!(scheduling.columns_after, 1);
+        let scheduled: Vec<Vec<&str>> = scheduling
+            .circuit_matrix
+            .iter()
+            .map(|row| row.iter().map(|cell| cell.as_str()).collect())
+            .collect();
+        let before = validate_circuit(&grid, 10, &GateRegistry::default()).unwrap();
+        let after = validate_circuit(&scheduled, 10, &GateRegistry::default()).unwrap();
+        assert_eq!(
+            after.operations().collect::<Vec<_>>(),
+            before.operations().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_controlled_gates_keep_their_own_column() {
+        let grid = vec![
+            vec!["H", "C", "I"],
+            vec!["I", "X", "I"],
+            vec!["I", "I", "Z"],
+            vec!["Y", "I", "I"],
+        ];
+
+        for order in [Schedule::Asap, Schedule::Alap] {
+            let scheduling = schedule(&grid, order);
+
+            assert_eq!(scheduling.columns_after, 2, "{:?}", order);
+            let controlled_column = scheduling.circuit_matrix[0]
+                .iter()
+                .position(|cell| cell == "C")
+                .unwrap();
+            assert!(scheduling
+                .circuit_matrix
+                .iter()
+                .skip(2)
+                .all(|row| row[controlled_column] == "I"));
+        }
+    }
+
+    #[test]
+    fn test_classical_bits_keep_their_order() {
+        let grid = vec![
+            vec!["H", "M", "I", "I", "I"],
+            vec!["I", "I", "I", "X?c0", "I"],
+            vec!["I", "I", "I", "I", "H"],
+        ];
+
+        assert_eq!(
+            schedule(&grid, Schedule::Asap).circuit_matrix,
+            vec![
+                vec!["H", "M", "I"],
+                vec!["I", "I", "X?c0"],
+                vec!["H", "I", "I"],
+            ]
+        );
+        assert_eq!(
+            schedule(&grid, Schedule::Alap).circuit_matrix,
+            vec![
+                vec!["H", "M", "I"],
+                vec!["I", "I", "X?c0"],
+                vec!["I", "I", "H"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty_circuit() {
+        let scheduling = schedule(&[vec!["I", "I"], vec!["I", "I"]], Schedule::Alap);
+
+        assert_eq!(scheduling.circuit_matrix, vec![vec!["I"], vec!["I"]]);
+        assert_eq!(scheduling.columns_after, 1);
+    }
+
+    #[test]
+    fn test_grid_without_columns() {
+        let scheduling = schedule(&[vec![], vec![]], Schedule::Asap);
+
+        assert_eq!(scheduling.circuit_matrix, vec![Vec::<String>::new(); 2]);
+        assert_eq!(scheduling.columns_before, 0);
+        assert_eq!(scheduling.columns_after, 0);
+    }
+}
@@ -1,44 +1,476 @@
-use crate::simulation::circuit_parser::build_circuit_from_data;
-use crate::simulation::circuit_validator::{validate_grid_input, QuantumCircuitError};
+use crate::simulation::circuit::{Circuit, Operation, MAX_MATRIX_QUBITS};
+use crate::simulation::circuit_parser::parse_circuit;
+use crate::simulation::circuit_validator::{validate_circuit, QuantumCircuitError};
+use crate::simulation::density_matrix::DensityMatrix;
+use crate::simulation::entanglement::analyse_entanglement;
+use crate::simulation::gate_registry::GateRegistry;
+use crate::simulation::mps::{MatrixProductState, MpsOptions};
+use crate::simulation::noise::NoiseModel;
+use crate::simulation::observable::PauliString;
 use crate::simulation::quantum_gate::QuantumGate;
 use crate::simulation::quantum_state::QuantumState;
-use crate::simulation::utils::{format_to_complex_container, to_little_endian};
-use crate::Step;
-use ndarray::Array1;
+use crate::simulation::stabilizer::{is_clifford_circuit, Tableau};
+use crate::simulation::utils::{
+    density_matrix_to_little_endian, format_density_matrix, format_qubit_states,
+    format_to_complex_container, to_little_endian,
+};
+use crate::{ClassicalCondition, Step};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 
-pub fn simulate_circuit(incoming_data: Vec<Vec<&str>>) -> Result<Vec<Step>, QuantumCircuitError> {
-    let validation_result = validate_grid_input(&incoming_data);
-    if validation_result.is_err() {
-        return Err(validation_result.unwrap_err());
+// How the state is represented during the simulation
+// A state vector holds 2^n amplitudes, a density matrix 4^n entries but can also represent mixed states
+// A stabilizer tableau holds 2n Pauli strings, but only for circuits of Clifford gates without noise
+// A matrix product state grows with the entanglement instead of the number of qubits and may be truncated
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SimulationMode {
+    #[default]
+    StateVector,
+    DensityMatrix,
+    Stabilizer,
+    Mps,
+}
+
+// Settings for a simulation, given in the request next to the circuit
+// In state vector mode noise is applied by sampling a single trajectory,
+// in density matrix mode the channels are applied exactly
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SimulationOptions {
+    #[serde(default)]
+    pub simulation_mode: SimulationMode,
+    #[serde(default)]
+    pub noise: Option<NoiseModel>,
+    // Add the entanglement analysis to every step
+    #[serde(default)]
+    pub analysis: bool,
+    // Only used in mps mode
+    #[serde(default)]
+    pub mps: MpsOptions,
+    // Set by the server, not the request: for circuits with more qubits only the last step has the amplitudes
+    #[serde(skip)]
+    pub max_step_state_qubits: Option<usize>,
+}
+
+impl SimulationOptions {
+    // The options to simulate the grid with, switching from the state vector to the stabilizer backend
+    // for noiseless Clifford circuits with more qubits than a state vector is allowed to have
+    pub fn for_circuit(
+        &self,
+        grid: &[Vec<&str>],
+        max_state_vector_qubits: usize,
+        gates: &GateRegistry,
+    ) -> SimulationOptions {
+        let use_stabilizer = self.simulation_mode == SimulationMode::StateVector
+            && self.noise.is_none()
+            && grid.len() > max_state_vector_qubits
+            && parse_circuit(grid, gates).is_ok_and(|circuit| is_clifford_circuit(&circuit));
+
+        match use_stabilizer {
+            true => SimulationOptions {
+                simulation_mode: SimulationMode::Stabilizer,
+                ..self.clone()
+            },
+            false => self.clone(),
+        }
     }
 
-    let circuit: Array1<QuantumGate> = build_circuit_from_data(incoming_data);
-    let mut state = QuantumState::new(&vec![0_usize; circuit.get(0).unwrap().size] as &[usize]);
+    // Check that the options can be used for a validated circuit
+    pub fn validate(&self, circuit: &Circuit) -> Result<(), QuantumCircuitError> {
+        if let Some(noise) = &self.noise {
+            // The tableau can not represent the mixed states noise leads to
+            if !noise.is_valid() || self.simulation_mode == SimulationMode::Stabilizer {
+                return Err(QuantumCircuitError::InvalidNoiseModel);
+            }
+        }
 
-    let mut state_list: Vec<Step> = vec![];
+        if self.simulation_mode == SimulationMode::Stabilizer && !is_clifford_circuit(circuit) {
+            return Err(QuantumCircuitError::NonCliffordCircuit);
+        }
+
+        if self.simulation_mode == SimulationMode::Mps && !self.mps.is_valid() {
+            return Err(QuantumCircuitError::InvalidMpsOptions);
+        }
+
+        // Clifford circuits have no control markers, the matrix product state gets the matrix including them
+        if self.simulation_mode == SimulationMode::Mps
+            && circuit
+                .operations()
+                .any(|operation| operation.qubits().len() > MAX_MATRIX_QUBITS)
+        {
+            return Err(QuantumCircuitError::TooManyControls);
+        }
+
+        Ok(())
+    }
+}
+
+// A representation of the state that the simulation loop can apply operations to
+pub trait SimulationState: Sized {
+    fn apply_gate_to_qubits(self, gate: &QuantumGate, qubits: &[usize]) -> Self;
+    // Backends without their own handling of controls apply the matrix including them,
+    // which SimulationOptions::validate limits to MAX_MATRIX_QUBITS
+    fn apply_operation(self, operation: &Operation) -> Self {
+        self.apply_gate_to_qubits(&operation.matrix(), &operation.qubits())
+    }
+    fn apply_channel<R: Rng + ?Sized>(
+        self,
+        kraus_operators: &[QuantumGate],
+        qubit: usize,
+        rng: &mut R,
+    ) -> Self;
+    fn measure<R: Rng + ?Sized>(self, qubit: usize, rng: &mut R) -> (Self, u8);
+    fn reduced_density_matrix(&self, qubits: &[usize]) -> DensityMatrix;
+    fn expectation_value(&self, pauli_string: &PauliString) -> f64;
+    // The response for a step, without the classical bits and conditions
+    // The amplitudes of a state vector are only formatted with_amplitudes, the other states have none
+    fn to_step(&self, step: usize, with_amplitudes: bool) -> Step;
+}
+
+impl SimulationState for QuantumState {
+    fn apply_gate_to_qubits(self, gate: &QuantumGate, qubits: &[usize]) -> Self {
+        QuantumState::apply_gate_to_qubits(self, gate, qubits)
+    }
+
+    fn apply_operation(self, operation: &Operation) -> Self {
+        self.apply_controlled_gate_to_qubits(
+            &operation.gate.matrix(&operation.params),
+            &operation.controls,
+            &operation.targets,
+        )
+    }
+
+    fn apply_channel<R: Rng + ?Sized>(
+        self,
+        kraus_operators: &[QuantumGate],
+        qubit: usize,
+        rng: &mut R,
+    ) -> Self {
+        QuantumState::apply_channel(self, kraus_operators, qubit, rng)
+    }
+
+    fn measure<R: Rng + ?Sized>(self, qubit: usize, rng: &mut R) -> (Self, u8) {
+        QuantumState::measure(self, qubit, rng)
+    }
+
+    fn reduced_density_matrix(&self, qubits: &[usize]) -> DensityMatrix {
+        QuantumState::reduced_density_matrix(self, qubits)
+    }
 
-    state_list.push(Step {
-        step: 0,
-        state: format_to_complex_container(&to_little_endian(&state)),
-    });
+    fn expectation_value(&self, pauli_string: &PauliString) -> f64 {
+        pauli_string.expectation_value(self)
+    }
+
+    fn to_step(&self, step: usize, with_amplitudes: bool) -> Step {
+        Step {
+            step,
+            state: match with_amplitudes {
+                true => format_to_complex_container(&to_little_endian(self)),
+                false => Vec::new(),
+            },
+            qubits: format_qubit_states(|qubit| self.reduced_density_matrix(&[qubit]), self.size()),
+            ..Default::default()
+        }
+    }
+}
+
+impl SimulationState for DensityMatrix {
+    fn apply_gate_to_qubits(self, gate: &QuantumGate, qubits: &[usize]) -> Self {
+        DensityMatrix::apply_gate_to_qubits(self, gate, qubits)
+    }
+
+    fn apply_operation(self, operation: &Operation) -> Self {
+        self.apply_controlled_gate_to_qubits(
+            &operation.gate.matrix(&operation.params),
+            &operation.controls,
+            &operation.targets,
+        )
+    }
+
+    fn apply_channel<R: Rng + ?Sized>(
+        self,
+        kraus_operators: &[QuantumGate],
+        qubit: usize,
+        _rng: &mut R,
+    ) -> Self {
+        DensityMatrix::apply_channel(self, kraus_operators, qubit)
+    }
+
+    fn measure<R: Rng + ?Sized>(self, qubit: usize, rng: &mut R) -> (Self, u8) {
+        DensityMatrix::measure(self, qubit, rng)
+    }
 
-    for (step, step_gate) in circuit.into_iter().enumerate() {
-        state = state.apply_gate(step_gate);
+    fn reduced_density_matrix(&self, qubits: &[usize]) -> DensityMatrix {
+        DensityMatrix::reduced_density_matrix(self, qubits)
+    }
+
+    fn expectation_value(&self, pauli_string: &PauliString) -> f64 {
+        pauli_string.expectation_value_of_density_matrix(self)
+    }
+
+    fn to_step(&self, step: usize, _with_amplitudes: bool) -> Step {
+        Step {
+            step,
+            density_matrix: format_density_matrix(&density_matrix_to_little_endian(self)),
+            purity: Some(self.purity()),
+            qubits: format_qubit_states(|qubit| self.reduced_density_matrix(&[qubit]), self.size()),
+            ..Default::default()
+        }
+    }
+}
+
+// Noise is rejected for the stabilizer backend before the simulation starts
+impl SimulationState for Tableau {
+    fn apply_gate_to_qubits(self, gate: &QuantumGate, qubits: &[usize]) -> Self {
+        Tableau::apply_gate_to_qubits(self, gate, qubits)
+    }
+
+    fn apply_channel<R: Rng + ?Sized>(
+        self,
+        _kraus_operators: &[QuantumGate],
+        _qubit: usize,
+        _rng: &mut R,
+    ) -> Self {
+        unreachable!("Noise can not be simulated with the stabilizer backend")
+    }
+
+    fn measure<R: Rng + ?Sized>(self, qubit: usize, rng: &mut R) -> (Self, u8) {
+        Tableau::measure(self, qubit, rng)
+    }
 
-        state_list.push(Step {
-            step: step + 1,
-            state: format_to_complex_container(&to_little_endian(&state)),
-        });
+    fn reduced_density_matrix(&self, qubits: &[usize]) -> DensityMatrix {
+        Tableau::reduced_density_matrix(self, qubits)
     }
 
+    fn expectation_value(&self, pauli_string: &PauliString) -> f64 {
+        Tableau::expectation_value(self, pauli_string)
+    }
+
+    fn to_step(&self, step: usize, _with_amplitudes: bool) -> Step {
+        Step {
+            step,
+            stabilizers: self.stabilizers(),
+            qubits: format_qubit_states(|qubit| self.reduced_density_matrix(&[qubit]), self.size()),
+            ..Default::default()
+        }
+    }
+}
+
+impl SimulationState for MatrixProductState {
+    fn apply_gate_to_qubits(self, gate: &QuantumGate, qubits: &[usize]) -> Self {
+        MatrixProductState::apply_gate_to_qubits(self, gate, qubits)
+    }
+
+    fn apply_channel<R: Rng + ?Sized>(
+        self,
+        kraus_operators: &[QuantumGate],
+        qubit: usize,
+        rng: &mut R,
+    ) -> Self {
+        MatrixProductState::apply_channel(self, kraus_operators, qubit, rng)
+    }
+
+    fn measure<R: Rng + ?Sized>(self, qubit: usize, rng: &mut R) -> (Self, u8) {
+        MatrixProductState::measure(self, qubit, rng)
+    }
+
+    fn reduced_density_matrix(&self, qubits: &[usize]) -> DensityMatrix {
+        MatrixProductState::reduced_density_matrix(self, qubits)
+    }
+
+    fn expectation_value(&self, pauli_string: &PauliString) -> f64 {
+        MatrixProductState::expectation_value(self, pauli_string)
+    }
+
+    fn to_step(&self, step: usize, _with_amplitudes: bool) -> Step {
+        let density_matrices = self.single_qubit_density_matrices();
+
+        Step {
+            step,
+            qubits: format_qubit_states(|qubit| density_matrices[qubit].clone(), self.size()),
+            truncation_error: Some(self.truncation_error),
+            bond_dimension: Some(self.bond_dimension()),
+            ..Default::default()
+        }
+    }
+}
+
+// Simulate the circuit by applying every gate only to the qubits it acts on,
+// with the representation and noise given in the options
+// max_qubits is the largest number of rows accepted by the validator
+pub fn simulate_circuit(
+    incoming_data: Vec<Vec<&str>>,
+    max_qubits: usize,
+    gates: &GateRegistry,
+    options: &SimulationOptions,
+) -> Result<Vec<Step>, QuantumCircuitError> {
+    simulate_circuit_with_rng(
+        incoming_data,
+        max_qubits,
+        gates,
+        options,
+        &mut rand::thread_rng(),
+    )
+}
+
+// Same as simulate_circuit, but measurement outcomes and noise are sampled from the given random number generator
+pub fn simulate_circuit_with_rng<R: Rng + ?Sized>(
+    incoming_data: Vec<Vec<&str>>,
+    max_qubits: usize,
+    gates: &GateRegistry,
+    options: &SimulationOptions,
+    rng: &mut R,
+) -> Result<Vec<Step>, QuantumCircuitError> {
+    let circuit = validate_circuit(&incoming_data, max_qubits, gates)?;
+    options.validate(&circuit)?;
+
+    let bits = vec![0_usize; circuit.qubits];
+    let state_list = match options.simulation_mode {
+        SimulationMode::StateVector => {
+            run_circuit(&circuit, QuantumState::new(&bits), options, rng)
+        }
+        SimulationMode::DensityMatrix => {
+            run_circuit(&circuit, DensityMatrix::new(&bits), options, rng)
+        }
+        SimulationMode::Stabilizer => run_circuit(&circuit, Tableau::new(bits.len()), options, rng),
+        SimulationMode::Mps => run_circuit(
+            &circuit,
+            MatrixProductState::new(&bits, options.mps),
+            options,
+            rng,
+        ),
+    };
+
     Ok(state_list)
 }
 
+// Run a validated circuit on the given initial state and collect the state after every step
+fn run_circuit<S: SimulationState, R: Rng + ?Sized>(
+    circuit: &Circuit,
+    state: S,
+    options: &SimulationOptions,
+    rng: &mut R,
+) -> Vec<Step> {
+    let mut state_list: Vec<Step> = vec![];
+
+    run_operations(
+        circuit,
+        state,
+        options.noise.as_ref(),
+        rng,
+        |step, state, classical_bits, conditions| {
+            let analysis = options.analysis.then(|| {
+                analyse_entanglement(circuit.qubits, |qubits| {
+                    state.reduced_density_matrix(qubits)
+                })
+            });
+
+            // Every state vector has 2^n amplitudes, so large circuits only return the final one
+            let with_amplitudes = step == circuit.moments.len()
+                || options
+                    .max_step_state_qubits
+                    .is_none_or(|max_qubits| circuit.qubits <= max_qubits);
+
+            state_list.push(Step {
+                classical_bits: classical_bits.to_vec(),
+                conditions,
+                analysis,
+                ..state.to_step(step, with_amplitudes)
+            });
+        },
+    );
+
+    state_list
+}
+
+// Apply the operations of a validated circuit to the state, returning the final state and classical bits
+// on_step is called with the state after every step, starting with the initial state as step 0
+// The classical bits are empty if the circuit does not measure anything
+pub fn run_operations<S, R, F>(
+    circuit: &Circuit,
+    mut state: S,
+    noise: Option<&NoiseModel>,
+    rng: &mut R,
+    mut on_step: F,
+) -> (S, Vec<Option<u8>>)
+where
+    S: SimulationState,
+    R: Rng + ?Sized,
+    F: FnMut(usize, &S, &[Option<u8>], Vec<ClassicalCondition>),
+{
+    // One classical bit per qubit, only reported if the circuit measures something
+    let mut classical_bits: Vec<Option<u8>> = if circuit.has_measurements() {
+        vec![None; circuit.qubits]
+    } else {
+        vec![]
+    };
+
+    on_step(0, &state, &classical_bits, vec![]);
+
+    for (step, moment) in circuit.moments.iter().enumerate() {
+        let mut conditions: Vec<ClassicalCondition> = vec![];
+
+        for operation in &moment.operations {
+            if operation.is_measurement() {
+                let qubit = operation.targets[0];
+                let (collapsed_state, outcome) = state.measure(qubit, rng);
+                state = collapsed_state;
+                classical_bits[qubit] = Some(outcome);
+                continue;
+            }
+
+            let applied = operation
+                .condition
+                .is_none_or(|bit| classical_bits[bit] == Some(1));
+            if applied {
+                state = state.apply_operation(operation);
+            }
+
+            if let Some(bit) = operation.condition {
+                conditions.push(ClassicalCondition {
+                    qubit: operation.targets[0],
+                    classical_bit: bit,
+                    applied,
+                });
+            }
+        }
+
+        // Noise is applied to every qubit an operation touches in this step, after all gates of the step
+        // Control qubits get the channels of the gate they control
+        if let Some(noise) = noise {
+            let mut touched: Vec<(usize, &str)> = moment
+                .operations
+                .iter()
+                .flat_map(|operation| {
+                    operation
+                        .qubits()
+                        .into_iter()
+                        .map(|qubit| (qubit, operation.gate.name()))
+                })
+                .collect();
+            touched.sort();
+
+            for (qubit, name) in touched {
+                for channel in noise.channels_for(name) {
+                    state = state.apply_channel(&channel.kraus_operators(), qubit, rng);
+                }
+            }
+        }
+
+        on_step(step + 1, &state, &classical_bits, conditions);
+    }
+
+    (state, classical_bits)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::simulation::noise::{ChannelKind, NoiseChannel};
     use ndarray::arr2;
     use num::Complex;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
 
     #[test]
     fn test_single_operation() {
@@ -124,4 +556,507 @@ mod tests {
 
         assert_eq!(result.col, expected_result);
     }
+
+    #[test]
+    fn test_circuit_above_six_qubits() {
+        // H on the first qubit and a CNOT chain creates a 16 qubit GHZ state
+        let no_of_qubits = 16;
+        let mut grid = vec![vec!["I"; no_of_qubits]; no_of_qubits];
+        grid[0][0] = "H";
+        for step in 1..no_of_qubits {
+            grid[step - 1][step] = "CNOT-1";
+            grid[step][step] = "CNOT-2";
+        }
+
+        let state_list = simulate_circuit(
+            grid,
+            24,
+            &GateRegistry::default(),
+            &SimulationOptions::default(),
+        )
+        .unwrap();
+        let final_state = &state_list.last().unwrap().state;
+
+        assert_eq!(state_list.len(), no_of_qubits + 1);
+        assert!((final_state[0].re - 1.0 / 2.0_f64.sqrt()).abs() < 1e-12);
+        assert!((final_state[(1 << no_of_qubits) - 1].re - 1.0 / 2.0_f64.sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_many_controls() {
+        // X on every control, then 19 controls on an X, which would be a 2^20 x 2^20 matrix
+        let no_of_qubits = 20;
+        let mut grid = vec![vec!["X", "C"]; no_of_qubits - 1];
+        grid.push(vec!["I", "X"]);
+
+        let circuit = validate_circuit(&grid, no_of_qubits, &GateRegistry::default()).unwrap();
+
+        let (state, _) = run_operations(
+            &circuit,
+            QuantumState::new(&vec![0; no_of_qubits]),
+            None,
+            &mut StdRng::seed_from_u64(0),
+            |_, _, _, _| {},
+        );
+
+        assert_eq!(state.col, QuantumState::new(&vec![1; no_of_qubits]).col);
+    }
+
+    #[test]
+    fn test_controls_in_density_matrix_mode() {
+        let grid = vec![
+            vec!["H", "C", "I"],
+            vec!["H", "O", "I"],
+            vec!["I", "O", "C"],
+            vec!["I", "RY(pi/3)", "X"],
+        ];
+        let density_matrix_options = SimulationOptions {
+            simulation_mode: SimulationMode::DensityMatrix,
+            ..Default::default()
+        };
+
+        let state_list = simulate_circuit(
+            grid.clone(),
+            4,
+            &GateRegistry::default(),
+            &SimulationOptions::default(),
+        )
+        .unwrap();
+        let density_list =
+            simulate_circuit(grid, 4, &GateRegistry::default(), &density_matrix_options).unwrap();
+
+        for (step, density_step) in state_list.iter().zip(density_list.iter()) {
+            for (qubit, density_qubit) in step.qubits.iter().zip(density_step.qubits.iter()) {
+                assert!((qubit.purity - density_qubit.purity).abs() < 1e-9);
+                assert!((qubit.bloch_vector.z - density_qubit.bloch_vector.z).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_qubit_limit() {
+        let grid = vec![vec!["H"]; 7];
+
+        assert!(simulate_circuit(
+            grid.clone(),
+            7,
+            &GateRegistry::default(),
+            &SimulationOptions::default()
+        )
+        .is_ok());
+        assert_eq!(
+            simulate_circuit(
+                grid,
+                6,
+                &GateRegistry::default(),
+                &SimulationOptions::default()
+            )
+            .err(),
+            Some(QuantumCircuitError::TooManyQubits)
+        );
+    }
+
+    #[test]
+    fn test_measurement_records_classical_bit() {
+        let grid = vec![vec!["X", "M"], vec!["I", "I"]];
+
+        let state_list = simulate_circuit(
+            grid,
+            6,
+            &GateRegistry::default(),
+            &SimulationOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(state_list[0].classical_bits, vec![None, None]);
+        assert_eq!(state_list[1].classical_bits, vec![None, None]);
+        assert_eq!(state_list[2].classical_bits, vec![Some(1), None]);
+    }
+
+    #[test]
+    fn test_no_classical_bits_without_measurement() {
+        let grid = vec![vec!["H"]];
+
+        let state_list = simulate_circuit(
+            grid,
+            6,
+            &GateRegistry::default(),
+            &SimulationOptions::default(),
+        )
+        .unwrap();
+
+        assert!(state_list.iter().all(|step| step.classical_bits.is_empty()));
+    }
+
+    #[test]
+    fn test_measurement_of_bell_state_is_correlated() {
+        let grid = vec![vec!["H", "CNOT-1", "M", "I"], vec!["I", "CNOT-2", "I", "M"]];
+
+        for seed in 0..10 {
+            let state_list = simulate_circuit_with_rng(
+                grid.clone(),
+                6,
+                &GateRegistry::default(),
+                &SimulationOptions::default(),
+                &mut StdRng::seed_from_u64(seed),
+            )
+            .unwrap();
+            let last_step = state_list.last().unwrap();
+
+            assert_eq!(last_step.classical_bits[0], last_step.classical_bits[1]);
+
+            // The collapsed state is either |00> or |11> with amplitude 1
+            let probabilities: Vec<f64> = last_step
+                .state
+                .iter()
+                .map(|amplitude| amplitude.re * amplitude.re + amplitude.im * amplitude.im)
+                .collect();
+            let index = if last_step.classical_bits[0] == Some(1) {
+                3
+            } else {
+                0
+            };
+            assert!((probabilities[index] - 1.0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_quantum_teleportation() {
+        // Teleport (|0> + i|1>) / sqrt(2) from qubit 0 to qubit 2
+        let grid = vec![
+            vec!["H", "S", "CNOT-1", "H", "M", "I", "I"],
+            vec!["H", "CNOT-1", "CNOT-2", "I", "M", "I", "I"],
+            vec!["I", "CNOT-2", "I", "I", "I", "X?c1", "Z?c0"],
+        ];
+
+        for seed in 0..10 {
+            let state_list = simulate_circuit_with_rng(
+                grid.clone(),
+                6,
+                &GateRegistry::default(),
+                &SimulationOptions::default(),
+                &mut StdRng::seed_from_u64(seed),
+            )
+            .unwrap();
+            let last_step = state_list.last().unwrap();
+
+            let bit_0 = last_step.classical_bits[0].unwrap() as usize;
+            let bit_1 = last_step.classical_bits[1].unwrap() as usize;
+            assert_eq!(state_list[6].conditions[0].applied, bit_1 == 1);
+            assert_eq!(state_list[7].conditions[0].applied, bit_0 == 1);
+
+            // Little endian index, qubit 0 is the least significant bit
+            let zero = &last_step.state[bit_0 + 2 * bit_1];
+            let one = &last_step.state[bit_0 + 2 * bit_1 + 4];
+            assert!((zero.re - 1.0 / 2.0_f64.sqrt()).abs() < 1e-12 && zero.im.abs() < 1e-12);
+            assert!(one.re.abs() < 1e-12 && (one.im - 1.0 / 2.0_f64.sqrt()).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_cnot_with_control_below_target() {
+        // X on qubit 1, then CNOT with qubit 1 as control and qubit 0 as target: |00> -> |11>
+        let grid = vec![vec!["I", "CNOT-2"], vec!["X", "CNOT-1"]];
+
+        let state_list = simulate_circuit(
+            grid,
+            6,
+            &GateRegistry::default(),
+            &SimulationOptions::default(),
+        )
+        .unwrap();
+        let final_state = &state_list.last().unwrap().state;
+
+        assert_eq!(final_state[3].re, 1.0);
+    }
+
+    #[test]
+    fn test_density_matrix_mode_matches_state_vector() {
+        let grid = vec![vec!["H", "CNOT-1"], vec!["I", "CNOT-2"]];
+        let options = SimulationOptions {
+            simulation_mode: SimulationMode::DensityMatrix,
+            noise: None,
+            ..Default::default()
+        };
+
+        let state_list = simulate_circuit_with_rng(
+            grid,
+            6,
+            &GateRegistry::default(),
+            &options,
+            &mut StdRng::seed_from_u64(0),
+        )
+        .unwrap();
+        let last_step = state_list.last().unwrap();
+
+        assert!(last_step.state.is_empty());
+        assert!((last_step.purity.unwrap() - 1.0).abs() < 1e-12);
+        for (row, column) in [(0, 0), (0, 3), (3, 0), (3, 3)] {
+            assert!((last_step.density_matrix[row][column].re - 0.5).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_purity_drops_with_noise() {
+        let grid = vec![vec!["H", "X", "H"]];
+        let options = SimulationOptions {
+            simulation_mode: SimulationMode::DensityMatrix,
+            noise: Some(NoiseModel {
+                global: vec![NoiseChannel {
+                    channel: ChannelKind::Depolarizing,
+                    probability: 0.1,
+                }],
+                gates: Default::default(),
+            }),
+            ..Default::default()
+        };
+
+        let state_list = simulate_circuit_with_rng(
+            grid,
+            6,
+            &GateRegistry::default(),
+            &options,
+            &mut StdRng::seed_from_u64(0),
+        )
+        .unwrap();
+        let purities: Vec<f64> = state_list.iter().map(|step| step.purity.unwrap()).collect();
+
+        assert_eq!(purities[0], 1.0);
+        assert!(purities.windows(2).all(|pair| pair[1] < pair[0]));
+    }
+
+    #[test]
+    fn test_per_gate_noise() {
+        // Phase flips only affect the H gate, the identity step has no noise
+        let grid = vec![vec!["H", "I"]];
+        let mut gates = std::collections::HashMap::new();
+        gates.insert(
+            "H".to_string(),
+            vec![NoiseChannel {
+                channel: ChannelKind::PhaseFlip,
+                probability: 0.5,
+            }],
+        );
+        let options = SimulationOptions {
+            simulation_mode: SimulationMode::DensityMatrix,
+            noise: Some(NoiseModel {
+                global: vec![],
+                gates,
+            }),
+            ..Default::default()
+        };
+
+        let state_list = simulate_circuit_with_rng(
+            grid,
+            6,
+            &GateRegistry::default(),
+            &options,
+            &mut StdRng::seed_from_u64(0),
+        )
+        .unwrap();
+
+        // A full phase flip on |+> gives the maximally mixed state
+        assert!((state_list[1].purity.unwrap() - 0.5).abs() < 1e-12);
+        assert!((state_list[2].purity.unwrap() - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_invalid_noise_model() {
+        let options = SimulationOptions {
+            simulation_mode: SimulationMode::DensityMatrix,
+            noise: Some(NoiseModel {
+                global: vec![NoiseChannel {
+                    channel: ChannelKind::BitFlip,
+                    probability: -0.1,
+                }],
+                gates: Default::default(),
+            }),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            simulate_circuit_with_rng(
+                vec![vec!["H"]],
+                6,
+                &GateRegistry::default(),
+                &options,
+                &mut StdRng::seed_from_u64(0)
+            )
+            .err(),
+            Some(QuantumCircuitError::InvalidNoiseModel)
+        );
+    }
+
+    #[test]
+    fn test_stabilizer_mode_is_selected_for_large_clifford_circuits() {
+        let clifford = vec![vec!["H", "CNOT-1"], vec!["I", "CNOT-2"], vec!["S", "I"]];
+        let options = SimulationOptions::default();
+
+        assert_eq!(
+            options
+                .for_circuit(&clifford, 2, &GateRegistry::default())
+                .simulation_mode,
+            SimulationMode::Stabilizer
+        );
+        assert_eq!(
+            options
+                .for_circuit(&clifford, 3, &GateRegistry::default())
+                .simulation_mode,
+            SimulationMode::StateVector
+        );
+
+        let non_clifford = vec![vec!["T"], vec!["I"], vec!["I"]];
+        assert_eq!(
+            options
+                .for_circuit(&non_clifford, 2, &GateRegistry::default())
+                .simulation_mode,
+            SimulationMode::StateVector
+        );
+    }
+
+    #[test]
+    fn test_stabilizer_mode() {
+        let grid = vec![vec!["H", "CNOT-1", "M"], vec!["I", "CNOT-2", "M"]];
+        let options = SimulationOptions {
+            simulation_mode: SimulationMode::Stabilizer,
+            ..Default::default()
+        };
+
+        let state_list = simulate_circuit_with_rng(
+            grid,
+            6,
+            &GateRegistry::default(),
+            &options,
+            &mut StdRng::seed_from_u64(3),
+        )
+        .unwrap();
+
+        assert!(state_list[2].state.is_empty());
+        assert_eq!(state_list[2].stabilizers, vec!["+XX", "+ZZ"]);
+        assert!(state_list[2].qubits[0].bloch_vector.z.abs() < 1e-12);
+        let bits = &state_list[3].classical_bits;
+        assert_eq!(bits[0], bits[1]);
+    }
+
+    #[test]
+    fn test_stabilizer_mode_rejects_non_clifford_circuits_and_noise() {
+        let options = SimulationOptions {
+            simulation_mode: SimulationMode::Stabilizer,
+            ..Default::default()
+        };
+        assert_eq!(
+            simulate_circuit_with_rng(
+                vec![vec!["T"]],
+                6,
+                &GateRegistry::default(),
+                &options,
+                &mut StdRng::seed_from_u64(0)
+            )
+            .err(),
+            Some(QuantumCircuitError::NonCliffordCircuit)
+        );
+
+        let options = SimulationOptions {
+            simulation_mode: SimulationMode::Stabilizer,
+            noise: Some(NoiseModel {
+                global: vec![NoiseChannel {
+                    channel: ChannelKind::BitFlip,
+                    probability: 0.1,
+                }],
+                gates: Default::default(),
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            simulate_circuit_with_rng(
+                vec![vec!["H"]],
+                6,
+                &GateRegistry::default(),
+                &options,
+                &mut StdRng::seed_from_u64(0)
+            )
+            .err(),
+            Some(QuantumCircuitError::InvalidNoiseModel)
+        );
+    }
+
+    #[test]
+    fn test_mps_mode_matches_state_vector() {
+        let grid = vec![
+            vec!["H", "CNOT-1", "I", "RY(0.3)"],
+            vec!["I", "I", "C", "T"],
+            vec!["X", "CNOT-2", "RX(1.2)", "H"],
+        ];
+        let options = SimulationOptions {
+            simulation_mode: SimulationMode::Mps,
+            ..Default::default()
+        };
+
+        let expected = simulate_circuit_with_rng(
+            grid.clone(),
+            6,
+            &GateRegistry::default(),
+            &SimulationOptions::default(),
+            &mut StdRng::seed_from_u64(0),
+        )
+        .unwrap();
+        let state_list = simulate_circuit_with_rng(
+            grid,
+            6,
+            &GateRegistry::default(),
+            &options,
+            &mut StdRng::seed_from_u64(0),
+        )
+        .unwrap();
+
+        for (step, expected_step) in state_list.iter().zip(&expected) {
+            assert!(step.state.is_empty());
+            assert_eq!(step.truncation_error, Some(0.0));
+            for (qubit, expected_qubit) in step.qubits.iter().zip(&expected_step.qubits) {
+                assert!((qubit.bloch_vector.x - expected_qubit.bloch_vector.x).abs() < 1e-9);
+                assert!((qubit.bloch_vector.y - expected_qubit.bloch_vector.y).abs() < 1e-9);
+                assert!((qubit.bloch_vector.z - expected_qubit.bloch_vector.z).abs() < 1e-9);
+            }
+        }
+        assert_eq!(state_list[2].bond_dimension, Some(2));
+    }
+
+    #[test]
+    fn test_invalid_mps_options() {
+        let options = SimulationOptions {
+            simulation_mode: SimulationMode::Mps,
+            mps: MpsOptions {
+                max_bond_dimension: 0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(
+            simulate_circuit_with_rng(
+                vec![vec!["H"]],
+                6,
+                &GateRegistry::default(),
+                &options,
+                &mut StdRng::seed_from_u64(0)
+            )
+            .err(),
+            Some(QuantumCircuitError::InvalidMpsOptions)
+        );
+    }
+
+    #[test]
+    fn test_too_many_controls_in_mps_mode() {
+        let mut grid = vec![vec!["C"]; MAX_MATRIX_QUBITS];
+        grid.push(vec!["X"]);
+        let options = SimulationOptions {
+            simulation_mode: SimulationMode::Mps,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            simulate_circuit(grid, 64, &GateRegistry::default(), &options).err(),
+            Some(QuantumCircuitError::TooManyControls)
+        );
+    }
 }
@@ -0,0 +1,526 @@
+// Stabilizer tableau simulation of Clifford circuits (Aaronson and Gottesman, "Improved simulation of stabilizer circuits")
+// The state of n qubits is stored as n destabilizer and n stabilizer Pauli strings with a sign each,
+// so gates take O(n) and measurements O(n^2) time instead of the 2^n amplitudes of a state vector
+// Only H, S, X, Y, Z, CNOT, CZ and SWAP (and measurements) keep a stabilizer state a stabilizer state
+
+use crate::simulation::circuit::{Circuit, Gate};
+use crate::simulation::density_matrix::DensityMatrix;
+use crate::simulation::observable::{Pauli, PauliString};
+use crate::simulation::quantum_gate::QuantumGate;
+use ndarray::Array2;
+use num::Complex;
+use rand::Rng;
+
+// The gates the tableau can apply
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CliffordGate {
+    // Only left in the circuit as a classically controlled gate like "I?c0"
+    I,
+    H,
+    S,
+    X,
+    Y,
+    Z,
+    Cnot,
+    Cz,
+    Swap,
+}
+
+impl CliffordGate {
+    // Recognise a Clifford gate from its matrix, the operations of the circuit only carry matrices
+    fn from_gate(gate: &QuantumGate) -> Option<CliffordGate> {
+        let candidates = [
+            (CliffordGate::I, QuantumGate::i_gate()),
+            (CliffordGate::H, QuantumGate::h_gate()),
+            (CliffordGate::S, QuantumGate::s_gate()),
+            (CliffordGate::X, QuantumGate::x_gate()),
+            (CliffordGate::Y, QuantumGate::y_gate()),
+            (CliffordGate::Z, QuantumGate::z_gate()),
+            (CliffordGate::Cnot, QuantumGate::cnot_gate()),
+            (CliffordGate::Cz, QuantumGate::cz_gate()),
+            (CliffordGate::Swap, QuantumGate::swap_gate()),
+        ];
+
+        candidates
+            .into_iter()
+            .find(|(_, candidate)| {
+                candidate.size == gate.size
+                    && candidate
+                        .matrix
+                        .iter()
+                        .zip(gate.matrix.iter())
+                        .all(|(a, b)| (a - b).norm() < 1e-12)
+            })
+            .map(|(clifford_gate, _)| clifford_gate)
+    }
+}
+
+// Whether every operation of the circuit can be simulated with the tableau
+// Controlled gates, T, CCNOT and parameterised gates are not Clifford gates in general, so they are rejected
+pub fn is_clifford_circuit(circuit: &Circuit) -> bool {
+    circuit.operations().all(|operation| {
+        operation.controls.is_empty()
+            && matches!(
+                operation.gate,
+                Gate::I
+                    | Gate::H
+                    | Gate::S
+                    | Gate::X
+                    | Gate::Y
+                    | Gate::Z
+                    | Gate::Measure
+                    | Gate::Cnot
+                    | Gate::Cz
+                    | Gate::Swap
+            )
+    })
+}
+
+// A Pauli string with a sign, x[qubit] and z[qubit] give the Pauli on that qubit, X = (1, 0), Z = (0, 1), Y = (1, 1)
+#[derive(Debug, Clone, PartialEq)]
+struct PauliRow {
+    x: Vec<bool>,
+    z: Vec<bool>,
+    negative: bool,
+}
+
+impl PauliRow {
+    fn identity(no_of_qubits: usize) -> PauliRow {
+        PauliRow {
+            x: vec![false; no_of_qubits],
+            z: vec![false; no_of_qubits],
+            negative: false,
+        }
+    }
+
+    // The qubits the row acts on with something other than I
+    fn support(&self) -> Vec<usize> {
+        (0..self.x.len())
+            .filter(|&qubit| self.x[qubit] || self.z[qubit])
+            .collect()
+    }
+
+    // Only the qubits in the support of the other row need to be checked, which keeps small Pauli strings cheap
+    fn anticommutes_with(&self, other: &PauliRow, support: &[usize]) -> bool {
+        support
+            .iter()
+            .filter(|&&qubit| {
+                (self.x[qubit] && other.z[qubit]) != (self.z[qubit] && other.x[qubit])
+            })
+            .count()
+            % 2
+            == 1
+    }
+
+    // Multiply this row from the left by another row that commutes with it, so the result has a real sign
+    fn multiply_by(&mut self, other: &PauliRow) {
+        // The power of i picked up on every qubit when multiplying the two Paulis
+        let exponent: i32 = (0..self.x.len())
+            .map(|qubit| {
+                let (x1, z1) = (i32::from(other.x[qubit]), i32::from(other.z[qubit]));
+                let (x2, z2) = (i32::from(self.x[qubit]), i32::from(self.z[qubit]));
+                match (x1, z1) {
+                    (0, 0) => 0,
+                    (1, 1) => z2 - x2,
+                    (1, 0) => z2 * (2 * x2 - 1),
+                    _ => x2 * (1 - 2 * z2),
+                }
+            })
+            .sum();
+        let total = 2 * i32::from(self.negative) + 2 * i32::from(other.negative) + exponent;
+
+        self.negative = total.rem_euclid(4) == 2;
+        for qubit in 0..self.x.len() {
+            self.x[qubit] ^= other.x[qubit];
+            self.z[qubit] ^= other.z[qubit];
+        }
+    }
+}
+
+// The stabilizer state of n qubits, rows 0..n are the destabilizers and rows n..2n the stabilizers
+// Destabilizer i anticommutes with stabilizer i and commutes with all other rows
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tableau {
+    rows: Vec<PauliRow>,
+}
+
+impl Tableau {
+    // Create the tableau of |0...0>, destabilizer i is X_i and stabilizer i is Z_i
+    pub fn new(no_of_qubits: usize) -> Tableau {
+        let mut rows = vec![PauliRow::identity(no_of_qubits); 2 * no_of_qubits];
+
+        for qubit in 0..no_of_qubits {
+            rows[qubit].x[qubit] = true;
+            rows[qubit + no_of_qubits].z[qubit] = true;
+        }
+
+        Tableau { rows }
+    }
+
+    pub fn size(&self) -> usize {
+        self.rows.len() / 2
+    }
+
+    // Apply a Clifford gate to the given qubits, panics if the gate is not one of the Clifford gates
+    pub fn apply_gate_to_qubits(mut self, gate: &QuantumGate, qubits: &[usize]) -> Tableau {
+        let clifford_gate = CliffordGate::from_gate(gate)
+            .expect("The stabilizer backend can only apply Clifford gates");
+
+        match (clifford_gate, qubits) {
+            (CliffordGate::I, &[_]) => {}
+            (CliffordGate::H, &[qubit]) => self.hadamard(qubit),
+            (CliffordGate::S, &[qubit]) => self.phase(qubit),
+            // A Pauli gate negates the rows that anticommute with it
+            (CliffordGate::X, &[qubit]) => self.negate_rows(|row| row.z[qubit]),
+            (CliffordGate::Y, &[qubit]) => self.negate_rows(|row| row.x[qubit] != row.z[qubit]),
+            (CliffordGate::Z, &[qubit]) => self.negate_rows(|row| row.x[qubit]),
+            (CliffordGate::Cnot, &[control, target]) => self.cnot(control, target),
+            (CliffordGate::Cz, &[control, target]) => {
+                self.hadamard(target);
+                self.cnot(control, target);
+                self.hadamard(target);
+            }
+            (CliffordGate::Swap, &[first, second]) => {
+                for row in &mut self.rows {
+                    row.x.swap(first, second);
+                    row.z.swap(first, second);
+                }
+            }
+            _ => panic!("Trying to apply a gate to the wrong number of qubits"),
+        }
+
+        self
+    }
+
+    fn hadamard(&mut self, qubit: usize) {
+        for row in &mut self.rows {
+            row.negative ^= row.x[qubit] && row.z[qubit];
+            std::mem::swap(&mut row.x[qubit], &mut row.z[qubit]);
+        }
+    }
+
+    fn phase(&mut self, qubit: usize) {
+        for row in &mut self.rows {
+            row.negative ^= row.x[qubit] && row.z[qubit];
+            row.z[qubit] ^= row.x[qubit];
+        }
+    }
+
+    fn cnot(&mut self, control: usize, target: usize) {
+        for row in &mut self.rows {
+            row.negative ^= row.x[control] && row.z[target] && (row.x[target] == row.z[control]);
+            row.x[target] ^= row.x[control];
+            row.z[control] ^= row.z[target];
+        }
+    }
+
+    fn negate_rows(&mut self, anticommutes: impl Fn(&PauliRow) -> bool) {
+        for row in &mut self.rows {
+            row.negative ^= anticommutes(row);
+        }
+    }
+
+    // Measure a qubit in the computational basis
+    // The outcome is random if a stabilizer anticommutes with Z of the qubit and fixed by the stabilizers otherwise
+    pub fn measure<R: Rng + ?Sized>(mut self, qubit: usize, rng: &mut R) -> (Tableau, u8) {
+        let n = self.size();
+        let mut z = PauliRow::identity(n);
+        z.z[qubit] = true;
+
+        match (n..2 * n).find(|&row| self.rows[row].x[qubit]) {
+            Some(pivot) => {
+                let pivot_row = self.rows[pivot].clone();
+                for (index, row) in self.rows.iter_mut().enumerate() {
+                    if index != pivot && row.x[qubit] {
+                        row.multiply_by(&pivot_row);
+                    }
+                }
+
+                // The anticommuting stabilizer becomes the destabilizer of the new stabilizer +-Z
+                z.negative = rng.gen::<bool>();
+                self.rows[pivot - n] = std::mem::replace(&mut self.rows[pivot], z);
+                let outcome = u8::from(self.rows[pivot].negative);
+
+                (self, outcome)
+            }
+            None => {
+                let outcome = u8::from(self.stabilizer_product(&z).negative);
+                (self, outcome)
+            }
+        }
+    }
+
+    // Write a Pauli string that commutes with all stabilizers as a product of stabilizers,
+    // the product contains stabilizer i exactly if destabilizer i anticommutes with the Pauli string
+    fn stabilizer_product(&self, pauli: &PauliRow) -> PauliRow {
+        let n = self.size();
+        let support = pauli.support();
+        let mut product = PauliRow::identity(n);
+
+        for row in 0..n {
+            if self.rows[row].anticommutes_with(pauli, &support) {
+                product.multiply_by(&self.rows[row + n]);
+            }
+        }
+
+        product
+    }
+
+    // Calculate the expectation value of a Pauli string, 0 if it anticommutes with a stabilizer
+    // and otherwise plus or minus its coefficient
+    pub fn expectation_value(&self, pauli_string: &PauliString) -> f64 {
+        let n = self.size();
+        let pauli = PauliRow {
+            x: pauli_string
+                .paulis
+                .iter()
+                .map(|pauli| matches!(pauli, Pauli::X | Pauli::Y))
+                .collect(),
+            z: pauli_string
+                .paulis
+                .iter()
+                .map(|pauli| matches!(pauli, Pauli::Z | Pauli::Y))
+                .collect(),
+            negative: false,
+        };
+
+        let support = pauli.support();
+        if self.rows[n..]
+            .iter()
+            .any(|row| row.anticommutes_with(&pauli, &support))
+        {
+            return 0.0;
+        }
+
+        if self.stabilizer_product(&pauli).negative {
+            -pauli_string.coefficient
+        } else {
+            pauli_string.coefficient
+        }
+    }
+
+    // Calculate the density matrix of a few qubits from the expectation values of the Pauli strings on them,
+    // rho = 1/2^k sum over P of <P> P
+    pub fn reduced_density_matrix(&self, qubits: &[usize]) -> DensityMatrix {
+        let dim = 1 << qubits.len();
+        let mut matrix = Array2::<Complex<f64>>::zeros((dim, dim));
+        let paulis = [Pauli::I, Pauli::X, Pauli::Y, Pauli::Z];
+
+        for index in 0..dim * dim {
+            let local: Vec<Pauli> = (0..qubits.len())
+                .map(|k| paulis[(index >> (2 * (qubits.len() - k - 1))) & 3])
+                .collect();
+
+            let mut full = vec![Pauli::I; self.size()];
+            for (&qubit, &pauli) in qubits.iter().zip(&local) {
+                full[qubit] = pauli;
+            }
+            let value = self.expectation_value(&PauliString {
+                coefficient: 1.0,
+                paulis: full,
+            });
+            if value == 0.0 {
+                continue;
+            }
+
+            let operator = local
+                .iter()
+                .map(|pauli| match pauli {
+                    Pauli::I => QuantumGate::i_gate(),
+                    Pauli::X => QuantumGate::x_gate(),
+                    Pauli::Y => QuantumGate::y_gate(),
+                    Pauli::Z => QuantumGate::z_gate(),
+                })
+                .reduce(|acc, gate| acc.kronecker(gate))
+                .unwrap();
+            matrix = matrix + operator.matrix * Complex::new(value / dim as f64, 0.0);
+        }
+
+        DensityMatrix { matrix }
+    }
+
+    // The stabilizer generators as signed Pauli strings, e.g. "+XX" and "-ZZ", letter i acts on qubit i
+    pub fn stabilizers(&self) -> Vec<String> {
+        self.rows[self.size()..]
+            .iter()
+            .map(|row| {
+                let sign = if row.negative { '-' } else { '+' };
+                let paulis = row.x.iter().zip(&row.z).map(|pair| match pair {
+                    (false, false) => 'I',
+                    (true, false) => 'X',
+                    (true, true) => 'Y',
+                    (false, true) => 'Z',
+                });
+                std::iter::once(sign).chain(paulis).collect()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::circuit_parser::parse_circuit;
+    use crate::simulation::gate_registry::GateRegistry;
+    use crate::simulation::observable::Observable;
+    use crate::simulation::quantum_state::QuantumState;
+    use crate::simulation::simulator::{
+        simulate_circuit_with_rng, SimulationMode, SimulationOptions,
+    };
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_bell_state_stabilizers() {
+        let tableau = Tableau::new(2)
+            .apply_gate_to_qubits(&QuantumGate::h_gate(), &[0])
+            .apply_gate_to_qubits(&QuantumGate::cnot_gate(), &[0, 1]);
+
+        assert_eq!(tableau.stabilizers(), vec!["+XX", "+ZZ"]);
+    }
+
+    #[test]
+    fn test_pauli_gates_flip_signs() {
+        let tableau = Tableau::new(3)
+            .apply_gate_to_qubits(&QuantumGate::x_gate(), &[0])
+            .apply_gate_to_qubits(&QuantumGate::y_gate(), &[1])
+            .apply_gate_to_qubits(&QuantumGate::h_gate(), &[2])
+            .apply_gate_to_qubits(&QuantumGate::z_gate(), &[2]);
+
+        assert_eq!(tableau.stabilizers(), vec!["-ZII", "-IZI", "-IIX"]);
+    }
+
+    // Test that the expectation values of the tableau match the state vector for a random-looking Clifford circuit
+    #[test]
+    fn test_expectation_values_match_state_vector() {
+        let gates = [
+            (QuantumGate::h_gate(), vec![0]),
+            (QuantumGate::s_gate(), vec![0]),
+            (QuantumGate::cnot_gate(), vec![0, 2]),
+            (QuantumGate::h_gate(), vec![1]),
+            (QuantumGate::cz_gate(), vec![1, 2]),
+            (QuantumGate::y_gate(), vec![2]),
+            (QuantumGate::swap_gate(), vec![0, 1]),
+            (QuantumGate::s_gate(), vec![2]),
+            (QuantumGate::h_gate(), vec![2]),
+        ];
+
+        let (state, tableau) = gates.iter().fold(
+            (QuantumState::new(&[0, 0, 0]), Tableau::new(3)),
+            |(state, tableau), (gate, qubits)| {
+                (
+                    state.apply_gate_to_qubits(gate, qubits),
+                    tableau.apply_gate_to_qubits(gate, qubits),
+                )
+            },
+        );
+
+        let letters = ['I', 'X', 'Y', 'Z'];
+        for index in 0..64 {
+            let word: String = (0..3).map(|k| letters[(index >> (2 * k)) & 3]).collect();
+            let observable = Observable::parse(&word).unwrap();
+            let expected = observable.terms[0].expectation_value(&state);
+            let actual = tableau.expectation_value(&observable.terms[0]);
+
+            assert!(
+                (actual - expected).abs() < 1e-12,
+                "{}: {} != {}",
+                word,
+                actual,
+                expected
+            );
+        }
+
+        let reduced = tableau.reduced_density_matrix(&[0, 2]);
+        for (a, b) in reduced
+            .matrix
+            .iter()
+            .zip(state.reduced_density_matrix(&[0, 2]).matrix.iter())
+        {
+            assert!((a - b).norm() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_measurement() {
+        let mut rng = StdRng::seed_from_u64(5);
+
+        // Both qubits of a Bell state always give the same outcome
+        for _ in 0..10 {
+            let tableau = Tableau::new(2)
+                .apply_gate_to_qubits(&QuantumGate::h_gate(), &[0])
+                .apply_gate_to_qubits(&QuantumGate::cnot_gate(), &[0, 1]);
+
+            let (tableau, first) = tableau.measure(0, &mut rng);
+            let (_, second) = tableau.measure(1, &mut rng);
+            assert_eq!(first, second);
+        }
+
+        // A deterministic outcome
+        let (_, outcome) = Tableau::new(2)
+            .apply_gate_to_qubits(&QuantumGate::x_gate(), &[1])
+            .measure(1, &mut rng);
+        assert_eq!(outcome, 1);
+    }
+
+    #[test]
+    fn test_classically_controlled_identity() {
+        let grid = vec![vec!["H", "M", "I"], vec!["I", "I", "I?c0"]];
+        let options = SimulationOptions {
+            simulation_mode: SimulationMode::Stabilizer,
+            ..Default::default()
+        };
+
+        // Both outcomes of the measurement occur, the identity is applied after a 1
+        for seed in 0..10 {
+            let state_list = simulate_circuit_with_rng(
+                grid.clone(),
+                6,
+                &GateRegistry::default(),
+                &options,
+                &mut StdRng::seed_from_u64(seed),
+            )
+            .unwrap();
+
+            assert_eq!(state_list.len(), 4);
+        }
+    }
+
+    #[test]
+    fn test_hundreds_of_qubits() {
+        let n = 300;
+        let mut tableau = Tableau::new(n).apply_gate_to_qubits(&QuantumGate::h_gate(), &[0]);
+        for qubit in 1..n {
+            tableau = tableau.apply_gate_to_qubits(&QuantumGate::cnot_gate(), &[qubit - 1, qubit]);
+        }
+
+        let (mut tableau, first) = tableau.measure(0, &mut StdRng::seed_from_u64(1));
+        for qubit in 1..n {
+            let (collapsed, outcome) = tableau.measure(qubit, &mut StdRng::seed_from_u64(2));
+            assert_eq!(outcome, first);
+            tableau = collapsed;
+        }
+    }
+
+    #[test]
+    fn test_is_clifford_circuit() {
+        let is_clifford = |grid: &[Vec<&str>]| {
+            is_clifford_circuit(&parse_circuit(grid, &GateRegistry::default()).unwrap())
+        };
+
+        assert!(is_clifford(&[
+            vec!["H", "CNOT-1", "M", "I"],
+            vec!["S", "CNOT-2", "SWAP-1", "X?c0"],
+            vec!["Y", "CZ-1", "SWAP-2", "Z"],
+            vec!["I", "CZ-2", "I", "I"],
+        ]));
+        assert!(!is_clifford(&[vec!["T"]]));
+        assert!(!is_clifford(&[vec!["RZ(pi/2)"]]));
+        assert!(!is_clifford(&[vec!["C"], vec!["X"]]));
+        assert!(!is_clifford(&[
+            vec!["CCNOT-1"],
+            vec!["CCNOT-2"],
+            vec!["CCNOT-3"]
+        ]));
+    }
+}
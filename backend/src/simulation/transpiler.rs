@@ -0,0 +1,630 @@
+// Rewrite a circuit with only the gates of a native gate set, like the compiler for a real device does
+// The basis needs CNOT or CZ to entangle and a way to build every single-qubit gate:
+// U3 on its own, RZ with SX, or two of RX, RY and RZ
+// Gates of the basis are kept, the others are decomposed:
+// - Single-qubit gates are multiplied out and written as Euler rotations, up to global phase
+// - SWAP is three CNOTs and CZ is a CNOT between H gates (and the other way around for a CZ basis)
+// - CCNOT is the textbook circuit of six CNOTs with T, T^dagger and H gates
+// - A gate with one control uses U = e^(i alpha) A X B X C with ABC = I, the phase goes on the control
+// - More controls are removed one at a time with controlled square roots (Barenco et al., lemma 7.9),
+//   which triples the number of gates for every control, so at most MAX_CONTROLS are decomposed
+// - Anti-controls are controls between two X gates
+// Custom gates on more than one qubit have no decomposition
+
+use crate::simulation::circuit::{Circuit, Gate, Operation};
+use crate::simulation::circuit_validator::{validate_circuit, QuantumCircuitError};
+use crate::simulation::equivalence::{compare_rewritten, Comparison};
+use crate::simulation::gate_registry::GateRegistry;
+use crate::simulation::linalg::{adjoint, equal_up_to_phase, normalize_angle, u3_angles};
+use crate::simulation::lint::describe;
+use crate::simulation::quantum_gate::QuantumGate;
+use ndarray::Array2;
+use num::Complex;
+use serde::Serialize;
+use std::f64::consts::PI;
+
+const TOLERANCE: f64 = 1e-9;
+
+// Largest number of controls of a decomposed gate, the qubits of a CNOT, CZ or CCNOT other than the target included
+const MAX_CONTROLS: usize = 8;
+
+// How the single-qubit gates are built
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Rotations {
+    U3,
+    RzSx,
+    RzRy,
+    RxRy,
+    RzRx,
+}
+
+#[derive(Debug, Clone)]
+pub struct Basis {
+    gates: Vec<Gate>,
+    rotations: Rotations,
+}
+
+impl Basis {
+    // A basis from the names of built-in gates, e.g. ["CNOT", "RZ", "SX", "X"] or ["CZ", "RX", "RY"]
+    pub fn new(names: &[String]) -> Result<Basis, QuantumCircuitError> {
+        let mut gates = Vec::new();
+        for name in names {
+            match Gate::from_name(name) {
+                Some(gate) => gates.push(gate),
+                None => return Err(unsupported(format!("\"{}\" is not a built-in gate", name))),
+            }
+        }
+
+        let has = |gate: Gate| gates.contains(&gate);
+        if !has(Gate::Cnot) && !has(Gate::Cz) {
+            return Err(unsupported("the basis needs CNOT or CZ".to_string()));
+        }
+        let rotations = if has(Gate::U3) {
+            Rotations::U3
+        } else if has(Gate::RZ) && has(Gate::SX) {
+            Rotations::RzSx
+        } else if has(Gate::RZ) && has(Gate::RY) {
+            Rotations::RzRy
+        } else if has(Gate::RX) && has(Gate::RY) {
+            Rotations::RxRy
+        } else if has(Gate::RZ) && has(Gate::RX) {
+            Rotations::RzRx
+        } else {
+            return Err(unsupported(
+                "the basis needs U3, RZ and SX, or two of RX, RY and RZ".to_string(),
+            ));
+        };
+
+        Ok(Basis { gates, rotations })
+    }
+
+    fn contains(&self, gate: &Gate) -> bool {
+        self.gates.contains(gate)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Transpilation {
+    pub circuit_matrix: Vec<Vec<String>>,
+    pub gates: usize,
+    pub depth: usize,
+    // The transpiled circuit compared with the original, only for circuits that have a unitary
+    // and at most max_unitary_qubits rows
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub equivalence: Option<Comparison>,
+}
+
+// Validate and transpile the grid and check the result against the original if it is small enough
+pub fn transpile_grid(
+    grid: &[Vec<&str>],
+    basis: &Basis,
+    max_qubits: usize,
+    max_unitary_qubits: usize,
+    gates: &GateRegistry,
+) -> Result<Transpilation, QuantumCircuitError> {
+    let circuit = validate_circuit(grid, max_qubits, gates)?;
+    let transpiled = transpile_circuit(&circuit, basis)?;
+    let circuit_matrix = transpiled.to_grid();
+    let equivalence = compare_rewritten(grid, &circuit_matrix, max_unitary_qubits, gates)?;
+
+    Ok(Transpilation {
+        gates: transpiled.operations().count(),
+        depth: transpiled.depth(),
+        circuit_matrix,
+        equivalence,
+    })
+}
+
+// The gates are packed into as few columns as possible, see Circuit::from_operations
+pub fn transpile_circuit(circuit: &Circuit, basis: &Basis) -> Result<Circuit, QuantumCircuitError> {
+    let mut transpiler = Transpiler {
+        basis,
+        condition: None,
+        operations: Vec::new(),
+    };
+    for operation in circuit.operations() {
+        transpiler.operation(operation)?;
+    }
+
+    Ok(Circuit::from_operations(
+        circuit.qubits,
+        transpiler.operations,
+    ))
+}
+
+fn unsupported(reason: String) -> QuantumCircuitError {
+    QuantumCircuitError::UnsupportedBasis(reason)
+}
+
+// Collects the native operations, every one gets the classical condition of the operation it comes from
+// Only single-qubit gates without controls can have a condition, so the condition stays on single-qubit gates
+struct Transpiler<'a> {
+    basis: &'a Basis,
+    condition: Option<usize>,
+    operations: Vec<Operation>,
+}
+
+impl Transpiler<'_> {
+    fn operation(&mut self, operation: &Operation) -> Result<(), QuantumCircuitError> {
+        self.condition = operation.condition;
+
+        if operation.is_measurement()
+            || (operation.controls.is_empty() && self.basis.contains(&operation.gate))
+        {
+            self.operations.push(operation.clone());
+            return Ok(());
+        }
+        if let Gate::Custom(gate) = &operation.gate {
+            if gate.matrix.size > 1 {
+                return Err(unsupported(format!(
+                    "the custom gate {} has more than one qubit and can not be decomposed",
+                    gate.name
+                )));
+            }
+        }
+        // The gate count grows about threefold with every control, 8 controls give some 18000 gates
+        if operation.qubits().len() - 1 > MAX_CONTROLS {
+            return Err(unsupported(format!(
+                "{} has more than {} controls and can not be decomposed",
+                describe(operation),
+                MAX_CONTROLS
+            )));
+        }
+
+        let x = Gate::X.matrix(&[]).matrix;
+        let anti_controls: Vec<usize> = operation
+            .controls
+            .iter()
+            .filter(|control| !control.state)
+            .map(|control| control.qubit)
+            .collect();
+        let mut controls: Vec<usize> = operation
+            .controls
+            .iter()
+            .map(|control| control.qubit)
+            .collect();
+        let targets = &operation.targets;
+
+        anti_controls
+            .iter()
+            .for_each(|&qubit| self.single(&x, qubit));
+        match &operation.gate {
+            Gate::Cnot => {
+                controls.push(targets[0]);
+                self.controlled(&controls, &x, targets[1]);
+            }
+            Gate::Ccnot => {
+                controls.extend(&targets[..2]);
+                self.controlled(&controls, &x, targets[2]);
+            }
+            Gate::Cz => {
+                controls.push(targets[0]);
+                self.controlled(&controls, &Gate::Z.matrix(&[]).matrix, targets[1]);
+            }
+            // A controlled SWAP is a Toffoli between two CNOTs
+            Gate::Swap => {
+                let (first, second) = (targets[0], targets[1]);
+                self.cnot(second, first);
+                controls.push(first);
+                self.controlled(&controls, &x, second);
+                self.cnot(second, first);
+            }
+            gate => self.controlled(
+                &controls,
+                &gate.matrix(&operation.params).matrix,
+                targets[0],
+            ),
+        }
+        anti_controls
+            .iter()
+            .for_each(|&qubit| self.single(&x, qubit));
+
+        Ok(())
+    }
+
+    fn push(&mut self, gate: Gate, targets: Vec<usize>, params: Vec<f64>) {
+        self.operations.push(Operation {
+            condition: self.condition,
+            ..Operation::new(gate, targets, params)
+        });
+    }
+
+    fn cnot(&mut self, control: usize, target: usize) {
+        match self.basis.contains(&Gate::Cnot) {
+            true => self.push(Gate::Cnot, vec![control, target], Vec::new()),
+            false => {
+                let h = Gate::H.matrix(&[]).matrix;
+                self.single(&h, target);
+                self.push(Gate::Cz, vec![control, target], Vec::new());
+                self.single(&h, target);
+            }
+        }
+    }
+
+    fn cz(&mut self, first: usize, second: usize) {
+        match self.basis.contains(&Gate::Cz) {
+            true => self.push(Gate::Cz, vec![first, second], Vec::new()),
+            false => {
+                let h = Gate::H.matrix(&[]).matrix;
+                self.single(&h, second);
+                self.push(Gate::Cnot, vec![first, second], Vec::new());
+                self.single(&h, second);
+            }
+        }
+    }
+
+    // The 2x2 unitary on the target, applied if all controls are |1>
+    // With controls the phase of the matrix matters, without it does not
+    fn controlled(&mut self, controls: &[usize], matrix: &Array2<Complex<f64>>, target: usize) {
+        let x = Gate::X.matrix(&[]).matrix;
+
+        match controls {
+            [] => self.single(matrix, target),
+            [control] if is_close(matrix, &x) => self.cnot(*control, target),
+            [control] if is_close(matrix, &Gate::Z.matrix(&[]).matrix) => self.cz(*control, target),
+            [control] => self.controlled_single(*control, matrix, target),
+            [first, second] if is_close(matrix, &x) => self.toffoli(*first, *second, target),
+            [rest @ .., last] => {
+                // With V^2 = U, the target gets V V = U only if all controls are |1>
+                let v = sqrt_unitary(matrix);
+                let v_dagger = adjoint(&v);
+                self.controlled(&[*last], &v, target);
+                self.controlled(rest, &x, *last);
+                self.controlled(&[*last], &v_dagger, target);
+                self.controlled(rest, &x, *last);
+                self.controlled(rest, &v, target);
+            }
+        }
+    }
+
+    // U = e^(i alpha) RZ(phi) RY(theta) RZ(lambda) = e^(i alpha) A X B X C (Nielsen and Chuang, corollary 4.2)
+    fn controlled_single(&mut self, control: usize, matrix: &Array2<Complex<f64>>, target: usize) {
+        let (theta, phi, lambda) = u3_angles(matrix);
+        let alpha = phase_between(matrix, &QuantumGate::u3(theta, phi, lambda).matrix)
+            + (phi + lambda) / 2.0;
+        let rz = |angle: f64| QuantumGate::rz(angle).matrix;
+        let ry = |angle: f64| QuantumGate::ry(angle).matrix;
+
+        self.single(&rz((lambda - phi) / 2.0), target);
+        self.cnot(control, target);
+        self.single(&ry(-theta / 2.0).dot(&rz(-(lambda + phi) / 2.0)), target);
+        self.cnot(control, target);
+        self.single(&rz(phi).dot(&ry(theta / 2.0)), target);
+        self.single(&QuantumGate::phase(alpha).matrix, control);
+    }
+
+    // Nielsen and Chuang, figure 4.9
+    fn toffoli(&mut self, first: usize, second: usize, target: usize) {
+        let h = Gate::H.matrix(&[]).matrix;
+        let t = Gate::T.matrix(&[]).matrix;
+        let t_dagger = QuantumGate::phase(-PI / 4.0).matrix;
+
+        self.single(&h, target);
+        self.cnot(second, target);
+        self.single(&t_dagger, target);
+        self.cnot(first, target);
+        self.single(&t, target);
+        self.cnot(second, target);
+        self.single(&t_dagger, target);
+        self.cnot(first, target);
+        self.single(&t, second);
+        self.single(&t, target);
+        self.single(&h, target);
+        self.cnot(first, second);
+        self.single(&t, first);
+        self.single(&t_dagger, second);
+        self.cnot(first, second);
+    }
+
+    // A single-qubit gate up to global phase, a gate of the basis with the same matrix is used as it is
+    fn single(&mut self, matrix: &Array2<Complex<f64>>, qubit: usize) {
+        if equal_up_to_phase(matrix, &Gate::I.matrix(&[]).matrix) {
+            return;
+        }
+        let native = self.basis.gates.iter().find(|gate| {
+            gate.size() == 1
+                && gate.no_of_params() == 0
+                && **gate != Gate::Measure
+                && equal_up_to_phase(matrix, &gate.matrix(&[]).matrix)
+        });
+        if let Some(gate) = native {
+            self.push(gate.clone(), vec![qubit], Vec::new());
+            return;
+        }
+
+        let (theta, phi, lambda) = u3_angles(matrix);
+        match self.basis.rotations {
+            Rotations::U3 => self.push(Gate::U3, vec![qubit], vec![theta, phi, lambda]),
+            Rotations::RzRy => self.euler(Gate::RZ, Gate::RY, [lambda, theta, phi], qubit),
+            // RY(theta) = RZ(pi/2) RX(theta) RZ(-pi/2)
+            Rotations::RzRx => self.euler(
+                Gate::RZ,
+                Gate::RX,
+                [lambda - PI / 2.0, theta, phi + PI / 2.0],
+                qubit,
+            ),
+            // H RZ H = RX and H RY H = RY^dagger, so the ZYZ angles of H U H give the XYX angles of U
+            Rotations::RxRy => {
+                let h = Gate::H.matrix(&[]).matrix;
+                let (theta, phi, lambda) = u3_angles(&h.dot(matrix).dot(&h));
+                self.euler(Gate::RX, Gate::RY, [lambda, -theta, phi], qubit);
+            }
+            // U3(theta, phi, lambda) = RZ(phi + pi) SX RZ(theta + pi) SX RZ(lambda) up to phase
+            Rotations::RzSx if theta.abs() < TOLERANCE => {
+                self.rotation(Gate::RZ, phi + lambda, qubit)
+            }
+            Rotations::RzSx => {
+                self.rotation(Gate::RZ, lambda, qubit);
+                self.push(Gate::SX, vec![qubit], Vec::new());
+                self.rotation(Gate::RZ, theta + PI, qubit);
+                self.push(Gate::SX, vec![qubit], Vec::new());
+                self.rotation(Gate::RZ, phi + PI, qubit);
+            }
+        }
+    }
+
+    // The rotations outer(angles[2]) inner(angles[1]) outer(angles[0]), the first angle is applied first
+    fn euler(&mut self, outer: Gate, inner: Gate, angles: [f64; 3], qubit: usize) {
+        match normalize_angle(angles[1]) == 0.0 {
+            true => self.rotation(outer, angles[0] + angles[2], qubit),
+            false => {
+                self.rotation(outer.clone(), angles[0], qubit);
+                self.rotation(inner, angles[1], qubit);
+                self.rotation(outer, angles[2], qubit);
+            }
+        }
+    }
+
+    // Rotations by a multiple of 2 pi are only a global phase and are left out
+    fn rotation(&mut self, gate: Gate, angle: f64, qubit: usize) {
+        let angle = normalize_angle(angle);
+        if angle != 0.0 {
+            self.push(gate, vec![qubit], vec![angle]);
+        }
+    }
+}
+
+fn is_close(first: &Array2<Complex<f64>>, second: &Array2<Complex<f64>>) -> bool {
+    first
+        .iter()
+        .zip(second.iter())
+        .all(|(a, b)| (a - b).norm() < TOLERANCE)
+}
+
+// The angle phi with first = e^(i phi) second, for matrices that are equal up to phase
+fn phase_between(first: &Array2<Complex<f64>>, second: &Array2<Complex<f64>>) -> f64 {
+    let (index, _) = second
+        .indexed_iter()
+        .max_by(|(_, a), (_, b)| a.norm().total_cmp(&b.norm()))
+        .unwrap();
+
+    (first[index] / second[index]).arg()
+}
+
+// A unitary square root of a 2x2 unitary, (M + sI) / t with s^2 = det(M) and t^2 = tr(M) + 2s
+// The other root of the determinant is used if t would be 0, e.g. for -I
+fn sqrt_unitary(matrix: &Array2<Complex<f64>>) -> Array2<Complex<f64>> {
+    let det = matrix[[0, 0]] * matrix[[1, 1]] - matrix[[0, 1]] * matrix[[1, 0]];
+    let trace = matrix[[0, 0]] + matrix[[1, 1]];
+    let mut s = det.sqrt();
+    if (trace + 2.0 * s).norm() < TOLERANCE {
+        s = -s;
+    }
+    let t = (trace + 2.0 * s).sqrt();
+
+    (matrix + &(Array2::<Complex<f64>>::eye(2) * s)) / t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::equivalence::Equivalence;
+    use crate::simulation::gate_registry::GateDefinition;
+
+    fn basis(names: &[&str]) -> Basis {
+        Basis::new(
+            &names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>(),
+        )
+        .unwrap()
+    }
+
+    // Transpile and check that only gates of the basis are used and the unitary is unchanged
+    fn assert_transpiles(grid: &[Vec<&str>], names: &[&str]) -> Transpilation {
+        let transpilation =
+            transpile_grid(grid, &basis(names), 10, 6, &GateRegistry::default()).unwrap();
+
+        for cell in transpilation.circuit_matrix.iter().flatten() {
+            let name = cell.split(['(', '-', '?']).next().unwrap();
+            assert!(
+                name == "I" || name == "M" || names.contains(&name),
+                "{} is not in {:?}",
+                cell,
+                names
+            );
+        }
+        if let Some(comparison) = &transpilation.equivalence {
+            assert_ne!(
+                comparison.equivalence,
+                Equivalence::Different,
+                "{:?}",
+                names
+            );
+        }
+
+        transpilation
+    }
+
+    const BASES: [&[&str]; 5] = [
+        &["CNOT", "RZ", "SX", "X"],
+        &["CZ", "RX", "RY"],
+        &["CNOT", "U3"],
+        &["CZ", "RZ", "RY"],
+        &["CNOT", "RZ", "RX"],
+    ];
+
+    #[test]
+    fn test_every_gate_in_every_basis() {
+        let grid = vec![
+            vec![
+                "H", "T", "CNOT-1", "SWAP-1", "CCNOT-1", "RX(pi/3)", "C", "O",
+            ],
+            vec![
+                "S",
+                "Y",
+                "CNOT-2",
+                "SWAP-2",
+                "CCNOT-2",
+                "U3(1, 2, 3)",
+                "H",
+                "C",
+            ],
+            vec!["SX", "Z", "CZ-1", "I", "CCNOT-3", "P(0.4)", "O", "C"],
+            vec!["X", "RY(-1)", "CZ-2", "I", "I", "RZ(pi/8)", "I", "Z"],
+        ];
+
+        for names in BASES {
+            let transpilation = assert_transpiles(&grid, names);
+            assert!(transpilation.equivalence.is_some());
+        }
+    }
+
+    #[test]
+    fn test_swap_is_three_cnots() {
+        let grid = vec![vec!["SWAP-1"], vec!["SWAP-2"]];
+
+        let transpilation = assert_transpiles(&grid, &["CNOT", "RZ", "SX", "X"]);
+
+        assert_eq!(
+            transpilation.circuit_matrix,
+            vec![
+                vec!["CNOT-2", "CNOT-1", "CNOT-2"],
+                vec!["CNOT-1", "CNOT-2", "CNOT-1"],
+            ]
+        );
+        assert_eq!(transpilation.gates, 3);
+    }
+
+    #[test]
+    fn test_toffoli() {
+        let grid = vec![vec!["CCNOT-1"], vec!["CCNOT-2"], vec!["CCNOT-3"]];
+
+        let transpilation = assert_transpiles(&grid, &["CNOT", "H", "T", "U3"]);
+
+        let cnots = transpilation
+            .circuit_matrix
+            .iter()
+            .flatten()
+            .filter(|cell| cell.starts_with("CNOT-1"))
+            .count();
+        assert_eq!(cnots, 6);
+    }
+
+    #[test]
+    fn test_native_gates_are_kept() {
+        let grid = vec![vec!["H", "CNOT-1", "RZ(pi/4)"], vec!["X", "CNOT-2", "SX"]];
+
+        let transpilation = assert_transpiles(&grid, &["CNOT", "RZ", "SX", "X", "H"]);
+
+        assert_eq!(
+            transpilation.circuit_matrix,
+            vec![vec!["H", "CNOT-1", "RZ(pi/4)"], vec!["X", "CNOT-2", "SX"]]
+        );
+    }
+
+    #[test]
+    fn test_many_controls() {
+        let grid = vec![vec!["C"], vec!["O"], vec!["C"], vec!["C"], vec!["RY(pi/5)"]];
+
+        for names in BASES {
+            assert_transpiles(&grid, names);
+        }
+    }
+
+    #[test]
+    fn test_too_many_controls() {
+        let mut grid = vec![vec!["C"]; MAX_CONTROLS];
+        grid.push(vec!["X"]);
+        let circuit = validate_circuit(&grid, 20, &GateRegistry::default()).unwrap();
+        assert!(transpile_circuit(&circuit, &basis(&["CNOT", "U3"])).is_ok());
+
+        grid.insert(0, vec!["O"]);
+        assert!(matches!(
+            transpile_grid(
+                &grid,
+                &basis(&["CNOT", "U3"]),
+                20,
+                6,
+                &GateRegistry::default()
+            ),
+            Err(QuantumCircuitError::UnsupportedBasis(_))
+        ));
+    }
+
+    #[test]
+    fn test_measurements_and_conditions() {
+        let grid = vec![vec!["H", "M", "I"], vec!["I", "I", "H?c0"]];
+
+        let transpilation = assert_transpiles(&grid, &["CZ", "RX", "RY"]);
+
+        assert_eq!(transpilation.equivalence, None);
+        let measurement = transpilation.circuit_matrix[0]
+            .iter()
+            .rposition(|cell| cell != "I");
+        assert_eq!(transpilation.circuit_matrix[0][measurement.unwrap()], "M");
+        assert!(transpilation.circuit_matrix[1]
+            .iter()
+            .filter(|cell| *cell != "I")
+            .all(|cell| cell.ends_with("?c0")));
+    }
+
+    #[test]
+    fn test_invalid_basis() {
+        for names in [&["CNOT", "RZ"][..], &["RZ", "SX"], &["CNOT", "FOO"], &[]] {
+            let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+            assert!(matches!(
+                Basis::new(&names),
+                Err(QuantumCircuitError::UnsupportedBasis(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_custom_gates() {
+        let mut gates = GateRegistry::default();
+        let definition = |name: &str, circuit: Vec<Vec<&str>>| GateDefinition {
+            name: name.to_string(),
+            matrix: None,
+            circuit: Some(
+                circuit
+                    .iter()
+                    .map(|row| row.iter().map(|cell| cell.to_string()).collect())
+                    .collect(),
+            ),
+        };
+        gates
+            .define(&definition("HS", vec![vec!["H", "S"]]), 4)
+            .unwrap();
+        gates
+            .define(
+                &definition("BELL", vec![vec!["H", "CNOT-1"], vec!["I", "CNOT-2"]]),
+                4,
+            )
+            .unwrap();
+        let basis = basis(&["CNOT", "U3"]);
+
+        let transpilation =
+            transpile_grid(&[vec!["HS"], vec!["C"]], &basis, 10, 6, &gates).unwrap();
+        assert_ne!(
+            transpilation.equivalence.unwrap().equivalence,
+            Equivalence::Different
+        );
+
+        assert!(matches!(
+            transpile_grid(&[vec!["BELL-1"], vec!["BELL-2"]], &basis, 10, 6, &gates),
+            Err(QuantumCircuitError::UnsupportedBasis(_))
+        ));
+    }
+}
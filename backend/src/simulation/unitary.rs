@@ -0,0 +1,154 @@
+use crate::simulation::circuit::Circuit;
+use crate::simulation::circuit_parser::expand_circuit;
+use crate::simulation::circuit_validator::{validate_circuit, QuantumCircuitError};
+use crate::simulation::gate_registry::GateRegistry;
+use crate::simulation::quantum_gate::QuantumGate;
+use ndarray::Array2;
+use num::Complex;
+
+// Calculate the unitary of the circuit after every step, the product of the gates of all columns so far
+// The first unitary is the identity before any column, so there is one more unitary than there are columns
+// Every unitary has 4^n entries, so use circuit_unitary if only the last one is needed
+pub fn circuit_unitaries(
+    incoming_data: Vec<Vec<&str>>,
+    max_qubits: usize,
+    gates: &GateRegistry,
+) -> Result<Vec<QuantumGate>, QuantumCircuitError> {
+    let circuit = unitary_circuit(incoming_data, max_qubits, gates)?;
+
+    // A later column is applied after the earlier ones, so it multiplies from the left
+    let unitaries =
+        expand_circuit(&circuit).fold(vec![identity(circuit.qubits)], |mut unitaries, gate| {
+            let matrix = gate.matrix.dot(&unitaries.last().unwrap().matrix);
+            unitaries.push(QuantumGate {
+                matrix,
+                size: circuit.qubits,
+            });
+            unitaries
+        });
+
+    Ok(unitaries)
+}
+
+// Calculate the unitary of the whole circuit, keeping only the product so far
+pub fn circuit_unitary(
+    incoming_data: Vec<Vec<&str>>,
+    max_qubits: usize,
+    gates: &GateRegistry,
+) -> Result<QuantumGate, QuantumCircuitError> {
+    let circuit = unitary_circuit(incoming_data, max_qubits, gates)?;
+
+    Ok(
+        expand_circuit(&circuit).fold(identity(circuit.qubits), |unitary, gate| QuantumGate {
+            matrix: gate.matrix.dot(&unitary.matrix),
+            size: circuit.qubits,
+        }),
+    )
+}
+
+// Measurements and classically controlled gates have no unitary, so circuits with them are rejected
+fn unitary_circuit(
+    incoming_data: Vec<Vec<&str>>,
+    max_qubits: usize,
+    gates: &GateRegistry,
+) -> Result<Circuit, QuantumCircuitError> {
+    let circuit = validate_circuit(&incoming_data, max_qubits, gates)?;
+
+    match circuit.has_measurements() || circuit.has_conditions() {
+        true => Err(QuantumCircuitError::NonUnitaryOperation),
+        false => Ok(circuit),
+    }
+}
+
+fn identity(no_of_qubits: usize) -> QuantumGate {
+    QuantumGate {
+        matrix: Array2::<Complex<f64>>::eye(1 << no_of_qubits),
+        size: no_of_qubits,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::quantum_state::QuantumState;
+
+    fn assert_matrix_close(actual: &Array2<Complex<f64>>, expected: &Array2<Complex<f64>>) {
+        for (a, b) in actual.iter().zip(expected.iter()) {
+            assert!((a - b).norm() < 1e-12, "{} != {}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn test_three_cnots_make_a_swap() {
+        let grid = vec![
+            vec!["CNOT-1", "CNOT-2", "CNOT-1"],
+            vec!["CNOT-2", "CNOT-1", "CNOT-2"],
+        ];
+
+        let unitary = circuit_unitary(grid, 6, &GateRegistry::default()).unwrap();
+
+        assert_matrix_close(&unitary.matrix, &QuantumGate::swap_gate().matrix);
+    }
+
+    #[test]
+    fn test_unitary_per_step() {
+        let grid = vec![vec!["H", "X"], vec!["I", "Z"]];
+
+        let unitaries = circuit_unitaries(grid.clone(), 6, &GateRegistry::default()).unwrap();
+
+        assert_eq!(unitaries.len(), 3);
+        assert_matrix_close(&unitaries[0].matrix, &Array2::eye(4));
+        assert_matrix_close(
+            &unitaries[1].matrix,
+            &QuantumGate::h_gate()
+                .kronecker(QuantumGate::i_gate())
+                .matrix,
+        );
+
+        // Without the steps only the product is kept, which is the last unitary
+        let unitary = circuit_unitary(grid, 6, &GateRegistry::default()).unwrap();
+        assert_matrix_close(&unitary.matrix, &unitaries[2].matrix);
+    }
+
+    // The unitary applied to a basis state gives the same state as the simulation
+    #[test]
+    fn test_unitary_matches_state_vector() {
+        let grid = vec![
+            vec!["H", "CNOT-1", "RY(pi/3)"],
+            vec!["T", "I", "C"],
+            vec!["I", "CNOT-2", "I"],
+        ];
+
+        let unitary = circuit_unitary(grid, 6, &GateRegistry::default()).unwrap();
+        let state = QuantumState::new(&[0, 1, 0])
+            .apply_gate_to_qubits(&QuantumGate::h_gate(), &[0])
+            .apply_gate_to_qubits(&QuantumGate::t_gate(), &[1])
+            .apply_gate_to_qubits(&QuantumGate::cnot_gate(), &[0, 2])
+            .apply_gate_to_qubits(
+                &QuantumGate::ry(std::f64::consts::PI / 3.0).controlled(1),
+                &[1, 0],
+            );
+
+        assert_matrix_close(
+            &unitary.matrix.dot(&QuantumState::new(&[0, 1, 0]).col),
+            &state.col,
+        );
+    }
+
+    #[test]
+    fn test_measurement_has_no_unitary() {
+        assert_eq!(
+            circuit_unitary(vec![vec!["H", "M"]], 6, &GateRegistry::default()).unwrap_err(),
+            QuantumCircuitError::NonUnitaryOperation
+        );
+        assert_eq!(
+            circuit_unitary(
+                vec![vec!["M", "I"], vec!["I", "X?c0"]],
+                6,
+                &GateRegistry::default()
+            )
+            .unwrap_err(),
+            QuantumCircuitError::NonUnitaryOperation
+        );
+    }
+}
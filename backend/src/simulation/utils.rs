@@ -1,5 +1,6 @@
+use crate::simulation::density_matrix::DensityMatrix;
 use crate::simulation::quantum_state::QuantumState;
-use crate::ComplexContainer;
+use crate::{BlochVector, ComplexContainer, QubitState};
 use ndarray::Array2;
 use num::Complex;
 
@@ -14,6 +15,62 @@ pub fn format_to_complex_container(state: &QuantumState) -> Vec<ComplexContainer
     container_vec
 }
 
+pub fn format_density_matrix(density_matrix: &DensityMatrix) -> Vec<Vec<ComplexContainer>> {
+    format_matrix(&density_matrix.matrix)
+}
+
+// The reduced density matrix, Bloch vector and purity of every qubit, traced out of the full state
+pub fn format_qubit_states(
+    reduced_density_matrix: impl Fn(usize) -> DensityMatrix,
+    no_of_qubits: usize,
+) -> Vec<QubitState> {
+    (0..no_of_qubits)
+        .map(|qubit| {
+            let reduced = reduced_density_matrix(qubit);
+            let [x, y, z] = reduced.bloch_vector();
+
+            QubitState {
+                density_matrix: format_density_matrix(&reduced),
+                bloch_vector: BlochVector { x, y, z },
+                purity: reduced.purity(),
+            }
+        })
+        .collect()
+}
+
+pub fn format_matrix(matrix: &Array2<Complex<f64>>) -> Vec<Vec<ComplexContainer>> {
+    matrix
+        .rows()
+        .into_iter()
+        .map(|row| {
+            row.iter()
+                .map(|el| ComplexContainer {
+                    re: el.re,
+                    im: el.im,
+                })
+                .collect()
+        })
+        .collect()
+}
+
+pub fn density_matrix_to_little_endian(density_matrix: &DensityMatrix) -> DensityMatrix {
+    DensityMatrix {
+        matrix: matrix_to_little_endian(&density_matrix.matrix),
+    }
+}
+
+// Reorder the rows and columns of a 2^n x 2^n matrix, such as a density matrix or unitary, like to_little_endian
+pub fn matrix_to_little_endian(matrix: &Array2<Complex<f64>>) -> Array2<Complex<f64>> {
+    let n = matrix.nrows().ilog2() as usize;
+    let mut new_matrix = Array2::<Complex<f64>>::zeros(matrix.raw_dim());
+
+    for ((row, column), value) in matrix.indexed_iter() {
+        new_matrix[[reverse_bits(row, n), reverse_bits(column, n)]] = *value;
+    }
+
+    new_matrix
+}
+
 pub fn to_little_endian(state: &QuantumState) -> QuantumState {
     let n = (state.col.len_of(ndarray::Axis(0)) as f64).log2() as usize; // Number of qubits
     let mut new_vec = Array2::<Complex<f64>>::zeros((state.col.len_of(ndarray::Axis(0)), 1));